@@ -1,22 +1,41 @@
 use crate::config;
+use crate::discovery;
+use crate::state;
 use chrono::Utc;
+use drago_proto::v1::{
+    AddAccessToken, AddApiKey, AddDnsRecord, ErrorBody, SyncRequest, SyncResponse,
+};
 use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
 use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::thread;
 use std::time::Duration;
 
-#[derive(Serialize)]
-struct SyncRequest {
-    ip_address: String,
-    time_synced: chrono::NaiveDateTime,
+/// Public addresses of this machine, either family may be missing
+#[derive(Debug, Default)]
+pub struct PublicIp {
+    pub ipv4: Option<String>,
+    pub ipv6: Option<String>,
+}
+
+impl std::fmt::Display for PublicIp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "IPv4 {}, IPv6 {}",
+            self.ipv4.as_deref().unwrap_or("none"),
+            self.ipv6.as_deref().unwrap_or("none")
+        )
+    }
 }
 
-#[derive(Deserialize, Debug)]
-pub struct SyncResponse {
-    pub success: bool,
-    pub updated: bool,
-    pub message: String,
+/// The server's error message and code, or the raw body when it isn't an error response
+fn error_message(text: &str) -> String {
+    match serde_json::from_str::<ErrorBody>(text) {
+        Ok(body) => format!("{} ({})", body.error.message, body.error.code),
+        Err(_) => text.to_string(),
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -55,17 +74,74 @@ struct DeviceCodeRequest {
     scope: Option<String>,
 }
 
-pub fn get_public_ip() -> Result<String, Box<dyn std::error::Error>> {
-    let client = Client::new();
-    let ip = client
-        .get("https://api.ipify.org")
-        .timeout(Duration::from_secs(10))
-        .send()?
-        .text()?;
-    Ok(ip.trim().to_string())
+/// Look up both public addresses from the sources in the config's `ip_discovery`, by default
+/// the Drago server's /ip. A machine without IPv6 (or IPv4) just gets None for it, it's only an
+/// error when neither family is found. Sources that disagree with the result go to `warn`.
+pub fn get_public_ip(warn: &dyn Fn(&str)) -> Result<PublicIp, Box<dyn std::error::Error>> {
+    let discovery = config::load_config()
+        .map(|config| config.ip_discovery)
+        .unwrap_or_default();
+    let api_url = get_api_url();
+
+    let lookup = |local: IpAddr| {
+        let consensus = discovery::discover(&discovery, &api_url, local)?;
+        for disagreement in &consensus.disagreements {
+            warn(disagreement);
+        }
+        Ok::<String, String>(consensus.address.to_string())
+    };
+    let ipv4 = lookup(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    let ipv6 = lookup(IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+
+    match (ipv4, ipv6) {
+        (Err(e4), Err(e6)) => {
+            Err(format!("No public address found (IPv4: {}, IPv6: {})", e4, e6).into())
+        }
+        (ipv4, ipv6) => Ok(PublicIp {
+            ipv4: ipv4.ok(),
+            ipv6: ipv6.ok(),
+        }),
+    }
+}
+
+/// A sync the server took
+pub struct Synced {
+    pub message: String,
+    /// How often the server wants the client to sync, when it said
+    pub interval: Option<Duration>,
 }
 
-pub fn sync_ip_to_api(ip: &str) -> Result<String, Box<dyn std::error::Error>> {
+/// /sync answered with an error status
+#[derive(Debug)]
+pub struct SyncHttpError {
+    /// Retry-After of a 429 or 5xx, how long the server wants us to stay away
+    pub retry_after: Option<Duration>,
+    message: String,
+}
+
+impl std::fmt::Display for SyncHttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SyncHttpError {}
+
+// Either a number of seconds or an HTTP date
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (at.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
+pub fn sync_ip_to_api(ip: &PublicIp) -> Result<Synced, Box<dyn std::error::Error>> {
     let config = config::load_config()?;
 
     // API key is required for sync
@@ -78,7 +154,9 @@ pub fn sync_ip_to_api(ip: &str) -> Result<String, Box<dyn std::error::Error>> {
 
     let time_synced = Utc::now().naive_utc();
     let req_body = SyncRequest {
-        ip_address: ip.to_string(),
+        ip_address: None,
+        ipv4: ip.ipv4.clone(),
+        ipv6: ip.ipv6.clone(),
         time_synced,
     };
 
@@ -91,27 +169,51 @@ pub fn sync_ip_to_api(ip: &str) -> Result<String, Box<dyn std::error::Error>> {
 
     let url = resp.url().to_string();
     let status = resp.status();
+    let retry_after = match status.as_u16() {
+        429 | 500..=599 => retry_after(resp.headers()),
+        _ => None,
+    };
     let text = resp.text()?;
 
     if !status.is_success() {
-        return Err(format!("{} HTTP {}: {}", url, status, text).into());
+        return Err(Box::new(SyncHttpError {
+            retry_after,
+            message: format!("{} HTTP {}: {}", url, status, error_message(&text)),
+        }));
+    }
+
+    // The provider failed, but the server keeps retrying the change on its own
+    if let Ok(body) = serde_json::from_str::<ErrorBody>(&text) {
+        return Ok(Synced {
+            message: format!("Sync queued on the server: {}", body.error.message),
+            interval: None,
+        });
     }
 
     // Try to parse the structured API response
     match serde_json::from_str::<SyncResponse>(&text) {
         Ok(api_resp) => {
             if api_resp.success {
-                Ok(format!(
-                    "Sync succeeded (record updated: {}): {}",
-                    api_resp.updated, api_resp.message
-                ))
+                if let Err(e) = state::save_sync(&api_key, ip, &api_resp.message) {
+                    eprintln!("Failed to save the sync state: {}", e);
+                }
+                Ok(Synced {
+                    message: format!(
+                        "Sync succeeded (record updated: {}): {}",
+                        api_resp.updated, api_resp.message
+                    ),
+                    interval: api_resp.sync_interval_secs.map(Duration::from_secs),
+                })
             } else {
                 Err(format!("API error: {}", api_resp.message).into())
             }
         }
         Err(_) => {
             // fallback if unexpected body
-            Ok(format!("Sync completed but unexpected response: {}", text))
+            Ok(Synced {
+                message: format!("Sync completed but unexpected response: {}", text),
+                interval: None,
+            })
         }
     }
 }
@@ -296,50 +398,11 @@ fn exchange_session_for_jwt(session_token: &str) -> Result<String, Box<dyn std::
 // CRUD Operations (use JWT token for authentication)
 // ============================================================================
 
-#[derive(Deserialize, Debug)]
-pub struct Zone {
-    pub id: String,
-    pub name: String,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct DnsRecord {
-    pub id: String,
-    pub name: String,
-    #[serde(rename = "type")]
-    pub record_type: String,
-    pub content: String,
-    pub ttl: i32,
-    pub proxied: bool,
-}
+pub use drago_proto::v1::{ApiKey, DnsAccessToken, DnsRecord, UpdateDnsRecord, Zone};
 
 #[derive(Deserialize, Debug)]
 struct ZoneRecordsResponse(Vec<(Zone, Vec<DnsRecord>)>);
 
-#[derive(Deserialize, Debug)]
-pub struct ApiKeyInfo {
-    pub id: String,
-    pub name: String,
-    pub record_name: String,
-}
-
-#[derive(Serialize)]
-struct AddRecordRequest {
-    zone_id: String,
-    zone_name: String,
-    record_type: String,
-    name: String,
-    content: String,
-    ttl: i32,
-    proxied: bool,
-}
-
-#[derive(Serialize)]
-struct AddApiKeyRequest {
-    name: String,
-    scope: String, // record_id
-}
-
 fn get_api_url() -> String {
     option_env!("DRAGO_API_URL")
         .unwrap_or("http://127.0.0.1:8080")
@@ -413,17 +476,22 @@ pub fn add_record(
         .map(|(_, name)| name.clone())
         .ok_or("Zone not found")?;
 
-    // Get current public IP for initial content
-    let ip = get_public_ip().unwrap_or_else(|_| "0.0.0.0".to_string());
+    // Get current public IP for initial content, the daemon adds the AAAA record on its first sync
+    let ip = get_public_ip(&|warning| eprintln!("⚠️  {}", warning))
+        .ok()
+        .and_then(|ip| ip.ipv4)
+        .unwrap_or_else(|| "0.0.0.0".to_string());
 
-    let request = AddRecordRequest {
+    let request = AddDnsRecord {
         zone_id: zone_id.to_string(),
-        zone_name,
+        zone_name: Some(zone_name.clone()),
         record_type: "A".to_string(),
         name: subdomain.to_string(),
         content: ip,
         ttl: ttl,
         proxied: false,
+        drift_policy: None,
+        fallback_content: None,
     };
 
     let resp = client
@@ -435,10 +503,10 @@ pub fn add_record(
 
     if !resp.status().is_success() {
         let text = resp.text()?;
-        return Err(format!("Failed to add record: {}", text).into());
+        return Err(format!("Failed to add record: {}", error_message(&text)).into());
     }
 
-    Ok(format!("{}.{}", subdomain, request.zone_name))
+    Ok(format!("{}.{}", subdomain, zone_name))
 }
 
 /// Delete a DNS record
@@ -458,14 +526,37 @@ pub fn delete_record(record_id: &str, zone_id: &str) -> Result<(), Box<dyn std::
 
     if !resp.status().is_success() {
         let text = resp.text()?;
-        return Err(format!("Failed to delete record: {}", text).into());
+        return Err(format!("Failed to delete record: {}", error_message(&text)).into());
     }
 
     Ok(())
 }
 
+/// Edit a DNS record in place, fields left as None keep their current value.
+/// Returns the record as it is now.
+pub fn update_record(edit: &UpdateDnsRecord) -> Result<DnsRecord, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let api_url = get_api_url();
+    let token = get_jwt_token()?;
+
+    let resp = client
+        .put(format!("{}/record", api_url))
+        .bearer_auth(&token)
+        .json(edit)
+        .timeout(Duration::from_secs(30))
+        .send()?;
+
+    if !resp.status().is_success() {
+        let text = resp.text()?;
+        return Err(format!("Failed to update record: {}", error_message(&text)).into());
+    }
+
+    let record: DnsRecord = resp.json()?;
+    Ok(record)
+}
+
 /// List all API keys for the authenticated user
-pub fn list_api_keys() -> Result<Vec<ApiKeyInfo>, Box<dyn std::error::Error>> {
+pub fn list_api_keys() -> Result<Vec<ApiKey>, Box<dyn std::error::Error>> {
     let client = Client::new();
     let api_url = get_api_url();
     let token = get_jwt_token()?;
@@ -480,7 +571,7 @@ pub fn list_api_keys() -> Result<Vec<ApiKeyInfo>, Box<dyn std::error::Error>> {
         return Err(format!("Failed to list API keys: HTTP {}", resp.status()).into());
     }
 
-    let keys: Vec<ApiKeyInfo> = resp.json()?;
+    let keys: Vec<ApiKey> = resp.json()?;
     Ok(keys)
 }
 
@@ -490,7 +581,8 @@ fn add_api_key(name: &str, record_id: &str) -> Result<String, Box<dyn std::error
     let api_url = get_api_url();
     let token = get_jwt_token()?;
 
-    let request = AddApiKeyRequest {
+    // The scope of a key is the record it controls
+    let request = AddApiKey {
         name: name.to_string(),
         scope: record_id.to_string(),
     };
@@ -504,7 +596,7 @@ fn add_api_key(name: &str, record_id: &str) -> Result<String, Box<dyn std::error
 
     if !resp.status().is_success() {
         let text = resp.text()?;
-        return Err(format!("Failed to create API key: {}", text).into());
+        return Err(format!("Failed to create API key: {}", error_message(&text)).into());
     }
 
     // The response is the API key as a string
@@ -512,13 +604,6 @@ fn add_api_key(name: &str, record_id: &str) -> Result<String, Box<dyn std::error
     Ok(api_key)
 }
 
-#[derive(Deserialize, Debug)]
-pub struct DnsAccessToken {
-    pub id: String,
-    pub name: String,
-    pub created_on: chrono::NaiveDateTime,
-}
-
 /// Get existing Cloudflare access tokens from the API
 pub fn get_cloudflare_tokens(
     jwt_token: &str,
@@ -534,7 +619,7 @@ pub fn get_cloudflare_tokens(
 
     if !resp.status().is_success() {
         let text = resp.text()?;
-        return Err(format!("Failed to get Cloudflare tokens: {}", text).into());
+        return Err(format!("Failed to get Cloudflare tokens: {}", error_message(&text)).into());
     }
 
     let tokens: Vec<DnsAccessToken> = resp.json()?;
@@ -550,15 +635,10 @@ pub fn store_cloudflare_token(
     let client = Client::new();
     let api_url = get_api_url();
 
-    #[derive(Serialize)]
-    struct StoreTokenRequest {
-        name: String,
-        token: String,
-    }
-
-    let request = StoreTokenRequest {
+    let request = AddAccessToken {
         name: token_name.to_string(),
         token: cloudflare_token.to_string(),
+        provider: Some("cloudflare".to_string()),
     };
 
     let resp = client
@@ -570,7 +650,7 @@ pub fn store_cloudflare_token(
 
     if !resp.status().is_success() {
         let text = resp.text()?;
-        return Err(format!("Failed to store Cloudflare token: {}", text).into());
+        return Err(format!("Failed to store Cloudflare token: {}", error_message(&text)).into());
     }
 
     Ok(())
@@ -681,6 +761,9 @@ pub fn complete_init_flow() -> Result<(), Box<dyn std::error::Error>> {
             token_type: "Bearer".to_string(),
             expires_at: None,
             api_key: None,
+            ip_discovery: Default::default(),
+            heartbeat_secs: None,
+            schedule: Default::default(),
         };
 
         let data = serde_json::to_string_pretty(&config)?;
@@ -836,3 +919,47 @@ pub fn verify_cloudflare_token(cf_token: &str) -> Result<(), Box<dyn std::error:
         Err(format!("Cloudflare API error ({}): {}", status, error_text).into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+
+    fn headers(retry_after: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(retry_after).unwrap());
+        headers
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        assert_eq!(retry_after(&headers("120")), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(&headers(" 0 ")), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_as_http_date() {
+        let at = Utc::now() + chrono::Duration::seconds(300);
+
+        for date in [
+            at.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+            at.to_rfc2822(),
+        ] {
+            let wait = retry_after(&headers(&date)).unwrap();
+            assert!(
+                wait > Duration::from_secs(290) && wait <= Duration::from_secs(300),
+                "{}: {:?}",
+                date,
+                wait
+            );
+        }
+    }
+
+    #[test]
+    fn retry_after_in_the_past_or_unreadable() {
+        assert_eq!(retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")), None);
+        assert_eq!(retry_after(&headers("-5")), None);
+        assert_eq!(retry_after(&headers("soon")), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+}
@@ -1,7 +1,10 @@
+use crate::discovery::IpDiscovery;
+use crate::schedule::SyncSchedule;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::io::{self, Write};
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
@@ -9,6 +12,30 @@ pub struct Config {
     pub token_type: String,
     pub expires_at: Option<chrono::NaiveDateTime>,
     pub api_key: Option<String>,
+    #[serde(default)]
+    pub ip_discovery: IpDiscovery,
+    /// Sync at least this often even when the address hasn't changed, so the server keeps
+    /// seeing the client as online. Unset uses the interval the server suggests, or 600.
+    #[serde(default)]
+    pub heartbeat_secs: Option<u64>,
+    #[serde(default)]
+    pub schedule: SyncSchedule,
+}
+
+// Below the server's default stale threshold of 3 missed 5 minute syncs
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(600);
+
+impl Config {
+    /// How long an unchanged address goes without a sync. Never longer than the interval the
+    /// server suggests, as that's what it judges the client online by.
+    pub fn heartbeat(&self, suggested: Option<Duration>) -> Duration {
+        let heartbeat = self
+            .heartbeat_secs
+            .map(Duration::from_secs)
+            .or(suggested)
+            .unwrap_or(DEFAULT_HEARTBEAT);
+        suggested.map_or(heartbeat, |suggested| heartbeat.min(suggested))
+    }
 }
 
 fn get_home_dir() -> io::Result<PathBuf> {
@@ -51,7 +78,14 @@ pub fn store_device_token() -> io::Result<()> {
     }
 
     // Load existing config or create new one (must do this BEFORE writing new config)
-    let api_key = load_config().ok().and_then(|c| c.api_key);
+    let existing = load_config().ok();
+    let api_key = existing.as_ref().and_then(|c| c.api_key.clone());
+    let heartbeat_secs = existing.as_ref().and_then(|c| c.heartbeat_secs);
+    let schedule = existing
+        .as_ref()
+        .map(|c| c.schedule.clone())
+        .unwrap_or_default();
+    let ip_discovery = existing.map(|c| c.ip_discovery).unwrap_or_default();
 
     // Write config JSON with access token first so we can use it for API calls
     let config = Config {
@@ -59,6 +93,9 @@ pub fn store_device_token() -> io::Result<()> {
         token_type: "Bearer".to_string(),
         expires_at: None, // Could implement token refresh in future
         api_key,
+        ip_discovery,
+        heartbeat_secs,
+        schedule,
     };
 
     let data = serde_json::to_string_pretty(&config)?;
@@ -1,15 +1,23 @@
 use crate::api;
+use crate::config;
+#[cfg(target_os = "linux")]
+use crate::netlink;
+use crate::state;
 use daemonize::Daemonize;
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::process;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 const PID_FILE: &str = "/tmp/drago.pid";
 const LOG_OUT: &str = "/tmp/drago.out";
 const LOG_ERR: &str = "/tmp/drago.err";
-const SYNC_INTERVAL: u64 = 300; // 5 minutes
+// Network changes come in bursts (DHCP renewals, PPPoE reconnects), sync once they've been
+// quiet this long, but no later than DEBOUNCE_MAX after the first one
+const DEBOUNCE: Duration = Duration::from_secs(3);
+const DEBOUNCE_MAX: Duration = Duration::from_secs(30);
 
 pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     // Create log files if they don't exist
@@ -26,17 +34,45 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     match daemonize.start() {
         Ok(_) => {
             log_message("Daemon started successfully");
+            // Started after daemonizing, threads don't survive the fork
+            let changes = watch_network();
+            let mut failures = 0;
+            // The last interval the server asked for
+            let mut suggested = None;
             loop {
-                match sync_ip() {
-                    Ok(()) => {
-                        // If successful, just continue
+                // Read every time so edits apply without a restart
+                let schedule = config::load_config()
+                    .map(|config| config.schedule)
+                    .unwrap_or_default();
+
+                let wait = match sync_ip(suggested) {
+                    Ok(interval) => {
+                        failures = 0;
+                        suggested = interval.or(suggested);
+                        schedule.interval(suggested)
                     }
                     Err(e) => {
-                        log_error(&format!("Sync failed: {}", e));
+                        failures += 1;
+                        let retry_after = e
+                            .downcast_ref::<api::SyncHttpError>()
+                            .and_then(|e| e.retry_after);
+                        let wait = schedule.backoff(failures, retry_after);
+                        log_error(&format!(
+                            "{} failed syncs in a row, retrying in {}s",
+                            failures,
+                            wait.as_secs()
+                        ));
+                        // The server asked us to stay away, a network change doesn't change that
+                        if retry_after.is_some() {
+                            thread::sleep(wait);
+                            while changes.try_recv().is_ok() {}
+                            continue;
+                        }
+                        wait
                     }
-                }
+                };
 
-                thread::sleep(Duration::from_secs(SYNC_INTERVAL));
+                wait_for_next_sync(&changes, wait);
             }
         }
         Err(e) => {
@@ -46,33 +82,90 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
     }
 }
 
-fn sync_ip() -> Result<(), Box<dyn std::error::Error>> {
+/// Reports changes to the host's addresses and default route. On other systems, or when netlink
+/// can't be used, the channel is closed and the daemon only syncs on its schedule.
+fn watch_network() -> Receiver<()> {
+    let (tx, rx) = mpsc::channel();
+
+    #[cfg(target_os = "linux")]
+    match netlink::Watcher::open() {
+        Ok(watcher) => {
+            log_message("Watching for network changes");
+            thread::spawn(move || {
+                if let Err(e) = watcher.run(tx) {
+                    log_error(&format!("Network watcher stopped: {}", e));
+                }
+            });
+        }
+        Err(e) => log_error(&format!("Failed to watch for network changes: {}", e)),
+    }
+    #[cfg(not(target_os = "linux"))]
+    drop(tx);
+
+    rx
+}
+
+/// Blocks until `wait` has passed or the network changed and settled
+fn wait_for_next_sync(changes: &Receiver<()>, wait: Duration) {
+    match changes.recv_timeout(wait) {
+        Ok(()) => {}
+        Err(RecvTimeoutError::Timeout) => return,
+        Err(RecvTimeoutError::Disconnected) => {
+            thread::sleep(wait);
+            return;
+        }
+    }
+
+    let first = Instant::now();
+    while first.elapsed() < DEBOUNCE_MAX {
+        if changes.recv_timeout(DEBOUNCE).is_err() {
+            break;
+        }
+    }
+    log_message("Network change detected");
+}
+
+/// Returns the interval the server suggested, if it was asked and said. `suggested` is the
+/// one it asked for last time.
+fn sync_ip(suggested: Option<Duration>) -> Result<Option<Duration>, Box<dyn std::error::Error>> {
     log_message("Starting IP sync");
 
     // Get public IP (usually the router IP)
-    let ip = match api::get_public_ip() {
+    let ip = match api::get_public_ip(&log_error) {
         Ok(ip) => {
             log_message(&format!("Public IP: {}", ip));
             ip
         }
         Err(e) => {
+            // The server falls back to the address the sync request comes from
             log_error(&format!("Failed to get public IP: {}", e));
-            return Err(e);
+            api::PublicIp::default()
         }
     };
 
+    // The server already has these addresses, only check in once the heartbeat is due
+    let config = config::load_config()?;
+    if let Some(api_key) = config.api_key.as_deref()
+        && let Some(last) = state::recent_sync(api_key, &ip, config.heartbeat(suggested))
+    {
+        log_message(&format!(
+            "Address unchanged since the sync at {} UTC, skipping",
+            last.synced_on.format("%Y-%m-%d %H:%M:%S")
+        ));
+        return Ok(None);
+    }
+
     // Sync to Drago API
     match api::sync_ip_to_api(&ip) {
-        Ok(resp_msg) => {
-            log_message(&format!("Sync completed successfully: {}", resp_msg));
+        Ok(synced) => {
+            log_message(&format!("Sync completed successfully: {}", synced.message));
+            Ok(synced.interval)
         }
         Err(e) => {
             log_error(&format!("Sync failed: {}", e));
-            return Err(e);
+            Err(e)
         }
     }
-
-    Ok(())
 }
 
 fn log_message(message: &str) {
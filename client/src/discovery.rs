@@ -0,0 +1,757 @@
+use crate::gateway;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use std::ffi::CStr;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const STUN_MAGIC_COOKIE: u32 = 0x2112_A442;
+
+/// Where the client looks up its public addresses, the `ip_discovery` section of the config
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IpDiscovery {
+    /// Asked in order until `quorum` of them agree
+    #[serde(default = "default_sources")]
+    pub sources: Vec<IpSource>,
+    #[serde(default = "default_quorum")]
+    pub quorum: usize,
+}
+
+impl Default for IpDiscovery {
+    fn default() -> Self {
+        IpDiscovery {
+            sources: default_sources(),
+            quorum: default_quorum(),
+        }
+    }
+}
+
+fn default_sources() -> Vec<IpSource> {
+    vec![IpSource::Drago]
+}
+
+fn default_quorum() -> usize {
+    1
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IpSource {
+    /// GET /ip on the Drago server
+    Drago,
+    /// Any service answering a GET with the caller's address as plain text
+    Http { url: String },
+    /// A STUN binding request, e.g. to stun.l.google.com:19302
+    Stun { server: String },
+    /// An A/AAAA query for `name`, or TXT with `txt`, sent straight to `resolver`. e.g.
+    /// myip.opendns.com at resolver1.opendns.com, or o-o.myaddr.l.google.com (TXT) at ns1.google.com
+    Dns {
+        resolver: String,
+        name: String,
+        #[serde(default)]
+        txt: bool,
+    },
+    /// A public address on a local interface, for machines that aren't behind NAT
+    Interface { name: Option<String> },
+    /// The home router's WAN address over PCP, NAT-PMP or UPnP IGD, IPv4 only. `address` and
+    /// `ssdp` replace the default gateway and the SSDP multicast group, e.g. for a mock router.
+    Gateway {
+        #[serde(default)]
+        address: Option<String>,
+        #[serde(default)]
+        ssdp: Option<String>,
+    },
+}
+
+impl fmt::Display for IpSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpSource::Drago => write!(f, "drago"),
+            IpSource::Http { url } => write!(f, "http {}", url),
+            IpSource::Stun { server } => write!(f, "stun {}", server),
+            IpSource::Dns { resolver, name, .. } => write!(f, "dns {} at {}", name, resolver),
+            IpSource::Interface { name: Some(name) } => write!(f, "interface {}", name),
+            IpSource::Interface { name: None } => write!(f, "interfaces"),
+            IpSource::Gateway { .. } => write!(f, "gateway"),
+        }
+    }
+}
+
+/// An address enough sources agreed on
+pub struct Consensus {
+    pub address: IpAddr,
+    /// Sources that answered with another address, for the log
+    pub disagreements: Vec<String>,
+}
+
+/// Asks the sources in order for the public address of `local`'s family (an unspecified
+/// address) until `quorum` of them report the same one
+pub fn discover(
+    discovery: &IpDiscovery,
+    api_url: &str,
+    local: IpAddr,
+) -> Result<Consensus, String> {
+    let quorum = discovery.quorum.max(1);
+    if quorum > discovery.sources.len() {
+        return Err(format!(
+            "quorum is {} but only {} sources are configured",
+            quorum,
+            discovery.sources.len()
+        ));
+    }
+
+    let mut votes: Vec<(IpAddr, &IpSource)> = Vec::new();
+    let mut errors = Vec::new();
+
+    for source in &discovery.sources {
+        let address = match lookup(source, api_url, local).and_then(|ip| check(ip, local)) {
+            Ok(address) => address,
+            Err(e) => {
+                errors.push(format!("{}: {}", source, e));
+                continue;
+            }
+        };
+        votes.push((address, source));
+
+        if votes.iter().filter(|(ip, _)| *ip == address).count() >= quorum {
+            let disagreements = votes
+                .iter()
+                .filter(|(ip, _)| *ip != address)
+                .map(|(ip, source)| {
+                    format!("{} reported {}, others agreed on {}", source, ip, address)
+                })
+                .collect();
+            return Ok(Consensus {
+                address,
+                disagreements,
+            });
+        }
+    }
+
+    let reported: Vec<String> = votes
+        .iter()
+        .map(|(ip, source)| format!("{}: {}", source, ip))
+        .collect();
+    Err(format!(
+        "no {} sources agreed (reported: [{}], failed: [{}])",
+        quorum,
+        reported.join(", "),
+        errors.join(", ")
+    ))
+}
+
+fn lookup(source: &IpSource, api_url: &str, local: IpAddr) -> Result<IpAddr, String> {
+    match source {
+        IpSource::Drago => fetch_ip(&format!("{}/ip", api_url), local),
+        IpSource::Http { url } => fetch_ip(url, local),
+        IpSource::Stun { server } => stun(server, local),
+        IpSource::Dns {
+            resolver,
+            name,
+            txt,
+        } => dns(resolver, name, *txt, local),
+        IpSource::Interface { name } => interface(name.as_deref(), local),
+        // Routers hand out IPv6 addresses rather than translating them
+        IpSource::Gateway { .. } if local.is_ipv6() => Err("only reports IPv4".to_string()),
+        IpSource::Gateway { address, ssdp } => {
+            gateway::external_ip(address.as_deref(), ssdp.as_deref())
+        }
+    }
+}
+
+// Anything else can't be what the DNS record should point at
+fn check(ip: IpAddr, local: IpAddr) -> Result<IpAddr, String> {
+    // A proxy in front of a service may still answer with the other family
+    if ip.is_ipv4() != local.is_ipv4() {
+        return Err(format!("{} is of the wrong address family", ip));
+    }
+    if !is_public(&ip) {
+        return Err(format!("{} is not a public address", ip));
+    }
+    Ok(ip)
+}
+
+fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // Carrier-grade NAT, not reachable from outside the ISP
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local fc00::/7 and link-local fe80::/10
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+fn fetch_ip(url: &str, local: IpAddr) -> Result<IpAddr, String> {
+    // Binding the local side to one family forces the connection over that family
+    let client = Client::builder()
+        .local_address(local)
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let body = client
+        .get(url)
+        .send()
+        .and_then(|resp| resp.error_for_status())
+        .and_then(|resp| resp.text())
+        .map_err(|e| e.to_string())?;
+
+    body.trim()
+        .parse()
+        .map_err(|_| format!("answered with {:?}, not an address", truncate(&body)))
+}
+
+fn truncate(text: &str) -> String {
+    text.trim().chars().take(40).collect()
+}
+
+// A UDP socket connected to `server` over `local`'s family
+fn udp_socket(server: &str, default_port: u16, local: IpAddr) -> Result<UdpSocket, String> {
+    let target = resolve(server, default_port, local)?;
+    let socket = UdpSocket::bind(SocketAddr::new(local, 0)).map_err(|e| e.to_string())?;
+    socket
+        .set_read_timeout(Some(TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    socket.connect(target).map_err(|e| e.to_string())?;
+    Ok(socket)
+}
+
+pub fn resolve(server: &str, default_port: u16, local: IpAddr) -> Result<SocketAddr, String> {
+    let candidates: Vec<SocketAddr> = if let Ok(ip) = server.parse::<IpAddr>() {
+        vec![SocketAddr::new(ip, default_port)]
+    } else if let Ok(addr) = server.parse::<SocketAddr>() {
+        vec![addr]
+    } else if server.contains(':') {
+        server
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .collect()
+    } else {
+        (server, default_port)
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .collect()
+    };
+
+    candidates
+        .into_iter()
+        .find(|addr| addr.is_ipv4() == local.is_ipv4())
+        .ok_or_else(|| {
+            format!(
+                "{} has no {} address",
+                server,
+                if local.is_ipv4() { "IPv4" } else { "IPv6" }
+            )
+        })
+}
+
+// From the OS, a guessable STUN transaction or DNS query id lets an off-path host spoof answers
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    getrandom::fill(&mut bytes).expect("no random numbers from the OS");
+    bytes
+}
+
+// RFC 5389 binding request, the server answers with the address it saw us from
+fn stun(server: &str, local: IpAddr) -> Result<IpAddr, String> {
+    let socket = udp_socket(server, 3478, local)?;
+    let transaction: [u8; 12] = random_bytes();
+
+    let mut request = Vec::with_capacity(20);
+    request.extend_from_slice(&0x0001u16.to_be_bytes()); // binding request
+    request.extend_from_slice(&0u16.to_be_bytes()); // no attributes
+    request.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(&transaction);
+
+    let mut buf = [0u8; 512];
+    let mut last_error = String::new();
+    // UDP, so the request or answer may just get lost
+    for _ in 0..3 {
+        socket.send(&request).map_err(|e| e.to_string())?;
+        match socket.recv(&mut buf) {
+            Ok(len) => return parse_stun(&buf[..len], &transaction),
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    Err(last_error)
+}
+
+fn parse_stun(msg: &[u8], transaction: &[u8; 12]) -> Result<IpAddr, String> {
+    if msg.len() < 20
+        || u16::from_be_bytes([msg[0], msg[1]]) != 0x0101
+        || msg[4..8] != STUN_MAGIC_COOKIE.to_be_bytes()
+        || &msg[8..20] != transaction
+    {
+        return Err("not a binding response to our request".to_string());
+    }
+
+    let mut mapped = None;
+    let mut attrs = &msg[20..];
+    while attrs.len() >= 4 {
+        let kind = u16::from_be_bytes([attrs[0], attrs[1]]);
+        let len = u16::from_be_bytes([attrs[2], attrs[3]]) as usize;
+        let Some(value) = attrs.get(4..4 + len) else {
+            break;
+        };
+
+        match kind {
+            // XOR-MAPPED-ADDRESS, what current servers send
+            0x0020 => return stun_address(value, Some(transaction)),
+            // MAPPED-ADDRESS from RFC 3489 servers
+            0x0001 => mapped = Some(stun_address(value, None)),
+            _ => {}
+        }
+
+        // Attributes are padded to 4 bytes
+        let next = 4 + len.div_ceil(4) * 4;
+        attrs = attrs.get(next..).unwrap_or_default();
+    }
+
+    mapped.unwrap_or_else(|| Err("response has no mapped address".to_string()))
+}
+
+fn stun_address(value: &[u8], xor: Option<&[u8; 12]>) -> Result<IpAddr, String> {
+    // Reserved byte, family, port, then the address
+    let mut key = STUN_MAGIC_COOKIE.to_be_bytes().to_vec();
+    if let Some(transaction) = xor {
+        key.extend_from_slice(transaction);
+    }
+    let unmask = |bytes: &[u8]| -> Vec<u8> {
+        bytes
+            .iter()
+            .enumerate()
+            .map(|(i, b)| if xor.is_some() { b ^ key[i] } else { *b })
+            .collect()
+    };
+
+    match (value.get(1), value.get(4..)) {
+        (Some(1), Some(addr)) if addr.len() >= 4 => {
+            let octets: [u8; 4] = unmask(&addr[..4]).try_into().unwrap();
+            Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        (Some(2), Some(addr)) if addr.len() >= 16 => {
+            let octets: [u8; 16] = unmask(&addr[..16]).try_into().unwrap();
+            Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => Err("malformed mapped address".to_string()),
+    }
+}
+
+// A single question straight to the resolver, without the system's resolver or cache in between
+fn dns(resolver: &str, name: &str, txt: bool, local: IpAddr) -> Result<IpAddr, String> {
+    let socket = udp_socket(resolver, 53, local)?;
+    let qtype: u16 = match (txt, local) {
+        (true, _) => 16,
+        (false, IpAddr::V4(_)) => 1,
+        (false, IpAddr::V6(_)) => 28,
+    };
+    let id: [u8; 2] = random_bytes();
+
+    let mut query = Vec::with_capacity(32 + name.len());
+    query.extend_from_slice(&id);
+    query.extend_from_slice(&[0x01, 0x00]); // recursion desired
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // one question
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("{} is not a valid name", name));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes()); // IN
+
+    let mut buf = [0u8; 1232];
+    let mut last_error = String::new();
+    for _ in 0..3 {
+        socket.send(&query).map_err(|e| e.to_string())?;
+        match socket.recv(&mut buf) {
+            Ok(len) => return parse_dns(&buf[..len], &id, qtype),
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    Err(last_error)
+}
+
+fn parse_dns(msg: &[u8], id: &[u8; 2], qtype: u16) -> Result<IpAddr, String> {
+    if msg.len() < 12 || msg[0..2] != id[..] {
+        return Err("not an answer to our query".to_string());
+    }
+    let rcode = msg[3] & 0x0f;
+    if rcode != 0 {
+        return Err(format!("resolver answered with rcode {}", rcode));
+    }
+    let questions = u16::from_be_bytes([msg[4], msg[5]]);
+    let answers = u16::from_be_bytes([msg[6], msg[7]]);
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(msg, pos)? + 4;
+    }
+    for _ in 0..answers {
+        pos = skip_name(msg, pos)?;
+        let header = msg.get(pos..pos + 10).ok_or("truncated answer")?;
+        let kind = u16::from_be_bytes([header[0], header[1]]);
+        let len = u16::from_be_bytes([header[8], header[9]]) as usize;
+        let data = msg
+            .get(pos + 10..pos + 10 + len)
+            .ok_or("truncated answer")?;
+        pos += 10 + len;
+
+        if kind != qtype {
+            // e.g. the CNAME in front of the address
+            continue;
+        }
+        return match kind {
+            1 if len == 4 => Ok(IpAddr::V4(Ipv4Addr::new(
+                data[0], data[1], data[2], data[3],
+            ))),
+            28 if len == 16 => {
+                let octets: [u8; 16] = data.try_into().unwrap();
+                Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+            }
+            // The first character string holds the address
+            16 if len > 0 => {
+                let text = data
+                    .get(1..1 + data[0] as usize)
+                    .ok_or("truncated TXT record")?;
+                let text = String::from_utf8_lossy(text);
+                text.trim()
+                    .parse()
+                    .map_err(|_| format!("TXT record {:?} is not an address", truncate(&text)))
+            }
+            _ => Err("malformed answer".to_string()),
+        };
+    }
+
+    Err("no answer of the asked type".to_string())
+}
+
+// Returns the position after a possibly compressed name
+fn skip_name(msg: &[u8], mut pos: usize) -> Result<usize, String> {
+    loop {
+        let len = *msg.get(pos).ok_or("truncated name")? as usize;
+        match len {
+            0 => return Ok(pos + 1),
+            // A pointer ends the name
+            len if len & 0xc0 == 0xc0 => return Ok(pos + 2),
+            len => pos += 1 + len,
+        }
+    }
+}
+
+fn interface(name: Option<&str>, local: IpAddr) -> Result<IpAddr, String> {
+    let mut found = None;
+
+    unsafe {
+        let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+        if libc::getifaddrs(&mut addrs) != 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+
+        let mut cursor = addrs;
+        while !cursor.is_null() && found.is_none() {
+            let entry = &*cursor;
+            cursor = entry.ifa_next;
+
+            if entry.ifa_addr.is_null() {
+                continue;
+            }
+            let interface = CStr::from_ptr(entry.ifa_name).to_string_lossy();
+            if name.is_some_and(|name| name != interface) {
+                continue;
+            }
+
+            let ip = match (i32::from((*entry.ifa_addr).sa_family), local) {
+                (libc::AF_INET, IpAddr::V4(_)) => {
+                    let addr = &*(entry.ifa_addr as *const libc::sockaddr_in);
+                    IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)))
+                }
+                (libc::AF_INET6, IpAddr::V6(_)) => {
+                    let addr = &*(entry.ifa_addr as *const libc::sockaddr_in6);
+                    IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr))
+                }
+                _ => continue,
+            };
+            if is_public(&ip) {
+                found = Some(ip);
+            }
+        }
+
+        libc::freeifaddrs(addrs);
+    }
+
+    found.ok_or_else(|| "no public address on the interface".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSACTION: [u8; 12] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+
+    fn stun_response(attrs: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (kind, value) in attrs {
+            body.extend_from_slice(&kind.to_be_bytes());
+            body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            body.extend_from_slice(value);
+            body.resize(body.len().div_ceil(4) * 4, 0);
+        }
+
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&0x0101u16.to_be_bytes());
+        msg.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        msg.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+        msg.extend_from_slice(&TRANSACTION);
+        msg.extend(body);
+        msg
+    }
+
+    fn mapped(ip: IpAddr) -> Vec<u8> {
+        let mut value = vec![0, if ip.is_ipv4() { 1 } else { 2 }, 0x30, 0x39];
+        match ip {
+            IpAddr::V4(ip) => value.extend_from_slice(&ip.octets()),
+            IpAddr::V6(ip) => value.extend_from_slice(&ip.octets()),
+        }
+        value
+    }
+
+    fn xor_mapped(ip: IpAddr) -> Vec<u8> {
+        let mut key = STUN_MAGIC_COOKIE.to_be_bytes().to_vec();
+        key.extend_from_slice(&TRANSACTION);
+        let mut value = mapped(ip);
+        for (i, byte) in value[4..].iter_mut().enumerate() {
+            *byte ^= key[i];
+        }
+        value
+    }
+
+    #[test]
+    fn stun_xor_mapped_address() {
+        let v4: IpAddr = "203.0.113.7".parse().unwrap();
+        let msg = stun_response(&[(0x0020, xor_mapped(v4))]);
+        assert_eq!(parse_stun(&msg, &TRANSACTION), Ok(v4));
+
+        let v6: IpAddr = "2001:db8::7".parse().unwrap();
+        let msg = stun_response(&[(0x0020, xor_mapped(v6))]);
+        assert_eq!(parse_stun(&msg, &TRANSACTION), Ok(v6));
+    }
+
+    #[test]
+    fn stun_prefers_xor_mapped_over_mapped() {
+        let old: IpAddr = "198.51.100.1".parse().unwrap();
+        let current: IpAddr = "203.0.113.7".parse().unwrap();
+        // An unknown attribute with an odd length checks the padding is skipped
+        let msg = stun_response(&[
+            (0x0001, mapped(old)),
+            (0x8022, b"abc".to_vec()),
+            (0x0020, xor_mapped(current)),
+        ]);
+        assert_eq!(parse_stun(&msg, &TRANSACTION), Ok(current));
+    }
+
+    #[test]
+    fn stun_mapped_address_from_old_servers() {
+        let ip: IpAddr = "198.51.100.1".parse().unwrap();
+        let msg = stun_response(&[(0x0001, mapped(ip))]);
+        assert_eq!(parse_stun(&msg, &TRANSACTION), Ok(ip));
+    }
+
+    #[test]
+    fn stun_rejects_other_responses() {
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let msg = stun_response(&[(0x0020, xor_mapped(ip))]);
+
+        let mut other = TRANSACTION;
+        other[0] = 0xff;
+        assert!(parse_stun(&msg, &other).is_err());
+        assert!(parse_stun(&msg[..19], &TRANSACTION).is_err());
+
+        let mut error = msg.clone();
+        error[1] = 0x11; // binding error response
+        assert!(parse_stun(&error, &TRANSACTION).is_err());
+
+        assert!(parse_stun(&stun_response(&[]), &TRANSACTION).is_err());
+    }
+
+    #[test]
+    fn stun_truncated_attributes() {
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let msg = stun_response(&[(0x0020, xor_mapped(ip))]);
+        // The attribute's length runs past the end of the message
+        assert!(parse_stun(&msg[..msg.len() - 1], &TRANSACTION).is_err());
+        // Long enough for the header, not for the address
+        let short = stun_response(&[(0x0020, xor_mapped(ip)[..6].to_vec())]);
+        assert!(parse_stun(&short, &TRANSACTION).is_err());
+        let short = stun_response(&[(0x0001, vec![0, 2, 0, 0, 1, 2, 3, 4])]);
+        assert!(parse_stun(&short, &TRANSACTION).is_err());
+    }
+
+    const ID: [u8; 2] = [0xab, 0xcd];
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for label in name.split('.') {
+            encoded.push(label.len() as u8);
+            encoded.extend_from_slice(label.as_bytes());
+        }
+        encoded.push(0);
+        encoded
+    }
+
+    // A response to a question for myip.example.com, answer names given encoded
+    fn dns_response(qtype: u16, answers: &[(Vec<u8>, u16, Vec<u8>)]) -> Vec<u8> {
+        let mut msg = ID.to_vec();
+        msg.extend_from_slice(&[0x81, 0x80, 0, 1]);
+        msg.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        msg.extend_from_slice(&[0, 0, 0, 0]);
+        msg.extend(name("myip.example.com"));
+        msg.extend_from_slice(&qtype.to_be_bytes());
+        msg.extend_from_slice(&1u16.to_be_bytes());
+
+        for (owner, kind, data) in answers {
+            msg.extend_from_slice(owner);
+            msg.extend_from_slice(&kind.to_be_bytes());
+            msg.extend_from_slice(&1u16.to_be_bytes());
+            msg.extend_from_slice(&300u32.to_be_bytes());
+            msg.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            msg.extend_from_slice(data);
+        }
+        msg
+    }
+
+    // Points back at the question's name
+    const QUESTION: [u8; 2] = [0xc0, 12];
+
+    #[test]
+    fn dns_a_and_aaaa() {
+        let msg = dns_response(1, &[(QUESTION.to_vec(), 1, vec![203, 0, 113, 7])]);
+        assert_eq!(parse_dns(&msg, &ID, 1), Ok("203.0.113.7".parse().unwrap()));
+
+        let v6: Ipv6Addr = "2001:db8::7".parse().unwrap();
+        let msg = dns_response(28, &[(QUESTION.to_vec(), 28, v6.octets().to_vec())]);
+        assert_eq!(parse_dns(&msg, &ID, 28), Ok(IpAddr::V6(v6)));
+    }
+
+    #[test]
+    fn dns_cname_then_a() {
+        // The CNAME target is "home" followed by a pointer to example.com in the question
+        let mut target = vec![4];
+        target.extend_from_slice(b"home");
+        target.extend_from_slice(&[0xc0, 12 + 5]);
+        let cname_at = 12 + name("myip.example.com").len() + 4 + 12;
+
+        let msg = dns_response(
+            1,
+            &[
+                (QUESTION.to_vec(), 5, target),
+                (vec![0xc0, cname_at as u8], 1, vec![203, 0, 113, 7]),
+            ],
+        );
+        assert_eq!(parse_dns(&msg, &ID, 1), Ok("203.0.113.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn dns_txt() {
+        let mut txt = vec![11];
+        txt.extend_from_slice(b"203.0.113.7");
+        let msg = dns_response(16, &[(QUESTION.to_vec(), 16, txt)]);
+        assert_eq!(parse_dns(&msg, &ID, 16), Ok("203.0.113.7".parse().unwrap()));
+
+        let mut txt = vec![5];
+        txt.extend_from_slice(b"hello");
+        let msg = dns_response(16, &[(QUESTION.to_vec(), 16, txt)]);
+        assert!(parse_dns(&msg, &ID, 16).is_err());
+
+        // The string's length runs past the record
+        let msg = dns_response(16, &[(QUESTION.to_vec(), 16, vec![20, b'1'])]);
+        assert!(parse_dns(&msg, &ID, 16).is_err());
+    }
+
+    #[test]
+    fn dns_rejects_bad_answers() {
+        let msg = dns_response(1, &[(QUESTION.to_vec(), 1, vec![203, 0, 113, 7])]);
+        assert!(parse_dns(&msg, &[0, 0], 1).is_err());
+        assert!(parse_dns(&msg[..msg.len() - 1], &ID, 1).is_err());
+        assert!(parse_dns(&msg[..11], &ID, 1).is_err());
+
+        let mut nxdomain = msg.clone();
+        nxdomain[3] = 0x83;
+        assert!(parse_dns(&nxdomain, &ID, 1).is_err());
+
+        assert!(parse_dns(&dns_response(1, &[]), &ID, 1).is_err());
+        let wrong_length = dns_response(1, &[(QUESTION.to_vec(), 1, vec![203, 0, 113])]);
+        assert!(parse_dns(&wrong_length, &ID, 1).is_err());
+    }
+
+    #[test]
+    fn skip_names() {
+        let msg = name("myip.example.com");
+        assert_eq!(skip_name(&msg, 0), Ok(msg.len()));
+        // Labels ending in a pointer
+        let msg = [1, b'a', 0xc0, 0];
+        assert_eq!(skip_name(&msg, 0), Ok(4));
+        assert_eq!(skip_name(&[0xc0, 12], 0), Ok(2));
+        // No terminating zero
+        assert!(skip_name(&[4, b'm', b'y', b'i', b'p'], 0).is_err());
+        assert!(skip_name(&[], 0).is_err());
+    }
+
+    #[test]
+    fn public_addresses() {
+        for ip in ["1.1.1.1", "8.8.8.8", "100.128.0.1", "2001:4860::8888"] {
+            assert!(is_public(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "127.0.0.1",
+            "169.254.1.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "192.0.2.1",
+            "203.0.113.7",
+            "224.0.0.1",
+            // Carrier-grade NAT
+            "100.64.0.1",
+            "100.127.255.254",
+            "::1",
+            "::",
+            "ff02::1",
+            // Unique local
+            "fd12:3456::1",
+            "fc00::1",
+            "fe80::1",
+        ] {
+            assert!(!is_public(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn random_bytes_differ() {
+        let first: [u8; 12] = random_bytes();
+        let second: [u8; 12] = random_bytes();
+        assert_ne!(first, second);
+    }
+}
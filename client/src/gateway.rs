@@ -0,0 +1,442 @@
+use crate::discovery;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+// PCP and NAT-PMP share the port, a router speaking only NAT-PMP answers PCP with version 0
+const PCP_PORT: u16 = 5351;
+const SSDP_PORT: u16 = 1900;
+// A router that supports the protocol answers right away, don't hold up the other sources
+const ATTEMPT_TIMEOUTS: [Duration; 2] = [Duration::from_millis(250), Duration::from_secs(1)];
+const SSDP_TIMEOUT: Duration = Duration::from_secs(3);
+// The MAP request is only made for the address in the response and released straight after
+const PCP_LIFETIME: u32 = 60;
+
+/// The router's WAN address, asked over PCP, NAT-PMP and UPnP IGD in that order. `address` is
+/// the router for PCP and NAT-PMP (the default route's gateway when None), `ssdp` where UPnP
+/// discovery is sent (the SSDP multicast group when None).
+pub fn external_ip(address: Option<&str>, ssdp: Option<&str>) -> Result<IpAddr, String> {
+    let any = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+    let gateway = match address {
+        Some(address) => discovery::resolve(address, PCP_PORT, any),
+        None => default_gateway().map(|ip| SocketAddr::new(IpAddr::V4(ip), PCP_PORT)),
+    };
+
+    let mut errors = Vec::new();
+    match gateway {
+        Ok(gateway) => {
+            match pcp(gateway) {
+                Ok(ip) => return Ok(ip),
+                Err(e) => errors.push(format!("PCP: {}", e)),
+            }
+            match nat_pmp(gateway) {
+                Ok(ip) => return Ok(ip),
+                Err(e) => errors.push(format!("NAT-PMP: {}", e)),
+            }
+        }
+        Err(e) => errors.push(format!("PCP/NAT-PMP: {}", e)),
+    }
+    match upnp(ssdp) {
+        Ok(ip) => return Ok(ip),
+        Err(e) => errors.push(format!("UPnP: {}", e)),
+    }
+
+    Err(errors.join(", "))
+}
+
+// The gateway of the IPv4 default route, from the kernel's routing table
+#[cfg(target_os = "linux")]
+fn default_gateway() -> Result<Ipv4Addr, String> {
+    const RTF_GATEWAY: u16 = 0x2;
+
+    let routes = std::fs::read_to_string("/proc/net/route").map_err(|e| e.to_string())?;
+    // Iface Destination Gateway Flags ..., addresses as the hex of the in-memory u32
+    for line in routes.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [_, destination, gateway, flags, ..] = fields[..] else {
+            continue;
+        };
+        let flags = u16::from_str_radix(flags, 16).unwrap_or(0);
+        if destination != "00000000" || flags & RTF_GATEWAY == 0 {
+            continue;
+        }
+        if let Ok(gateway) = u32::from_str_radix(gateway, 16) {
+            return Ok(Ipv4Addr::from(gateway.to_ne_bytes()));
+        }
+    }
+
+    Err("no IPv4 default route".to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_gateway() -> Result<Ipv4Addr, String> {
+    Err("set the gateway's address in the config".to_string())
+}
+
+fn connect(gateway: SocketAddr) -> Result<UdpSocket, String> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).map_err(|e| e.to_string())?;
+    socket.connect(gateway).map_err(|e| e.to_string())?;
+    Ok(socket)
+}
+
+fn exchange(socket: &UdpSocket, request: &[u8], buf: &mut [u8]) -> Result<usize, String> {
+    for timeout in ATTEMPT_TIMEOUTS {
+        socket
+            .set_read_timeout(Some(timeout))
+            .map_err(|e| e.to_string())?;
+        socket.send(request).map_err(|e| e.to_string())?;
+        match socket.recv(buf) {
+            Ok(len) => return Ok(len),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Err("no answer".to_string())
+}
+
+// RFC 6887 MAP request, the response carries the external address the mapping got
+fn pcp(gateway: SocketAddr) -> Result<IpAddr, String> {
+    let socket = connect(gateway)?;
+    let local = socket.local_addr().map_err(|e| e.to_string())?;
+    let client = match local.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    let nonce: [u8; 12] = discovery::random_bytes();
+
+    let mut request = Vec::with_capacity(60);
+    request.extend_from_slice(&[2, 1, 0, 0]); // version 2, MAP
+    request.extend_from_slice(&PCP_LIFETIME.to_be_bytes());
+    request.extend_from_slice(&client.octets());
+    request.extend_from_slice(&nonce);
+    request.extend_from_slice(&[17, 0, 0, 0]); // UDP
+    request.extend_from_slice(&local.port().to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes()); // any external port
+    request.extend_from_slice(&Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets());
+
+    let mut buf = [0u8; 1100];
+    let len = exchange(&socket, &request, &mut buf)?;
+    let response = &buf[..len];
+    if len < 60 || response[0] != 2 || response[1] != 0x81 || response[24..36] != nonce {
+        return Err("not a PCP MAP response".to_string());
+    }
+    if response[3] != 0 {
+        return Err(format!("result code {}", response[3]));
+    }
+    let octets: [u8; 16] = response[44..60].try_into().unwrap();
+    let external = Ipv6Addr::from(octets);
+
+    // Lifetime 0 deletes the mapping
+    request[4..8].copy_from_slice(&0u32.to_be_bytes());
+    let _ = socket.send(&request);
+
+    Ok(external
+        .to_ipv4_mapped()
+        .map(IpAddr::V4)
+        .unwrap_or(IpAddr::V6(external)))
+}
+
+// RFC 6886 external address request
+fn nat_pmp(gateway: SocketAddr) -> Result<IpAddr, String> {
+    let socket = connect(gateway)?;
+
+    let mut buf = [0u8; 16];
+    let len = exchange(&socket, &[0, 0], &mut buf)?;
+    if len < 12 || buf[0] != 0 || buf[1] != 128 {
+        return Err("not a NAT-PMP response".to_string());
+    }
+    let result = u16::from_be_bytes([buf[2], buf[3]]);
+    if result != 0 {
+        return Err(format!("result code {}", result));
+    }
+
+    Ok(IpAddr::V4(Ipv4Addr::new(buf[8], buf[9], buf[10], buf[11])))
+}
+
+// SSDP search for an IGD, then GetExternalIPAddress on its WAN connection service
+fn upnp(ssdp: Option<&str>) -> Result<IpAddr, String> {
+    let mut options = igd_next::SearchOptions {
+        timeout: Some(SSDP_TIMEOUT),
+        single_search_timeout: Some(SSDP_TIMEOUT),
+        ..Default::default()
+    };
+    if let Some(ssdp) = ssdp {
+        options.broadcast_address =
+            discovery::resolve(ssdp, SSDP_PORT, IpAddr::V4(Ipv4Addr::UNSPECIFIED))?;
+    }
+
+    let gateway = igd_next::search_gateway(options).map_err(|e| e.to_string())?;
+    gateway.get_external_ip().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    // A router on a local port answering each request with what `answer` returns
+    fn router(answer: impl Fn(&[u8]) -> Option<Vec<u8>> + Send + 'static) -> SocketAddr {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 1100];
+            while let Ok((len, from)) = socket.recv_from(&mut buf) {
+                if let Some(response) = answer(&buf[..len]) {
+                    let _ = socket.send_to(&response, from);
+                }
+            }
+        });
+        address
+    }
+
+    fn pcp_response(request: &[u8], result: u8, external: Ipv4Addr) -> Vec<u8> {
+        let mut response = vec![2, 0x81, 0, result];
+        response.extend_from_slice(&request[4..8]); // lifetime
+        response.extend_from_slice(&[0; 16]); // epoch, reserved
+        response.extend_from_slice(&request[24..44]); // nonce, protocol, ports
+        response.extend_from_slice(&external.to_ipv6_mapped().octets());
+        response
+    }
+
+    fn nat_pmp_response(result: u16, external: Ipv4Addr) -> Vec<u8> {
+        let mut response = vec![0, 128];
+        response.extend_from_slice(&result.to_be_bytes());
+        response.extend_from_slice(&[0; 4]); // epoch
+        response.extend_from_slice(&external.octets());
+        response
+    }
+
+    const EXTERNAL: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 9);
+
+    #[test]
+    fn pcp_map() {
+        let address = router(|request| match request {
+            [2, 1, ..] if request.len() == 60 => Some(pcp_response(request, 0, EXTERNAL)),
+            _ => None,
+        });
+
+        let ip = external_ip(Some(&address.to_string()), None);
+        assert_eq!(ip, Ok(IpAddr::V4(EXTERNAL)));
+    }
+
+    #[test]
+    fn nat_pmp_when_pcp_is_unsupported() {
+        // A NAT-PMP only router answers the PCP request in its own version with
+        // "unsupported version"
+        let address = router(|request| match request {
+            [2, ..] => Some(vec![0, 0x81, 0, 1, 0, 0, 0, 0]),
+            [0, 0] => Some(nat_pmp_response(0, EXTERNAL)),
+            _ => None,
+        });
+
+        let ip = external_ip(Some(&address.to_string()), None);
+        assert_eq!(ip, Ok(IpAddr::V4(EXTERNAL)));
+    }
+
+    #[test]
+    fn pcp_errors() {
+        let refused = router(|request| Some(pcp_response(request, 2, EXTERNAL)));
+        assert_eq!(pcp(refused), Err("result code 2".to_string()));
+
+        // An answer to someone else's request
+        let other = router(|request| {
+            let mut response = pcp_response(request, 0, EXTERNAL);
+            response[24] ^= 0xff;
+            Some(response)
+        });
+        assert!(pcp(other).is_err());
+
+        let short = router(|request| Some(pcp_response(request, 0, EXTERNAL)[..59].to_vec()));
+        assert!(pcp(short).is_err());
+    }
+
+    #[test]
+    fn nat_pmp_errors() {
+        let refused = router(|_| Some(nat_pmp_response(3, EXTERNAL)));
+        assert_eq!(nat_pmp(refused), Err("result code 3".to_string()));
+
+        let short = router(|_| Some(nat_pmp_response(0, EXTERNAL)[..11].to_vec()));
+        assert!(nat_pmp(short).is_err());
+    }
+
+    #[test]
+    fn retries_a_lost_request() {
+        let seen = AtomicUsize::new(0);
+        let address = router(move |_| {
+            // Drop the first request as if it got lost
+            if seen.fetch_add(1, Ordering::SeqCst) == 0 {
+                return None;
+            }
+            Some(nat_pmp_response(0, EXTERNAL))
+        });
+        assert_eq!(nat_pmp(address), Ok(IpAddr::V4(EXTERNAL)));
+    }
+
+    const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+    <deviceList>
+      <device>
+        <deviceType>urn:schemas-upnp-org:device:WANDevice:1</deviceType>
+        <deviceList>
+          <device>
+            <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
+            <serviceList>
+              <service>
+                <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+                <serviceId>urn:upnp-org:serviceId:WANIPConn1</serviceId>
+                <SCPDURL>/WANIPCn.xml</SCPDURL>
+                <controlURL>/ctl/IPConn</controlURL>
+                <eventSubURL>/evt/IPConn</eventSubURL>
+              </service>
+            </serviceList>
+          </device>
+        </deviceList>
+      </device>
+    </deviceList>
+  </device>
+</root>"#;
+
+    const SCPD: &str = r#"<?xml version="1.0"?>
+<scpd xmlns="urn:schemas-upnp-org:service-1-0">
+  <actionList>
+    <action>
+      <name>GetExternalIPAddress</name>
+      <argumentList>
+        <argument>
+          <name>NewExternalIPAddress</name>
+          <direction>out</direction>
+          <relatedStateVariable>ExternalIPAddress</relatedStateVariable>
+        </argument>
+      </argumentList>
+    </action>
+  </actionList>
+</scpd>"#;
+
+    fn soap(body: &str) -> String {
+        format!(
+            r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+  <s:Body>{}</s:Body>
+</s:Envelope>"#,
+            body
+        )
+    }
+
+    // Answers one HTTP request from the IGD's web server, whatever the path
+    fn serve(stream: std::net::TcpStream, soap_response: &(u16, String)) {
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+
+        let mut content_length = 0;
+        let mut soap_action = String::new();
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(':').unwrap();
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.trim().parse().unwrap(),
+                "soapaction" => soap_action = value.trim().to_string(),
+                _ => {}
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+        let (status, response) = match path {
+            "/rootDesc.xml" => (200, DESCRIPTION.to_string()),
+            "/WANIPCn.xml" => (200, SCPD.to_string()),
+            "/ctl/IPConn"
+                if soap_action.contains("WANIPConnection:1#GetExternalIPAddress")
+                    && String::from_utf8_lossy(&body).contains("GetExternalIPAddress") =>
+            {
+                soap_response.clone()
+            }
+            _ => (404, String::new()),
+        };
+
+        let mut stream = reader.into_inner();
+        let _ = write!(
+            stream,
+            "HTTP/1.1 {} X\r\nContent-Type: text/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            response.len(),
+            response
+        );
+    }
+
+    // An IGD: SSDP on a local UDP port pointing at its web server on a local TCP port, which
+    // serves the device description, the WANIPConnection SCPD and the control endpoint
+    fn igd(soap_response: (u16, String)) -> SocketAddr {
+        let http = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let location = format!("http://{}/rootDesc.xml", http.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in http.incoming() {
+                match stream {
+                    Ok(stream) => serve(stream, &soap_response),
+                    Err(_) => break,
+                }
+            }
+        });
+
+        router(move |request| {
+            let request = String::from_utf8_lossy(request);
+            (request.starts_with("M-SEARCH") && request.contains("InternetGatewayDevice")).then(
+                || {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\nUSN: uuid:mock::urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\nLOCATION: {}\r\n\r\n",
+                        location
+                    )
+                    .into_bytes()
+                },
+            )
+        })
+    }
+
+    #[test]
+    fn upnp_external_ip() {
+        let ssdp = igd((
+            200,
+            soap(&format!(
+                r#"<u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewExternalIPAddress>{}</NewExternalIPAddress>
+</u:GetExternalIPAddressResponse>"#,
+                EXTERNAL
+            )),
+        ));
+
+        assert_eq!(upnp(Some(&ssdp.to_string())), Ok(IpAddr::V4(EXTERNAL)));
+    }
+
+    #[test]
+    fn upnp_error() {
+        let ssdp = igd((
+            500,
+            soap(
+                r#"<s:Fault>
+<faultcode>s:Client</faultcode>
+<faultstring>UPnPError</faultstring>
+<detail>
+<UPnPError xmlns="urn:schemas-upnp-org:control-1-0">
+<errorCode>501</errorCode>
+<errorDescription>Action Failed</errorDescription>
+</UPnPError>
+</detail>
+</s:Fault>"#,
+            ),
+        ));
+
+        let error = upnp(Some(&ssdp.to_string())).unwrap_err();
+        assert!(error.contains("501"), "{}", error);
+    }
+}
@@ -4,7 +4,13 @@ use std::process;
 mod api;
 mod config;
 mod daemon;
+mod discovery;
+mod gateway;
+#[cfg(target_os = "linux")]
+mod netlink;
 mod processes;
+mod schedule;
+mod state;
 
 #[derive(Parser)]
 #[command(name = "drago")]
@@ -31,8 +37,11 @@ enum Commands {
     Daemon,
     /// List available DNS zones
     Zones,
-    /// List DNS records
-    Records,
+    /// List DNS records, or change one with `records edit`
+    Records {
+        #[command(subcommand)]
+        action: Option<RecordsAction>,
+    },
     #[command(hide = true)]
     /// Create a DNS record and get an API key for syncing
     Add {
@@ -69,6 +78,31 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum RecordsAction {
+    /// Change a record without deleting it, its API key keeps working
+    Edit {
+        /// Record ID to edit, found with drago records
+        #[arg(short, long)]
+        record: String,
+        /// New subdomain name (e.g., "home" for home.example.com)
+        #[arg(short, long)]
+        name: Option<String>,
+        /// New record type (A, AAAA, CNAME, ...)
+        #[arg(long = "type")]
+        record_type: Option<String>,
+        /// New content, needed when it doesn't fit the new type
+        #[arg(short, long)]
+        content: Option<String>,
+        /// New TTL in seconds
+        #[arg(short, long)]
+        ttl: Option<i32>,
+        /// Turn Cloudflare proxying on or off
+        #[arg(short, long)]
+        proxied: Option<bool>,
+    },
+}
+
 fn main() {
     let cli = Cli::parse();
 
@@ -130,7 +164,43 @@ fn main() {
                 process::exit(1);
             }
         },
-        Commands::Records => match api::list_records() {
+        Commands::Records {
+            action:
+                Some(RecordsAction::Edit {
+                    record,
+                    name,
+                    record_type,
+                    content,
+                    ttl,
+                    proxied,
+                }),
+        } => {
+            let edit = api::UpdateDnsRecord {
+                record_id: record,
+                name,
+                record_type,
+                content,
+                ttl,
+                proxied,
+                drift_policy: None,
+            };
+
+            match api::update_record(&edit) {
+                Ok(record) => println!(
+                    "✅ DNS record updated: {} ({}) -> {}, ttl {}{}",
+                    record.name,
+                    record.record_type,
+                    record.content,
+                    record.ttl,
+                    if record.proxied { ", proxied" } else { "" }
+                ),
+                Err(e) => {
+                    eprintln!("❌ Failed to update record: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        Commands::Records { action: None } => match api::list_records() {
             Ok(records) => {
                 if records.is_empty() {
                     println!("No DNS records found.");
@@ -138,8 +208,8 @@ fn main() {
                     println!("📋 DNS Records:\n");
                     for record in records {
                         println!(
-                            "  {} ({}) -> {}",
-                            record.name, record.record_type, record.content
+                            "  {} - {} ({}) -> {}",
+                            record.id, record.name, record.record_type, record.content
                         );
                     }
                 }
@@ -0,0 +1,240 @@
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::mpsc::Sender;
+
+// Big enough for the bursts the kernel sends when an interface comes up
+const BUFFER_SIZE: usize = 16 * 1024;
+const HEADER_LEN: usize = mem::size_of::<libc::nlmsghdr>();
+
+/// Listens to the kernel's address and route notifications over an rtnetlink socket
+pub struct Watcher {
+    socket: OwnedFd,
+}
+
+impl Watcher {
+    pub fn open() -> io::Result<Watcher> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = (libc::RTMGRP_IPV4_IFADDR
+            | libc::RTMGRP_IPV6_IFADDR
+            | libc::RTMGRP_IPV4_ROUTE
+            | libc::RTMGRP_IPV6_ROUTE) as u32;
+
+        let bound = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if bound < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Watcher { socket })
+    }
+
+    /// Sends on `changes` whenever a public address or a default route is added or removed.
+    /// Runs until the receiving end is dropped or the socket fails.
+    pub fn run(self, changes: Sender<()>) -> io::Result<()> {
+        let mut buf = vec![0u8; BUFFER_SIZE];
+
+        loop {
+            let read = unsafe {
+                libc::recv(
+                    self.socket.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+
+            let relevant = if read < 0 {
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    // The kernel dropped notifications we didn't read in time, any of them may have mattered
+                    Some(libc::ENOBUFS) => true,
+                    _ => return Err(err),
+                }
+            } else {
+                is_relevant(&buf[..read as usize])
+            };
+
+            if relevant && changes.send(()).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Whether any message in a datagram changes the addresses the world can reach us on
+fn is_relevant(mut data: &[u8]) -> bool {
+    while data.len() >= HEADER_LEN {
+        let len = u32::from_ne_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        let kind = u16::from_ne_bytes([data[4], data[5]]);
+        if len < HEADER_LEN || len > data.len() {
+            break;
+        }
+        let payload = &data[HEADER_LEN..len];
+
+        let relevant = match kind {
+            // struct ifaddrmsg: family, prefixlen, flags, scope, index.
+            // Loopback and link-local addresses never reach the DNS record.
+            libc::RTM_NEWADDR | libc::RTM_DELADDR => {
+                payload.len() >= 4 && payload[3] == libc::RT_SCOPE_UNIVERSE
+            }
+            // struct rtmsg: family, dst_len, src_len, tos, table, protocol, scope, type, flags.
+            // Only the default route, a new uplink shows up there.
+            libc::RTM_NEWROUTE | libc::RTM_DELROUTE => {
+                payload.len() >= 8 && payload[1] == 0 && payload[7] == libc::RTN_UNICAST
+            }
+            _ => false,
+        };
+        if relevant {
+            return true;
+        }
+
+        // Messages are padded to 4 bytes
+        let aligned = (len + 3) & !3;
+        if aligned >= data.len() {
+            break;
+        }
+        data = &data[aligned..];
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(kind: u16, payload: &[u8]) -> Vec<u8> {
+        let len = HEADER_LEN + payload.len();
+        let mut message = Vec::new();
+        message.extend_from_slice(&(len as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(&[0; 10]); // flags, seq, pid
+        message.extend_from_slice(payload);
+        message.resize((len + 3) & !3, 0);
+        message
+    }
+
+    fn address(kind: u16, scope: u8) -> Vec<u8> {
+        message(kind, &[libc::AF_INET as u8, 24, 0, scope, 2, 0, 0, 0])
+    }
+
+    fn route(kind: u16, dst_len: u8) -> Vec<u8> {
+        message(
+            kind,
+            &[
+                libc::AF_INET as u8,
+                dst_len,
+                0,
+                0,
+                libc::RT_TABLE_MAIN,
+                libc::RTPROT_BOOT,
+                libc::RT_SCOPE_UNIVERSE,
+                libc::RTN_UNICAST,
+                0,
+                0,
+                0,
+                0,
+            ],
+        )
+    }
+
+    #[test]
+    fn address_scope() {
+        assert!(is_relevant(&address(
+            libc::RTM_NEWADDR,
+            libc::RT_SCOPE_UNIVERSE
+        )));
+        assert!(is_relevant(&address(
+            libc::RTM_DELADDR,
+            libc::RT_SCOPE_UNIVERSE
+        )));
+        assert!(!is_relevant(&address(
+            libc::RTM_NEWADDR,
+            libc::RT_SCOPE_LINK
+        )));
+        assert!(!is_relevant(&address(
+            libc::RTM_NEWADDR,
+            libc::RT_SCOPE_HOST
+        )));
+    }
+
+    #[test]
+    fn default_route_only() {
+        assert!(is_relevant(&route(libc::RTM_NEWROUTE, 0)));
+        assert!(is_relevant(&route(libc::RTM_DELROUTE, 0)));
+        assert!(!is_relevant(&route(libc::RTM_NEWROUTE, 24)));
+    }
+
+    #[test]
+    fn other_messages() {
+        assert!(!is_relevant(&message(libc::RTM_NEWLINK, &[0; 16])));
+        assert!(!is_relevant(&[]));
+    }
+
+    #[test]
+    fn finds_a_relevant_message_later_in_the_datagram() {
+        let mut data = address(libc::RTM_NEWADDR, libc::RT_SCOPE_LINK);
+        data.extend(route(libc::RTM_NEWROUTE, 24));
+        assert!(!is_relevant(&data));
+
+        data.extend(route(libc::RTM_NEWROUTE, 0));
+        assert!(is_relevant(&data));
+    }
+
+    #[test]
+    fn payloads_are_padded() {
+        // A 3 byte payload is followed by a byte of padding before the next message
+        let mut data = message(libc::RTM_NEWLINK, &[0; 3]);
+        assert_eq!(data.len(), HEADER_LEN + 4);
+        data.extend(address(libc::RTM_NEWADDR, libc::RT_SCOPE_UNIVERSE));
+        assert!(is_relevant(&data));
+    }
+
+    #[test]
+    fn truncated_messages() {
+        let full = address(libc::RTM_NEWADDR, libc::RT_SCOPE_UNIVERSE);
+        // Shorter than a header
+        assert!(!is_relevant(&full[..HEADER_LEN - 1]));
+        // Header claims more than the datagram holds
+        assert!(!is_relevant(&full[..full.len() - 1]));
+        // Payload too short to hold the scope
+        assert!(!is_relevant(&message(libc::RTM_NEWADDR, &[2, 24, 0])));
+        assert!(!is_relevant(&message(libc::RTM_NEWROUTE, &[2, 0, 0, 0])));
+    }
+
+    #[test]
+    fn bad_lengths_stop_the_walk() {
+        let relevant = address(libc::RTM_NEWADDR, libc::RT_SCOPE_UNIVERSE);
+
+        // nlmsg_len shorter than the header would loop forever if followed
+        let mut undersized = message(libc::RTM_NEWLINK, &[]);
+        undersized[..4].copy_from_slice(&4u32.to_ne_bytes());
+        undersized.extend(&relevant);
+        assert!(!is_relevant(&undersized));
+
+        let mut oversized = message(libc::RTM_NEWLINK, &[0; 4]);
+        oversized[..4].copy_from_slice(&u32::MAX.to_ne_bytes());
+        oversized.extend(&relevant);
+        assert!(!is_relevant(&oversized));
+    }
+}
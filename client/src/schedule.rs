@@ -0,0 +1,180 @@
+use crate::discovery;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+// However the config or the server set things up, never hammer the server
+const MIN_WAIT: Duration = Duration::from_secs(10);
+
+/// When the daemon syncs, the `schedule` section of the config
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SyncSchedule {
+    /// Seconds between syncs. Unset uses the interval the server suggests, or 300.
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+    /// Every wait is moved by up to this many seconds either way, so clients started together
+    /// (say after a power cut) don't keep syncing together
+    #[serde(default = "default_jitter_secs")]
+    pub jitter_secs: u64,
+    /// The wait after a failed sync, doubled with each further failure up to `backoff_max_secs`
+    #[serde(default = "default_backoff_initial_secs")]
+    pub backoff_initial_secs: u64,
+    #[serde(default = "default_backoff_max_secs")]
+    pub backoff_max_secs: u64,
+}
+
+impl Default for SyncSchedule {
+    fn default() -> Self {
+        SyncSchedule {
+            interval_secs: None,
+            jitter_secs: default_jitter_secs(),
+            backoff_initial_secs: default_backoff_initial_secs(),
+            backoff_max_secs: default_backoff_max_secs(),
+        }
+    }
+}
+
+fn default_jitter_secs() -> u64 {
+    30
+}
+
+fn default_backoff_initial_secs() -> u64 {
+    30
+}
+
+fn default_backoff_max_secs() -> u64 {
+    1800
+}
+
+impl SyncSchedule {
+    /// The wait after a sync that went through, `suggested` is what the server last asked for
+    pub fn interval(&self, suggested: Option<Duration>) -> Duration {
+        let interval = self
+            .interval_secs
+            .map(Duration::from_secs)
+            .or(suggested)
+            .unwrap_or(DEFAULT_INTERVAL);
+        self.jitter(interval)
+    }
+
+    /// The wait after `failures` failed syncs in a row, never shorter than the server's
+    /// Retry-After
+    pub fn backoff(&self, failures: u32, retry_after: Option<Duration>) -> Duration {
+        let initial = self.backoff_initial_secs.max(1);
+        let doublings = failures.saturating_sub(1).min(32);
+        let secs = initial
+            .saturating_mul(1 << doublings)
+            .min(self.backoff_max_secs.max(initial));
+
+        let backoff = self.jitter(Duration::from_secs(secs));
+        retry_after.map_or(backoff, |retry_after| retry_after.max(backoff))
+    }
+
+    fn jitter(&self, wait: Duration) -> Duration {
+        let range = Duration::from_secs(self.jitter_secs);
+        let spread = range.as_millis() as u64 * 2 + 1;
+        let offset = u64::from_ne_bytes(discovery::random_bytes()) % spread;
+
+        (wait + Duration::from_millis(offset))
+            .saturating_sub(range)
+            .max(MIN_WAIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(jitter_secs: u64) -> SyncSchedule {
+        SyncSchedule {
+            jitter_secs,
+            ..SyncSchedule::default()
+        }
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn interval_prefers_config_then_server_then_default() {
+        let configured = SyncSchedule {
+            interval_secs: Some(120),
+            ..schedule(0)
+        };
+
+        assert_eq!(configured.interval(Some(secs(60))), secs(120));
+        assert_eq!(schedule(0).interval(Some(secs(60))), secs(60));
+        assert_eq!(schedule(0).interval(None), DEFAULT_INTERVAL);
+    }
+
+    #[test]
+    fn waits_are_never_shorter_than_min_wait() {
+        let eager = SyncSchedule {
+            interval_secs: Some(0),
+            backoff_initial_secs: 0,
+            backoff_max_secs: 0,
+            ..schedule(5)
+        };
+
+        for _ in 0..100 {
+            assert!(eager.interval(None) >= MIN_WAIT);
+            assert!(eager.interval(Some(secs(1))) >= MIN_WAIT);
+            assert!(eager.backoff(1, None) >= MIN_WAIT);
+        }
+    }
+
+    #[test]
+    fn jitter_stays_within_its_range() {
+        let schedule = schedule(30);
+
+        for _ in 0..1000 {
+            let wait = schedule.interval(Some(secs(300)));
+            assert!(wait >= secs(270) && wait <= secs(330), "{:?}", wait);
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let schedule = schedule(0);
+
+        assert_eq!(schedule.backoff(0, None), secs(30));
+        assert_eq!(schedule.backoff(1, None), secs(30));
+        assert_eq!(schedule.backoff(2, None), secs(60));
+        assert_eq!(schedule.backoff(3, None), secs(120));
+        assert_eq!(schedule.backoff(6, None), secs(960));
+        assert_eq!(schedule.backoff(7, None), secs(1800));
+        assert_eq!(schedule.backoff(u32::MAX, None), secs(1800));
+    }
+
+    #[test]
+    fn backoff_cap_below_the_initial_wait_keeps_the_initial_wait() {
+        let schedule = SyncSchedule {
+            backoff_initial_secs: 120,
+            backoff_max_secs: 60,
+            ..schedule(0)
+        };
+
+        assert_eq!(schedule.backoff(1, None), secs(120));
+        assert_eq!(schedule.backoff(10, None), secs(120));
+    }
+
+    #[test]
+    fn backoff_doesnt_overflow() {
+        let schedule = SyncSchedule {
+            backoff_initial_secs: u64::MAX / 2,
+            backoff_max_secs: u64::MAX,
+            ..schedule(0)
+        };
+
+        assert_eq!(schedule.backoff(40, None), secs(u64::MAX));
+    }
+
+    #[test]
+    fn retry_after_wins_when_longer() {
+        let schedule = schedule(0);
+
+        assert_eq!(schedule.backoff(1, Some(secs(600))), secs(600));
+        assert_eq!(schedule.backoff(3, Some(secs(5))), secs(120));
+    }
+}
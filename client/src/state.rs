@@ -0,0 +1,227 @@
+use crate::api::PublicIp;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// What the daemon last synced, kept in the state dir so a restart doesn't sync again
+#[derive(Serialize, Deserialize, Default)]
+struct SyncState {
+    // By the public part of the API key, one per record the client syncs
+    records: HashMap<String, LastSync>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LastSync {
+    pub ipv4: Option<String>,
+    pub ipv6: Option<String>,
+    pub synced_on: NaiveDateTime,
+    pub response: String,
+}
+
+pub fn get_state_path() -> PathBuf {
+    // ~/.local/state on Linux, next to the config elsewhere
+    let base = dirs::state_dir()
+        .or_else(dirs::config_dir)
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("drago").join("last_sync.json")
+}
+
+// dgo_<public id>_<secret>, the secret stays out of the state file
+fn record_key(api_key: &str) -> String {
+    api_key.split('_').nth(1).unwrap_or("default").to_string()
+}
+
+fn load() -> SyncState {
+    fs::read_to_string(get_state_path())
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// The last sync for the key when it sent the same addresses less than `heartbeat` ago.
+/// Without any address the server uses the one the request comes from, so that always syncs.
+pub fn recent_sync(api_key: &str, ip: &PublicIp, heartbeat: Duration) -> Option<LastSync> {
+    recent_in(load(), api_key, ip, heartbeat, Utc::now().naive_utc())
+}
+
+fn recent_in(
+    mut state: SyncState,
+    api_key: &str,
+    ip: &PublicIp,
+    heartbeat: Duration,
+    now: NaiveDateTime,
+) -> Option<LastSync> {
+    if ip.ipv4.is_none() && ip.ipv6.is_none() {
+        return None;
+    }
+
+    let last = state.records.remove(&record_key(api_key))?;
+    let age = now - last.synced_on;
+    let unchanged = last.ipv4 == ip.ipv4 && last.ipv6 == ip.ipv6;
+
+    let recent = age.to_std().is_ok_and(|age| age < heartbeat);
+
+    (unchanged && recent).then_some(last)
+}
+
+/// Remember a sync the server confirmed
+pub fn save_sync(api_key: &str, ip: &PublicIp, response: &str) -> io::Result<()> {
+    let path = get_state_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut state = load();
+    state.records.insert(
+        record_key(api_key),
+        LastSync {
+            ipv4: ip.ipv4.clone(),
+            ipv6: ip.ipv6.clone(),
+            synced_on: Utc::now().naive_utc(),
+            response: response.to_string(),
+        },
+    );
+
+    let data = serde_json::to_string_pretty(&state)?;
+    fs::write(&path, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const API_KEY: &str = "dgo_abc123_secret";
+    const HEARTBEAT: Duration = Duration::from_secs(600);
+
+    fn ip(ipv4: Option<&str>, ipv6: Option<&str>) -> PublicIp {
+        PublicIp {
+            ipv4: ipv4.map(str::to_string),
+            ipv6: ipv6.map(str::to_string),
+        }
+    }
+
+    fn now() -> NaiveDateTime {
+        "2026-01-01T12:00:00".parse().unwrap()
+    }
+
+    // A state with one sync of 192.0.2.1 for API_KEY, `age` ago
+    fn synced(age: chrono::Duration) -> SyncState {
+        let mut state = SyncState::default();
+        state.records.insert(
+            record_key(API_KEY),
+            LastSync {
+                ipv4: Some("192.0.2.1".to_string()),
+                ipv6: None,
+                synced_on: now() - age,
+                response: "Record synced successfully".to_string(),
+            },
+        );
+        state
+    }
+
+    #[test]
+    fn same_address_within_the_heartbeat_is_recent() {
+        let last = recent_in(
+            synced(chrono::Duration::seconds(599)),
+            API_KEY,
+            &ip(Some("192.0.2.1"), None),
+            HEARTBEAT,
+            now(),
+        );
+
+        assert_eq!(last.unwrap().response, "Record synced successfully");
+    }
+
+    #[test]
+    fn heartbeat_due_syncs_again() {
+        for age in [600, 601, 86_400] {
+            assert!(
+                recent_in(
+                    synced(chrono::Duration::seconds(age)),
+                    API_KEY,
+                    &ip(Some("192.0.2.1"), None),
+                    HEARTBEAT,
+                    now(),
+                )
+                .is_none(),
+                "{}",
+                age
+            );
+        }
+    }
+
+    #[test]
+    fn sync_from_the_future_syncs_again() {
+        // The clock went back, the age can't be trusted
+        assert!(
+            recent_in(
+                synced(chrono::Duration::seconds(-60)),
+                API_KEY,
+                &ip(Some("192.0.2.1"), None),
+                HEARTBEAT,
+                now(),
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn changed_addresses_sync_again() {
+        for changed in [
+            ip(Some("192.0.2.2"), None),
+            ip(Some("192.0.2.1"), Some("2001:db8::1")),
+            ip(None, Some("2001:db8::1")),
+        ] {
+            assert!(
+                recent_in(
+                    synced(chrono::Duration::seconds(1)),
+                    API_KEY,
+                    &changed,
+                    HEARTBEAT,
+                    now(),
+                )
+                .is_none(),
+                "{}",
+                changed
+            );
+        }
+    }
+
+    #[test]
+    fn no_address_always_syncs() {
+        assert!(
+            recent_in(
+                synced(chrono::Duration::seconds(1)),
+                API_KEY,
+                &ip(None, None),
+                HEARTBEAT,
+                now(),
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn other_key_syncs() {
+        assert!(
+            recent_in(
+                synced(chrono::Duration::seconds(1)),
+                "dgo_other_secret",
+                &ip(Some("192.0.2.1"), None),
+                HEARTBEAT,
+                now(),
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn record_key_leaves_out_the_secret() {
+        assert_eq!(record_key("dgo_abc123_secret"), "abc123");
+        assert_eq!(record_key("nounderscores"), "default");
+    }
+}
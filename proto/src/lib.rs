@@ -0,0 +1,7 @@
+//! Request and response bodies the Drago server and the `drago` client exchange.
+//! Both crates use these types, so a change to the wire format has to compile on both sides.
+//!
+//! Each API version gets its own module. A breaking change goes in a new module, while the
+//! server keeps accepting the old one for clients that haven't updated.
+
+pub mod v1;
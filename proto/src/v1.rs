@@ -0,0 +1,626 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AddDnsRecord {
+    pub zone_id: String,
+    // Still sent by older clients, the name stored for the zone is what gets used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone_name: Option<String>,
+    pub record_type: String,
+    pub name: String,
+    pub content: String,
+    pub ttl: i32,
+    pub proxied: bool,
+    // ignore, log or repair, defaults to log
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drift_policy: Option<String>,
+    // Served while the client is offline: an address of the record's family or a CNAME target
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_content: Option<String>,
+}
+
+// Fields left out keep their current value
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UpdateDnsRecord {
+    pub record_id: String,
+    // Relative to the zone ("home") or fully qualified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_type: Option<String>,
+    // Only needed when the current content doesn't fit the new type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drift_policy: Option<String>,
+}
+
+// Takes over a record that already exists at the provider, its content is left as it is
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AdoptDnsRecord {
+    pub zone_id: String,
+    pub record_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drift_policy: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_content: Option<String>,
+    // Also create an API key with this name for the record
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AdoptedDnsRecord {
+    pub record: DnsRecord,
+    // Only shown once, like any new API key
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AddAccessToken {
+    pub name: String,
+    pub token: String,
+    // Which DNS host the token belongs to, defaults to cloudflare
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DnsRecord {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub content: String,
+    pub ttl: i32,
+    // Missing on providers without a proxy
+    #[serde(default)]
+    pub proxied: bool,
+}
+
+// A record as Drago manages it, with the outcome of the last drift check
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ManagedDnsRecord {
+    #[serde(flatten)]
+    pub record: DnsRecord,
+    pub drift_policy: String,
+    // None until the reconciler has looked at the record
+    pub reconcile: Option<ReconcileResult>,
+    // online, stale or offline; None for records no client syncs
+    pub client_status: Option<String>,
+    pub last_synced_on: NaiveDateTime,
+    pub fallback_content: Option<String>,
+    pub fallback_active: bool,
+    // Set once the record's zone disappeared from its provider
+    pub orphaned_on: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReconcileResult {
+    // in_sync, drifted, repaired, missing or error
+    pub status: String,
+    pub detail: Option<String>,
+    pub checked_on: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Zone {
+    pub id: String,
+    pub name: String,
+    pub status: String,
+    #[serde(default)]
+    pub name_servers: Vec<String>,
+}
+
+/// What POST /zones/refresh found, zones are listed by name
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ZoneRefresh {
+    pub tokens: usize,
+    pub added: Vec<String>,
+    pub refreshed: Vec<String>,
+    pub orphaned: Vec<String>,
+    // Ids of tokens whose zones couldn't be listed, their zones were left as they were
+    pub failed_tokens: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DnsAccessToken {
+    pub name: String,
+    pub id: String,
+    pub provider: String,
+    pub created_on: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ApiKey {
+    pub id: String,
+    pub created_on: NaiveDateTime,
+    pub last_used: Option<NaiveDateTime>,
+    pub name: String,
+    pub record_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AddApiKey {
+    pub name: String,
+    pub scope: String,
+}
+
+// API key is sent in the authorization header.
+// When no address is sent the server uses the one the request came from.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SyncRequest {
+    // Older clients send a single address of either family
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv4: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<String>,
+    // Used as the new time updated and time api key used
+    pub time_synced: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SyncResponse {
+    pub success: bool,
+    pub updated: bool,
+    pub message: String,
+    // How often the server expects the client to sync, what it judges the client online by.
+    // Older servers leave it out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_interval_secs: Option<u64>,
+}
+
+/// What every failed request is answered with
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ErrorBody {
+    pub error: ErrorDetail,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ErrorDetail {
+    /// Stable, e.g. `record.not_found` or `provider.rate_limited`
+    #[cfg_attr(feature = "openapi", schema(example = "record.not_found"))]
+    pub code: String,
+    pub message: String,
+    /// Extra fields for some codes, such as `operation_id` for `provider.queued`
+    #[serde(default)]
+    pub details: Option<Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use serde_json::json;
+    use std::fmt::Debug;
+
+    fn time() -> NaiveDateTime {
+        NaiveDateTime::parse_from_str("2025-06-01 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    // Serializes to exactly `wire` and reads back to the same value
+    fn round_trip<T>(value: T, wire: Value)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug,
+    {
+        assert_eq!(serde_json::to_value(&value).unwrap(), wire);
+        assert_eq!(serde_json::from_value::<T>(wire).unwrap(), value);
+    }
+
+    fn record() -> DnsRecord {
+        DnsRecord {
+            id: "rec".to_string(),
+            name: "home.example.com".to_string(),
+            record_type: "A".to_string(),
+            content: "192.0.2.1".to_string(),
+            ttl: 300,
+            proxied: true,
+        }
+    }
+
+    #[test]
+    fn sync_request() {
+        round_trip(
+            SyncRequest {
+                ip_address: None,
+                ipv4: Some("192.0.2.1".to_string()),
+                ipv6: Some("2001:db8::1".to_string()),
+                time_synced: time(),
+            },
+            json!({
+                "ipv4": "192.0.2.1",
+                "ipv6": "2001:db8::1",
+                "time_synced": "2025-06-01T12:30:00",
+            }),
+        );
+        // Older clients send a single address
+        round_trip(
+            SyncRequest {
+                ip_address: Some("192.0.2.1".to_string()),
+                ipv4: None,
+                ipv6: None,
+                time_synced: time(),
+            },
+            json!({ "ip_address": "192.0.2.1", "time_synced": "2025-06-01T12:30:00" }),
+        );
+    }
+
+    #[test]
+    fn sync_response() {
+        round_trip(
+            SyncResponse {
+                success: true,
+                updated: false,
+                message: "unchanged".to_string(),
+                sync_interval_secs: Some(300),
+            },
+            json!({
+                "success": true,
+                "updated": false,
+                "message": "unchanged",
+                "sync_interval_secs": 300,
+            }),
+        );
+        // Older servers leave the interval out
+        round_trip(
+            SyncResponse {
+                success: true,
+                updated: true,
+                message: "updated".to_string(),
+                sync_interval_secs: None,
+            },
+            json!({ "success": true, "updated": true, "message": "updated" }),
+        );
+    }
+
+    #[test]
+    fn dns_record() {
+        round_trip(
+            record(),
+            json!({
+                "id": "rec",
+                "name": "home.example.com",
+                "type": "A",
+                "content": "192.0.2.1",
+                "ttl": 300,
+                "proxied": true,
+            }),
+        );
+
+        let unproxied: DnsRecord = serde_json::from_value(json!({
+            "id": "rec",
+            "name": "home.example.com",
+            "type": "A",
+            "content": "192.0.2.1",
+            "ttl": 300,
+        }))
+        .unwrap();
+        assert!(!unproxied.proxied);
+    }
+
+    #[test]
+    fn managed_dns_record() {
+        round_trip(
+            ManagedDnsRecord {
+                record: record(),
+                drift_policy: "log".to_string(),
+                reconcile: Some(ReconcileResult {
+                    status: "in_sync".to_string(),
+                    detail: None,
+                    checked_on: time(),
+                }),
+                client_status: Some("online".to_string()),
+                last_synced_on: time(),
+                fallback_content: None,
+                fallback_active: false,
+                orphaned_on: None,
+            },
+            json!({
+                "id": "rec",
+                "name": "home.example.com",
+                "type": "A",
+                "content": "192.0.2.1",
+                "ttl": 300,
+                "proxied": true,
+                "drift_policy": "log",
+                "reconcile": {
+                    "status": "in_sync",
+                    "detail": null,
+                    "checked_on": "2025-06-01T12:30:00",
+                },
+                "client_status": "online",
+                "last_synced_on": "2025-06-01T12:30:00",
+                "fallback_content": null,
+                "fallback_active": false,
+                "orphaned_on": null,
+            }),
+        );
+    }
+
+    #[test]
+    fn adopted_dns_record() {
+        round_trip(
+            AdoptedDnsRecord {
+                record: record(),
+                api_key: None,
+            },
+            json!({
+                "record": {
+                    "id": "rec",
+                    "name": "home.example.com",
+                    "type": "A",
+                    "content": "192.0.2.1",
+                    "ttl": 300,
+                    "proxied": true,
+                },
+                "api_key": null,
+            }),
+        );
+    }
+
+    #[test]
+    fn zone() {
+        round_trip(
+            Zone {
+                id: "zone".to_string(),
+                name: "example.com".to_string(),
+                status: "active".to_string(),
+                name_servers: vec!["ns1.example.net".to_string()],
+            },
+            json!({
+                "id": "zone",
+                "name": "example.com",
+                "status": "active",
+                "name_servers": ["ns1.example.net"],
+            }),
+        );
+
+        let zone: Zone = serde_json::from_value(json!({
+            "id": "zone",
+            "name": "example.com",
+            "status": "pending",
+        }))
+        .unwrap();
+        assert!(zone.name_servers.is_empty());
+    }
+
+    #[test]
+    fn zone_refresh() {
+        round_trip(
+            ZoneRefresh {
+                tokens: 2,
+                added: vec!["example.com".to_string()],
+                refreshed: vec![],
+                orphaned: vec!["example.org".to_string()],
+                failed_tokens: vec!["token".to_string()],
+            },
+            json!({
+                "tokens": 2,
+                "added": ["example.com"],
+                "refreshed": [],
+                "orphaned": ["example.org"],
+                "failed_tokens": ["token"],
+            }),
+        );
+    }
+
+    #[test]
+    fn access_tokens() {
+        round_trip(
+            AddAccessToken {
+                name: "home".to_string(),
+                token: "secret".to_string(),
+                provider: None,
+            },
+            json!({ "name": "home", "token": "secret" }),
+        );
+        round_trip(
+            DnsAccessToken {
+                name: "home".to_string(),
+                id: "token".to_string(),
+                provider: "cloudflare".to_string(),
+                created_on: time(),
+            },
+            json!({
+                "name": "home",
+                "id": "token",
+                "provider": "cloudflare",
+                "created_on": "2025-06-01T12:30:00",
+            }),
+        );
+    }
+
+    #[test]
+    fn api_keys() {
+        round_trip(
+            ApiKey {
+                id: "key".to_string(),
+                created_on: time(),
+                last_used: Some(time()),
+                name: "laptop".to_string(),
+                record_name: "home.example.com".to_string(),
+            },
+            json!({
+                "id": "key",
+                "created_on": "2025-06-01T12:30:00",
+                "last_used": "2025-06-01T12:30:00",
+                "name": "laptop",
+                "record_name": "home.example.com",
+            }),
+        );
+        round_trip(
+            ApiKey {
+                id: "key".to_string(),
+                created_on: time(),
+                last_used: None,
+                name: "laptop".to_string(),
+                record_name: "home.example.com".to_string(),
+            },
+            json!({
+                "id": "key",
+                "created_on": "2025-06-01T12:30:00",
+                "last_used": null,
+                "name": "laptop",
+                "record_name": "home.example.com",
+            }),
+        );
+        round_trip(
+            AddApiKey {
+                name: "laptop".to_string(),
+                scope: "rec".to_string(),
+            },
+            json!({ "name": "laptop", "scope": "rec" }),
+        );
+    }
+
+    #[test]
+    fn add_dns_record() {
+        round_trip(
+            AddDnsRecord {
+                zone_id: "zone".to_string(),
+                zone_name: None,
+                record_type: "A".to_string(),
+                name: "home".to_string(),
+                content: "192.0.2.1".to_string(),
+                ttl: 300,
+                proxied: false,
+                drift_policy: None,
+                fallback_content: None,
+            },
+            json!({
+                "zone_id": "zone",
+                "record_type": "A",
+                "name": "home",
+                "content": "192.0.2.1",
+                "ttl": 300,
+                "proxied": false,
+            }),
+        );
+        round_trip(
+            AddDnsRecord {
+                zone_id: "zone".to_string(),
+                zone_name: Some("example.com".to_string()),
+                record_type: "AAAA".to_string(),
+                name: "home".to_string(),
+                content: "2001:db8::1".to_string(),
+                ttl: 1,
+                proxied: true,
+                drift_policy: Some("repair".to_string()),
+                fallback_content: Some("backup.example.com".to_string()),
+            },
+            json!({
+                "zone_id": "zone",
+                "zone_name": "example.com",
+                "record_type": "AAAA",
+                "name": "home",
+                "content": "2001:db8::1",
+                "ttl": 1,
+                "proxied": true,
+                "drift_policy": "repair",
+                "fallback_content": "backup.example.com",
+            }),
+        );
+    }
+
+    #[test]
+    fn update_dns_record() {
+        round_trip(
+            UpdateDnsRecord {
+                record_id: "rec".to_string(),
+                name: None,
+                record_type: None,
+                content: None,
+                ttl: None,
+                proxied: None,
+                drift_policy: None,
+            },
+            json!({ "record_id": "rec" }),
+        );
+        round_trip(
+            UpdateDnsRecord {
+                record_id: "rec".to_string(),
+                name: Some("office".to_string()),
+                record_type: Some("CNAME".to_string()),
+                content: Some("home.example.com".to_string()),
+                ttl: Some(60),
+                proxied: Some(false),
+                drift_policy: Some("ignore".to_string()),
+            },
+            json!({
+                "record_id": "rec",
+                "name": "office",
+                "record_type": "CNAME",
+                "content": "home.example.com",
+                "ttl": 60,
+                "proxied": false,
+                "drift_policy": "ignore",
+            }),
+        );
+    }
+
+    #[test]
+    fn adopt_dns_record() {
+        round_trip(
+            AdoptDnsRecord {
+                zone_id: "zone".to_string(),
+                record_id: "rec".to_string(),
+                drift_policy: None,
+                fallback_content: None,
+                api_key_name: Some("laptop".to_string()),
+            },
+            json!({ "zone_id": "zone", "record_id": "rec", "api_key_name": "laptop" }),
+        );
+    }
+
+    #[test]
+    fn error_body() {
+        round_trip(
+            ErrorBody {
+                error: ErrorDetail {
+                    code: "provider.queued".to_string(),
+                    message: "Queued for retry".to_string(),
+                    details: Some(json!({ "operation_id": "op" })),
+                },
+            },
+            json!({
+                "error": {
+                    "code": "provider.queued",
+                    "message": "Queued for retry",
+                    "details": { "operation_id": "op" },
+                },
+            }),
+        );
+
+        let body: ErrorBody = serde_json::from_value(json!({
+            "error": { "code": "record.not_found", "message": "Record not found" },
+        }))
+        .unwrap();
+        assert_eq!(body.error.code, "record.not_found");
+        assert_eq!(body.error.details, None);
+    }
+}
@@ -19,6 +19,7 @@ export const dnsToken = mysqlTable('dns_token', {
 	userId: varchar('user_id', { length: 36 })
 		.notNull()
 		.references(() => user.id, { onDelete: 'cascade' }),
+	provider: varchar('provider', { length: 32 }).default('cloudflare').notNull(), // Which DNS host the token talks to
 	nonce: varbinary('nonce', { length: 12 }).notNull(), // 12-byte AES-GCM IV
 	tokenEncrypted: varbinary('token_encrypted', { length: 1024 }).notNull(), // ciphertext
 	tag: varbinary('tag', { length: 16 }).notNull(),
@@ -43,8 +44,9 @@ export const apiKeys = mysqlTable(
 		prefixId: varchar('prefix_id', { length: 20 }).notNull().unique(), // Indexed lookup
 		keyHash: varchar('key_hash', { length: 97 }).notNull(),
 		// Each API key can control a single DNS record. This is how we identify which record to update when a client syncs to us.
+		// Follows the record when an edit changes its id (rfc2136 ids are made of name and type)
 		dns_record_id: varchar('dns_record_id', { length: 255 })
-			.references(() => dnsRecord.id, { onDelete: 'cascade' })
+			.references(() => dnsRecord.id, { onDelete: 'cascade', onUpdate: 'cascade' })
 			.unique()
 			.notNull(),
 		lastUsed: timestamp('last_used'),
@@ -82,7 +84,17 @@ export const dnsRecord = mysqlTable(
 		lastSyncedOn: timestamp('last_synced_on')
 			.defaultNow()
 			.$onUpdate(() => new Date())
-			.notNull()
+			.notNull(),
+		// What to do when the provider's copy differs from ours: ignore, log or repair
+		driftPolicy: varchar('drift_policy', { length: 16 }).default('log').notNull(),
+		lastReconciledOn: timestamp('last_reconciled_on'),
+		reconcileStatus: varchar('reconcile_status', { length: 16 }), // in_sync, drifted, repaired, missing or error
+		reconcileDetail: text('reconcile_detail'),
+		// Served while the client is offline: an address of the record's family or a CNAME target
+		fallbackContent: text('fallback_content'),
+		fallbackActive: boolean('fallback_active').default(false).notNull(),
+		// Set when the record's zone disappeared from its provider
+		orphanedOn: timestamp('orphaned_on')
 	},
 	(t) => ({
 		userIdx: index('idx_dns_record_user_id').on(t.userId),
@@ -109,7 +121,9 @@ export const dnsZone = mysqlTable(
 			.defaultNow()
 			.$onUpdate(() => new Date())
 			.notNull(),
-		meta: text('meta') // store JSON (for nameservers, plan, status, etc.)
+		meta: text('meta'), // store JSON (for nameservers, plan, status, etc.)
+		// Set when the zone no longer shows up for its token
+		orphanedOn: timestamp('orphaned_on')
 	},
 	(t) => ({
 		userIdx: index('idx_dns_zone_user_id').on(t.userId),
@@ -117,3 +131,110 @@ export const dnsZone = mysqlTable(
 		zoneNameIdx: index('idx_dns_zone_zone_name').on(t.zoneName)
 	})
 );
+
+// ---------------------------
+// Sync History
+// ---------------------------
+export const syncEvent = mysqlTable(
+	'sync_event',
+	{
+		id: varchar('id', { length: 36 }).primaryKey(),
+		dnsRecordId: varchar('dns_record_id', { length: 255 })
+			.notNull()
+			.references(() => dnsRecord.id, { onDelete: 'cascade', onUpdate: 'cascade' }),
+		// Kept when the key is deleted so the history still shows the change
+		apiKeyId: varchar('api_key_id', { length: 36 }).references(() => apiKeys.id, {
+			onDelete: 'set null'
+		}),
+		oldContent: text('old_content'),
+		newContent: text('new_content'),
+		outcome: varchar('outcome', { length: 16 }).notNull(), // updated, unchanged or error
+		providerResponse: text('provider_response'), // What the DNS provider answered, or why the sync failed
+		createdOn: timestamp('created_on').defaultNow().notNull()
+	},
+	(t) => ({
+		recordCreatedIdx: index('idx_sync_event_record_created').on(t.dnsRecordId, t.createdOn)
+	})
+);
+
+// ---------------------------
+// Webhooks
+// ---------------------------
+export const webhook = mysqlTable(
+	'webhook',
+	{
+		id: varchar('id', { length: 36 }).primaryKey(),
+		userId: varchar('user_id', { length: 36 })
+			.notNull()
+			.references(() => user.id, { onDelete: 'cascade' }),
+		name: varchar('name', { length: 255 }).notNull(),
+		url: text('url').notNull(),
+		// Signing secret, encrypted the same way as DNS tokens
+		nonce: varbinary('nonce', { length: 12 }).notNull(),
+		secretEncrypted: varbinary('secret_encrypted', { length: 1024 }).notNull(),
+		tag: varbinary('tag', { length: 16 }).notNull(),
+		createdOn: timestamp('created_on').defaultNow().notNull(),
+		updatedOn: timestamp('updated_on')
+			.defaultNow()
+			.$onUpdate(() => new Date())
+			.notNull()
+	},
+	(t) => ({
+		userIdx: index('idx_webhook_user_id').on(t.userId)
+	})
+);
+
+// One row per delivery attempt
+export const webhookDelivery = mysqlTable(
+	'webhook_delivery',
+	{
+		id: varchar('id', { length: 36 }).primaryKey(),
+		webhookId: varchar('webhook_id', { length: 36 })
+			.notNull()
+			.references(() => webhook.id, { onDelete: 'cascade' }),
+		eventId: varchar('event_id', { length: 36 }).notNull(),
+		attempt: int('attempt').notNull(),
+		statusCode: int('status_code'), // Null when the receiver couldn't be reached
+		response: text('response'), // Start of the response body, or the connection error
+		success: boolean('success').notNull(),
+		createdOn: timestamp('created_on').defaultNow().notNull()
+	},
+	(t) => ({
+		webhookCreatedIdx: index('idx_webhook_delivery_webhook_created').on(t.webhookId, t.createdOn)
+	})
+);
+
+// ---------------------------
+// Provider Operations
+// ---------------------------
+// Every change Drago makes at a DNS provider is written here before it is sent, so a crash
+// or timeout between the provider and our own tables is retried instead of lost
+export const providerOperation = mysqlTable(
+	'provider_operation',
+	{
+		id: varchar('id', { length: 36 }).primaryKey(),
+		userId: varchar('user_id', { length: 36 })
+			.notNull()
+			.references(() => user.id, { onDelete: 'cascade' }),
+		zoneId: varchar('zone_id', { length: 255 })
+			.notNull()
+			.references(() => dnsZone.id, { onDelete: 'cascade' }),
+		// The record the operation is about, filled in once a create has been done
+		recordId: varchar('record_id', { length: 255 }),
+		kind: varchar('kind', { length: 16 }).notNull(), // create, update or delete
+		operation: text('operation').notNull(), // JSON: what to send and what to store afterwards
+		status: varchar('status', { length: 16 }).notNull(), // pending, running, done, failed or superseded
+		attempts: int('attempts').default(0).notNull(),
+		lastError: text('last_error'),
+		result: text('result'), // JSON of the record as the provider returned it
+		nextAttemptOn: timestamp('next_attempt_on').defaultNow().notNull(),
+		// A running operation past this time was abandoned (e.g. the server crashed) and is picked up again
+		lockedUntil: timestamp('locked_until'),
+		createdOn: timestamp('created_on').defaultNow().notNull(),
+		completedOn: timestamp('completed_on')
+	},
+	(t) => ({
+		statusNextAttemptIdx: index('idx_provider_operation_status_next').on(t.status, t.nextAttemptOn),
+		recordIdx: index('idx_provider_operation_record_id').on(t.recordId)
+	})
+);
@@ -1,4 +1,4 @@
-use crate::db::schema;
+use crate::{db::schema, lib::types::DnsRecord};
 use diesel::prelude::*;
 
 #[derive(Debug, Queryable, Selectable, Insertable)]
@@ -7,6 +7,7 @@ use diesel::prelude::*;
 pub struct DnsAccessToken {
     id: String,
     user_id: String,
+    provider: String,
     nonce: Vec<u8>,
     token_encrypted: Vec<u8>,
     tag: Vec<u8>,
@@ -20,6 +21,7 @@ pub struct NewDnsAccessToken<'a> {
     pub id: &'a str,
     pub name: &'a str,
     pub user_id: &'a str,
+    pub provider: &'a str,
     pub nonce: &'a Vec<u8>,
     pub token_encrypted: &'a Vec<u8>,
     pub tag: &'a Vec<u8>,
@@ -35,4 +37,61 @@ pub struct PutDnsRecord {
     pub record_name: String,
     pub ttl: i32,
     pub record_type: String,
+    pub proxied: bool,
+    // The provider is serving the record's fallback until the client syncs again
+    pub fallback_active: bool,
+}
+
+// What a finished provider operation writes to the record's row, None fields are left alone
+#[derive(Debug, AsChangeset)]
+#[diesel(table_name = schema::dns_record)]
+pub struct DnsRecordChanges<'a> {
+    pub id: &'a str,
+    pub record_name: &'a str,
+    pub ttl: i32,
+    pub proxied: bool,
+    pub record_type: Option<&'a str>,
+    pub content: Option<&'a str>,
+    pub fallback_active: Option<bool>,
+    pub drift_policy: Option<&'a str>,
+    pub last_synced_on: Option<chrono::NaiveDateTime>,
+}
+
+// Everything PUT /record may change, plus what decides how the change reaches the provider
+#[derive(Debug, Clone, Queryable)]
+#[diesel(table_name = schema::dns_record)]
+pub struct EditableDnsRecord {
+    pub id: String,
+    pub zone_id: String,
+    pub record_name: String,
+    pub record_type: String,
+    pub content: String,
+    pub ttl: i32,
+    pub proxied: bool,
+    pub fallback_content: Option<String>,
+    pub fallback_active: bool,
+}
+
+impl From<EditableDnsRecord> for DnsRecord {
+    fn from(record: EditableDnsRecord) -> Self {
+        Self {
+            id: record.id,
+            name: record.record_name,
+            record_type: record.record_type,
+            content: record.content,
+            ttl: record.ttl,
+            proxied: record.proxied,
+        }
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = schema::sync_event)]
+pub struct NewSyncEvent<'a> {
+    pub dns_record_id: &'a str,
+    pub api_key_id: Option<&'a str>,
+    pub old_content: Option<&'a str>,
+    pub new_content: Option<&'a str>,
+    pub outcome: &'a str,
+    pub provider_response: Option<&'a str>,
 }
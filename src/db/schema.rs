@@ -73,6 +73,15 @@ diesel::table! {
         ttl -> Integer,
         proxied -> Bool,
         last_synced_on -> Timestamp,
+        #[max_length = 16]
+        drift_policy -> Varchar,
+        last_reconciled_on -> Nullable<Timestamp>,
+        #[max_length = 16]
+        reconcile_status -> Nullable<Varchar>,
+        reconcile_detail -> Nullable<Text>,
+        fallback_content -> Nullable<Text>,
+        fallback_active -> Bool,
+        orphaned_on -> Nullable<Timestamp>,
     }
 }
 
@@ -84,6 +93,8 @@ diesel::table! {
         name -> Varchar,
         #[max_length = 36]
         user_id -> Varchar,
+        #[max_length = 32]
+        provider -> Varchar,
         #[max_length = 12]
         nonce -> Varbinary,
         #[max_length = 1024]
@@ -107,6 +118,7 @@ diesel::table! {
         zone_name -> Varchar,
         last_synced_on -> Timestamp,
         meta -> Nullable<Text>,
+        orphaned_on -> Nullable<Timestamp>,
     }
 }
 
@@ -121,6 +133,31 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    provider_operation (id) {
+        #[max_length = 36]
+        id -> Varchar,
+        #[max_length = 36]
+        user_id -> Varchar,
+        #[max_length = 255]
+        zone_id -> Varchar,
+        #[max_length = 255]
+        record_id -> Nullable<Varchar>,
+        #[max_length = 16]
+        kind -> Varchar,
+        operation -> Text,
+        #[max_length = 16]
+        status -> Varchar,
+        attempts -> Integer,
+        last_error -> Nullable<Text>,
+        result -> Nullable<Text>,
+        next_attempt_on -> Timestamp,
+        locked_until -> Nullable<Timestamp>,
+        created_on -> Timestamp,
+        completed_on -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     session (id) {
         #[max_length = 36]
@@ -137,6 +174,23 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    sync_event (id) {
+        #[max_length = 36]
+        id -> Varchar,
+        #[max_length = 255]
+        dns_record_id -> Varchar,
+        #[max_length = 36]
+        api_key_id -> Nullable<Varchar>,
+        old_content -> Nullable<Text>,
+        new_content -> Nullable<Text>,
+        #[max_length = 16]
+        outcome -> Varchar,
+        provider_response -> Nullable<Text>,
+        created_on -> Timestamp,
+    }
+}
+
 diesel::table! {
     user (id) {
         #[max_length = 36]
@@ -165,6 +219,42 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    webhook (id) {
+        #[max_length = 36]
+        id -> Varchar,
+        #[max_length = 36]
+        user_id -> Varchar,
+        #[max_length = 255]
+        name -> Varchar,
+        url -> Text,
+        #[max_length = 12]
+        nonce -> Varbinary,
+        #[max_length = 1024]
+        secret_encrypted -> Varbinary,
+        #[max_length = 16]
+        tag -> Varbinary,
+        created_on -> Timestamp,
+        updated_on -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhook_delivery (id) {
+        #[max_length = 36]
+        id -> Varchar,
+        #[max_length = 36]
+        webhook_id -> Varchar,
+        #[max_length = 36]
+        event_id -> Varchar,
+        attempt -> Integer,
+        status_code -> Nullable<Integer>,
+        response -> Nullable<Text>,
+        success -> Bool,
+        created_on -> Timestamp,
+    }
+}
+
 diesel::joinable!(account -> user (user_id));
 diesel::joinable!(api_keys -> dns_record (dns_record_id));
 diesel::joinable!(api_keys -> user (user_id));
@@ -173,7 +263,13 @@ diesel::joinable!(dns_record -> user (user_id));
 diesel::joinable!(dns_token -> user (user_id));
 diesel::joinable!(dns_zone -> dns_token (token_id));
 diesel::joinable!(dns_zone -> user (user_id));
+diesel::joinable!(provider_operation -> dns_zone (zone_id));
+diesel::joinable!(provider_operation -> user (user_id));
 diesel::joinable!(session -> user (user_id));
+diesel::joinable!(sync_event -> api_keys (api_key_id));
+diesel::joinable!(sync_event -> dns_record (dns_record_id));
+diesel::joinable!(webhook -> user (user_id));
+diesel::joinable!(webhook_delivery -> webhook (webhook_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     account,
@@ -183,7 +279,11 @@ diesel::allow_tables_to_appear_in_same_query!(
     dns_token,
     dns_zone,
     jwks,
+    provider_operation,
     session,
+    sync_event,
     user,
     verification,
+    webhook,
+    webhook_delivery,
 );
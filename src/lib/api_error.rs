@@ -0,0 +1,240 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Value};
+
+use crate::lib::{outbox::OutboxError, providers::ProviderError};
+
+/// Something the API hands out by id, for `<resource>.not_found`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Record,
+    /// A record looked up at the DNS provider rather than in our tables
+    ProviderRecord,
+    Zone,
+    DnsToken,
+    ApiKey,
+    Webhook,
+    Operation,
+}
+
+impl Resource {
+    fn code(&self) -> &'static str {
+        match self {
+            Resource::Record => "record.not_found",
+            Resource::ProviderRecord => "provider.record_not_found",
+            Resource::Zone => "zone.not_found",
+            Resource::DnsToken => "token.not_found",
+            Resource::ApiKey => "api_key.not_found",
+            Resource::Webhook => "webhook.not_found",
+            Resource::Operation => "operation.not_found",
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Resource::Record => "Record not found",
+            Resource::ProviderRecord => "Record not found at the provider",
+            Resource::Zone => "Zone not found",
+            Resource::DnsToken => "DNS token not found",
+            Resource::ApiKey => "API key not found",
+            Resource::Webhook => "Webhook not found",
+            Resource::Operation => "Operation not found",
+        }
+    }
+}
+
+/// Every way a request can fail. Sent as `{"error": {"code", "message", "details"}}`, where the
+/// code is stable for clients to branch on and the message is for people.
+#[derive(Debug)]
+pub enum ApiError {
+    /// Missing, malformed or unknown API key
+    InvalidApiKey(String),
+    /// Missing or invalid session token
+    Unauthorized(String),
+    /// The resource doesn't exist or belongs to another account, which look the same
+    NotFound(Resource),
+    /// The API key is valid but controls another name
+    HostMismatch(String),
+    /// The request can't be right whatever the state of things
+    Invalid(String),
+    /// The DNS token was refused by its provider when it was added
+    InvalidDnsToken(String),
+    RecordExists,
+    /// The record exists at the provider, it can be adopted instead
+    RecordAtProvider {
+        name: String,
+        record_id: String,
+    },
+    RecordAlreadyManaged,
+    /// Another record with the name is in the way
+    RecordConflict(String),
+    Provider(ProviderError),
+    /// The provider failed but the change is queued and will be retried
+    Queued {
+        operation_id: String,
+        error: ProviderError,
+    },
+    Database(diesel::result::Error),
+    Internal(String),
+}
+
+// The envelope is shared with the client, which reads the code back out of it
+pub use drago_proto::v1::{ErrorBody, ErrorDetail};
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidApiKey(_) | ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) | ApiError::HostMismatch(_) => StatusCode::NOT_FOUND,
+            ApiError::Invalid(_) | ApiError::InvalidDnsToken(_) => StatusCode::BAD_REQUEST,
+            ApiError::RecordExists
+            | ApiError::RecordAtProvider { .. }
+            | ApiError::RecordAlreadyManaged
+            | ApiError::RecordConflict(_) => StatusCode::CONFLICT,
+            ApiError::Provider(error) => provider_status(error),
+            ApiError::Queued { .. } => StatusCode::ACCEPTED,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidApiKey(_) => "auth.invalid_key",
+            ApiError::Unauthorized(_) => "auth.unauthorized",
+            ApiError::NotFound(resource) => resource.code(),
+            ApiError::HostMismatch(_) => "record.not_found",
+            ApiError::Invalid(_) => "request.invalid",
+            ApiError::InvalidDnsToken(_) => "token.invalid",
+            ApiError::RecordExists => "record.exists",
+            ApiError::RecordAtProvider { .. } => "record.exists_at_provider",
+            ApiError::RecordAlreadyManaged => "record.already_managed",
+            ApiError::RecordConflict(_) => "record.conflict",
+            ApiError::Provider(error) => provider_code(error),
+            ApiError::Queued { .. } => "provider.queued",
+            ApiError::Database(_) => "internal.database",
+            ApiError::Internal(_) => "internal.error",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ApiError::InvalidApiKey(msg)
+            | ApiError::Unauthorized(msg)
+            | ApiError::Invalid(msg)
+            | ApiError::InvalidDnsToken(msg)
+            | ApiError::RecordConflict(msg) => msg.clone(),
+            ApiError::NotFound(resource) => resource.message().to_string(),
+            ApiError::HostMismatch(hostname) => format!("API key does not control {}", hostname),
+            ApiError::RecordExists => "Record already exists".to_string(),
+            ApiError::RecordAtProvider { name, .. } => format!(
+                "{} already exists at the provider, adopt it with POST /record/adopt",
+                name
+            ),
+            ApiError::RecordAlreadyManaged => "Record already managed".to_string(),
+            ApiError::Provider(error) => error.to_string(),
+            ApiError::Queued { error, .. } => {
+                format!("{}, the change is queued and will be retried", error)
+            }
+            // Details stay in the log, they describe our tables rather than the request
+            ApiError::Database(_) => "Database error".to_string(),
+            ApiError::Internal(_) => "Internal error".to_string(),
+        }
+    }
+
+    fn details(&self) -> Option<Value> {
+        match self {
+            ApiError::RecordAtProvider { record_id, .. } => Some(json!({ "record_id": record_id })),
+            ApiError::Provider(ProviderError::Api { status, .. }) => {
+                Some(json!({ "provider_status": status }))
+            }
+            ApiError::Queued { operation_id, .. } => Some(json!({ "operation_id": operation_id })),
+            _ => None,
+        }
+    }
+}
+
+// The provider is upstream of us: its failures are gateway errors unless the request was at fault
+fn provider_status(error: &ProviderError) -> StatusCode {
+    match error {
+        ProviderError::Api { status: 429, .. } => StatusCode::TOO_MANY_REQUESTS,
+        // Our token or record id no longer works there, not something the caller can fix
+        ProviderError::Api {
+            status: 401 | 403 | 404,
+            ..
+        } => StatusCode::BAD_GATEWAY,
+        // The provider refused what the caller asked for, e.g. content it doesn't accept
+        ProviderError::Api { status, .. } if *status < 500 => StatusCode::UNPROCESSABLE_ENTITY,
+        ProviderError::Network(_) => StatusCode::SERVICE_UNAVAILABLE,
+        ProviderError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+        ProviderError::Config(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        ProviderError::Api { .. } | ProviderError::Decode(_) => StatusCode::BAD_GATEWAY,
+    }
+}
+
+fn provider_code(error: &ProviderError) -> &'static str {
+    match error {
+        ProviderError::Api { status: 429, .. } => "provider.rate_limited",
+        ProviderError::Api {
+            status: 401 | 403, ..
+        } => "provider.auth_failed",
+        ProviderError::Api { status: 404, .. } => "provider.not_found",
+        ProviderError::Api { status, .. } if *status >= 500 => "provider.unavailable",
+        ProviderError::Api { .. } => "provider.rejected",
+        ProviderError::Network(_) => "provider.unreachable",
+        ProviderError::Decode(_) => "provider.bad_response",
+        ProviderError::Unsupported(_) => "provider.unsupported",
+        ProviderError::Config(_) => "provider.misconfigured",
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match &self {
+            ApiError::Database(err) => tracing::error!("Database error: {}", err),
+            ApiError::Internal(msg) => tracing::error!("Internal error: {}", msg),
+            _ => {}
+        }
+
+        let body = ErrorBody {
+            error: ErrorDetail {
+                code: self.code().to_string(),
+                message: self.message(),
+                details: self.details(),
+            },
+        };
+
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(err: diesel::result::Error) -> Self {
+        ApiError::Database(err)
+    }
+}
+
+impl From<ProviderError> for ApiError {
+    fn from(err: ProviderError) -> Self {
+        ApiError::Provider(err)
+    }
+}
+
+impl From<OutboxError> for ApiError {
+    fn from(err: OutboxError) -> Self {
+        match err {
+            OutboxError::Provider {
+                operation_id,
+                error,
+                queued: true,
+            } => ApiError::Queued {
+                operation_id,
+                error,
+            },
+            OutboxError::Provider { error, .. } => ApiError::Provider(error),
+            OutboxError::Db(msg) => ApiError::Internal(msg),
+        }
+    }
+}
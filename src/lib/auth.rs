@@ -1,15 +1,22 @@
 use axum::{
     extract::{FromRef, FromRequestParts},
-    http::{header::AUTHORIZATION, request::Parts, StatusCode},
+    http::{header::AUTHORIZATION, request::Parts},
 };
 use jsonwebtoken::decode_header;
 use rand::{distr::Alphanumeric, Rng};
 use reqwest::Client;
 use serde::Deserialize;
 use serde_json::Value;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use subtle::ConstantTimeEq;
 use tokio::sync::RwLock;
 
+use crate::lib::{api_error::ApiError, metrics};
+
 // Base64 engine for URL-safe decoding
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 
@@ -57,28 +64,23 @@ impl AuthState {
         }
     }
 
-    pub async fn get_key(&self, kid: &str) -> Result<ed25519_dalek::VerifyingKey, String> {
+    /// Looks up the verifying key for `kid`, refreshing the JWKS on a miss.
+    /// `Ok(None)` means the JWKS has no such key, `Err` that it couldn't be fetched.
+    pub async fn get_key(&self, kid: &str) -> Result<Option<ed25519_dalek::VerifyingKey>, String> {
         // Check cache first
         {
             let store = self.keystore.read().await;
             if let Some(key) = store.get(kid) {
-                return Ok(*key);
+                return Ok(Some(*key));
             }
         }
 
         // Fetch from Better Auth if missing
-        let resp = self
-            .http_client
-            .get(&self.jwks_url)
-            .send()
-            .await
-            .map_err(|_| "Failed to send JWKS request")?;
-
-        if !resp.status().is_success() {
-            return Err(format!("JWKS endpoint returned status: {}", resp.status()));
-        }
-
-        let jwks: JwksResponse = resp.json().await.map_err(|_| "Failed to parse JWKS")?;
+        let jwks = self.fetch_jwks().await;
+        metrics::JWKS_FETCHES
+            .with_label_values(&[if jwks.is_ok() { "success" } else { "error" }])
+            .inc();
+        let jwks = jwks?;
 
         let mut store = self.keystore.write().await;
         for key in jwks.keys {
@@ -101,14 +103,91 @@ impl AuthState {
             }
         }
 
-        store
-            .get(kid)
-            .copied()
-            .ok_or_else(|| format!("Key ID '{}' not found in JWKS", kid))
+        Ok(store.get(kid).copied())
+    }
+
+    async fn fetch_jwks(&self) -> Result<JwksResponse, String> {
+        let resp = self
+            .http_client
+            .get(&self.jwks_url)
+            .send()
+            .await
+            .map_err(|_| "Failed to send JWKS request")?;
+
+        if !resp.status().is_success() {
+            return Err(format!("JWKS endpoint returned status: {}", resp.status()));
+        }
+
+        resp.json()
+            .await
+            .map_err(|_| "Failed to parse JWKS".to_string())
     }
 }
 
-// 4. The Extractor (Middleware)
+// 4. Short lived cache of verified API keys, keyed by the public prefix.
+// Saves the key lookup on every sync; entries expire quickly so deleted keys stop working soon after.
+#[derive(Clone)]
+pub struct ApiKeyCache {
+    entries: Arc<RwLock<HashMap<String, CachedApiKey>>>,
+    ttl: Duration,
+}
+
+struct CachedApiKey {
+    key_id: String,
+    digest: Vec<u8>,
+    expires_at: Instant,
+}
+
+impl ApiKeyCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: Arc::new(RwLock::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    /// Returns the api_keys id if this exact key was verified recently
+    pub async fn get(&self, prefix: &str, digest: &[u8]) -> Option<String> {
+        let entries = self.entries.read().await;
+        let entry = entries.get(prefix)?;
+
+        if entry.expires_at <= Instant::now() {
+            return None;
+        }
+
+        // Compare in constant time, the prefix alone is not a secret
+        if bool::from(entry.digest.as_slice().ct_eq(digest)) {
+            Some(entry.key_id.clone())
+        } else {
+            None
+        }
+    }
+
+    pub async fn insert(&self, prefix: &str, key_id: &str, digest: Vec<u8>) {
+        let mut entries = self.entries.write().await;
+        let now = Instant::now();
+
+        // Drop anything stale while we hold the lock so the map can't grow forever
+        entries.retain(|_, entry| entry.expires_at > now);
+        entries.insert(
+            prefix.to_string(),
+            CachedApiKey {
+                key_id: key_id.to_string(),
+                digest,
+                expires_at: now + self.ttl,
+            },
+        );
+    }
+
+    pub async fn evict(&self, key_id: &str) {
+        self.entries
+            .write()
+            .await
+            .retain(|_, entry| entry.key_id != key_id);
+    }
+}
+
+// 5. The Extractor (Middleware)
 pub struct User(pub Claims);
 
 impl<S> FromRequestParts<S> for User
@@ -116,7 +195,7 @@ where
     S: Send + Sync,
     AuthState: FromRef<S>,
 {
-    type Rejection = (StatusCode, String);
+    type Rejection = ApiError;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let auth_state = AuthState::from_ref(state);
@@ -124,31 +203,33 @@ where
         let auth_header = parts
             .headers
             .get(AUTHORIZATION)
-            .ok_or((StatusCode::UNAUTHORIZED, "Missing Authorization".into()))?
+            .ok_or(ApiError::Unauthorized("Missing Authorization".into()))?
             .to_str()
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid header".into()))?;
+            .map_err(|_| ApiError::Unauthorized("Invalid header".into()))?;
 
         if !auth_header.starts_with("Bearer ") {
-            return Err((StatusCode::UNAUTHORIZED, "Invalid format".into()));
+            return Err(ApiError::Unauthorized("Invalid format".into()));
         }
 
         let token = &auth_header[7..];
         let header = decode_header(token)
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid token header".into()))?;
+            .map_err(|_| ApiError::Unauthorized("Invalid token header".into()))?;
 
         let kid = header
             .kid
-            .ok_or((StatusCode::UNAUTHORIZED, "Missing kid".into()))?;
+            .ok_or(ApiError::Unauthorized("Missing kid".into()))?;
 
+        // A kid we don't know is a forged or stale token, only a failed fetch is our problem
         let verifying_key = auth_state
             .get_key(&kid)
             .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+            .map_err(ApiError::Internal)?
+            .ok_or(ApiError::Unauthorized("Unknown kid".into()))?;
 
         // Parse JWT manually for Ed25519 verification
         let parts: Vec<&str> = token.split('.').collect();
         if parts.len() != 3 {
-            return Err((StatusCode::UNAUTHORIZED, "Invalid auth token format".into()));
+            return Err(ApiError::Unauthorized("Invalid auth token format".into()));
         }
 
         let header_b64 = parts[0];
@@ -156,44 +237,36 @@ where
         let signature_b64 = parts[2];
 
         // Decode signature
-        let signature_bytes = URL_SAFE_NO_PAD.decode(signature_b64).map_err(|_| {
-            (
-                StatusCode::UNAUTHORIZED,
-                "Failed to decode signature".into(),
-            )
-        })?;
+        let signature_bytes = URL_SAFE_NO_PAD
+            .decode(signature_b64)
+            .map_err(|_| ApiError::Unauthorized("Failed to decode signature".into()))?;
 
         if signature_bytes.len() != 64 {
-            return Err((StatusCode::UNAUTHORIZED, "Invalid signature length".into()));
+            return Err(ApiError::Unauthorized("Invalid signature length".into()));
         }
 
         let signature_array: [u8; 64] = signature_bytes[..64]
             .try_into()
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid signature format".into()))?;
+            .map_err(|_| ApiError::Unauthorized("Invalid signature format".into()))?;
         let signature = ed25519_dalek::Signature::from_bytes(&signature_array);
 
         // Verify signature
         let message = format!("{}.{}", header_b64, payload_b64);
         verifying_key
             .verify_strict(message.as_bytes(), &signature)
-            .map_err(|_| {
-                (
-                    StatusCode::UNAUTHORIZED,
-                    "Signature verification failed".into(),
-                )
-            })?;
+            .map_err(|_| ApiError::Unauthorized("Signature verification failed".into()))?;
 
         // Decode payload
         let payload_bytes = URL_SAFE_NO_PAD
             .decode(payload_b64)
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "Failed to decode payload".into()))?;
+            .map_err(|_| ApiError::Unauthorized("Failed to decode payload".into()))?;
 
         let payload: Value = serde_json::from_slice(&payload_bytes)
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "Failed to parse payload".into()))?;
+            .map_err(|_| ApiError::Unauthorized("Failed to parse payload".into()))?;
 
         // Convert to Claims
         let mut claims: Claims = serde_json::from_value(payload.clone())
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "Failed to parse claims".into()))?;
+            .map_err(|_| ApiError::Unauthorized("Failed to parse claims".into()))?;
 
         // Extract extra fields
         if let Ok(extra) = serde_json::from_value::<HashMap<String, Value>>(payload) {
@@ -203,7 +276,7 @@ where
         // Validate expiration
         let now = chrono::Utc::now().timestamp() as usize;
         if claims.exp < now {
-            return Err((StatusCode::UNAUTHORIZED, "Token has expired".into()));
+            return Err(ApiError::Unauthorized("Token has expired".into()));
         }
 
         Ok(User(claims))
@@ -229,3 +302,102 @@ pub fn generate_api_key() -> (String, String, String) {
 
     (full_key, public_id, secret)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_ID: &str = "0190c0de-0000-7000-8000-000000000000";
+
+    #[tokio::test]
+    async fn returns_the_key_for_the_same_digest() {
+        let cache = ApiKeyCache::new(Duration::from_secs(60));
+        cache.insert("prefix", KEY_ID, vec![1; 32]).await;
+
+        assert_eq!(cache.get("prefix", &[1; 32]).await.as_deref(), Some(KEY_ID));
+        assert_eq!(cache.get("other", &[1; 32]).await, None);
+    }
+
+    #[tokio::test]
+    async fn other_digest_misses() {
+        let cache = ApiKeyCache::new(Duration::from_secs(60));
+        cache.insert("prefix", KEY_ID, vec![1; 32]).await;
+
+        assert_eq!(cache.get("prefix", &[2; 32]).await, None);
+        assert_eq!(cache.get("prefix", &[1; 31]).await, None);
+        assert_eq!(cache.get("prefix", &[]).await, None);
+    }
+
+    #[tokio::test]
+    async fn expired_entry_misses() {
+        let cache = ApiKeyCache::new(Duration::ZERO);
+        cache.insert("prefix", KEY_ID, vec![1; 32]).await;
+
+        assert_eq!(cache.get("prefix", &[1; 32]).await, None);
+    }
+
+    #[tokio::test]
+    async fn evicted_key_misses() {
+        let cache = ApiKeyCache::new(Duration::from_secs(60));
+        cache.insert("prefix", KEY_ID, vec![1; 32]).await;
+        cache.insert("kept", "another key", vec![2; 32]).await;
+
+        cache.evict(KEY_ID).await;
+
+        assert_eq!(cache.get("prefix", &[1; 32]).await, None);
+        assert_eq!(
+            cache.get("kept", &[2; 32]).await.as_deref(),
+            Some("another key")
+        );
+    }
+
+    /// Serves a JWKS holding one Ed25519 key under `kid`, returns the base url
+    async fn serve_jwks(kid: &'static str) -> (String, ed25519_dalek::VerifyingKey) {
+        let key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]).verifying_key();
+        let jwks = serde_json::json!({
+            "keys": [{
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": URL_SAFE_NO_PAD.encode(key.as_bytes()),
+                "kid": kid,
+            }]
+        });
+        let app = axum::Router::new().route(
+            "/api/auth/jwks",
+            axum::routing::get(move || async move { axum::Json(jwks) }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (format!("http://{}", addr), key)
+    }
+
+    #[tokio::test]
+    async fn known_kid_returns_the_key() {
+        let (base_url, key) = serve_jwks("current").await;
+        let auth = AuthState::new(&base_url);
+
+        assert_eq!(auth.get_key("current").await, Ok(Some(key)));
+    }
+
+    #[tokio::test]
+    async fn unknown_kid_is_not_an_error() {
+        let (base_url, _) = serve_jwks("current").await;
+        let auth = AuthState::new(&base_url);
+
+        assert_eq!(auth.get_key("stale").await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn unreachable_jwks_is_an_error() {
+        // Bind and drop to get a port nothing listens on
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let auth = AuthState::new(&format!("http://{}", addr));
+
+        assert!(auth.get_key("current").await.is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+use ipnet::IpNet;
+use std::{
+    env,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use crate::lib::api_error::ApiError;
+
+/// Reverse proxies whose X-Forwarded-For / Forwarded headers we believe.
+/// Configured with TRUSTED_PROXIES, a comma separated list of CIDRs (e.g. "127.0.0.1/32,10.0.0.0/8").
+#[derive(Clone, Default)]
+pub struct TrustedProxies(Arc<Vec<IpNet>>);
+
+impl TrustedProxies {
+    pub fn from_env() -> Result<Self, String> {
+        let Ok(list) = env::var("TRUSTED_PROXIES") else {
+            return Ok(Self::default());
+        };
+
+        let networks = list
+            .split(',')
+            .map(str::trim)
+            .filter(|cidr| !cidr.is_empty())
+            .map(|cidr| {
+                // Accept bare addresses as single host networks
+                cidr.parse::<IpNet>()
+                    .or_else(|_| cidr.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| format!("Invalid CIDR in TRUSTED_PROXIES: {}", cidr))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self(Arc::new(networks)))
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(ip))
+    }
+
+    /// The address of the client behind any trusted proxies
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        // Anyone can send these headers, only listen when a trusted proxy set them
+        if !self.contains(&peer) {
+            return peer;
+        }
+
+        let forwarded_chain = forwarded_for(headers).or_else(|| x_forwarded_for(headers));
+        let Some(chain) = forwarded_chain else {
+            return peer;
+        };
+
+        // Each proxy appends the address it saw, so walk back from the closest one
+        // and stop at the first hop we don't trust
+        chain
+            .iter()
+            .rev()
+            .find(|ip| !self.contains(ip))
+            .or_else(|| chain.first())
+            .copied()
+            .unwrap_or(peer)
+    }
+}
+
+// X-Forwarded-For: client, proxy1, proxy2
+fn x_forwarded_for(headers: &HeaderMap) -> Option<Vec<IpAddr>> {
+    let ips: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(parse_node)
+        .collect();
+
+    (!ips.is_empty()).then_some(ips)
+}
+
+// Forwarded: for=192.0.2.60;proto=http, for="[2001:db8:cafe::17]:4711" (RFC 7239)
+fn forwarded_for(headers: &HeaderMap) -> Option<Vec<IpAddr>> {
+    let ips: Vec<IpAddr> = headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for").then_some(value)
+            })
+        })
+        .filter_map(parse_node)
+        .collect();
+
+    (!ips.is_empty()).then_some(ips)
+}
+
+/// Parse "1.2.3.4", "1.2.3.4:80", "\"[2001:db8::1]:80\"" or "2001:db8::1"
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    // Bracketed IPv6 without a port
+    node.strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .and_then(|ip| ip.parse().ok())
+}
+
+/// Extractor for the caller's address, honouring trusted proxy headers
+pub struct ClientIp(pub IpAddr);
+
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+    TrustedProxies: FromRef<S>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let trusted = TrustedProxies::from_ref(state);
+
+        let ConnectInfo(peer) = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .copied()
+            .ok_or_else(|| ApiError::Internal("Missing connection info".to_string()))?;
+
+        // Dual stack listeners report IPv4 callers as ::ffff:a.b.c.d
+        let ip = trusted.resolve(peer.ip().to_canonical(), &parts.headers);
+        Ok(ClientIp(ip.to_canonical()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn trusted(networks: &[&str]) -> TrustedProxies {
+        TrustedProxies(Arc::new(
+            networks.iter().map(|net| net.parse().unwrap()).collect(),
+        ))
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_headers_are_ignored() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+        let headers = headers(&[
+            ("x-forwarded-for", "198.51.100.7"),
+            ("forwarded", "for=198.51.100.8"),
+        ]);
+
+        assert_eq!(proxies.resolve(ip("192.0.2.1"), &headers), ip("192.0.2.1"));
+        assert_eq!(
+            TrustedProxies::default().resolve(ip("10.0.0.1"), &headers),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn walks_back_past_trusted_hops() {
+        let proxies = trusted(&["10.0.0.0/8", "192.0.2.0/24"]);
+        let headers = headers(&[
+            ("x-forwarded-for", "203.0.113.50, 198.51.100.7"),
+            ("x-forwarded-for", "192.0.2.9, 10.0.0.2"),
+        ]);
+
+        // 203.0.113.50 could be made up by the untrusted 198.51.100.7, which is the client
+        assert_eq!(
+            proxies.resolve(ip("10.0.0.1"), &headers),
+            ip("198.51.100.7")
+        );
+    }
+
+    #[test]
+    fn all_trusted_chain_takes_the_first_hop() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+        let headers = headers(&[("x-forwarded-for", "10.1.1.1, 10.0.0.2")]);
+
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("10.1.1.1"));
+    }
+
+    #[test]
+    fn missing_or_unparsable_headers_keep_the_peer() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+
+        assert_eq!(
+            proxies.resolve(ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+        assert_eq!(
+            proxies.resolve(
+                ip("10.0.0.1"),
+                &headers(&[("x-forwarded-for", "unknown, not-an-ip")])
+            ),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn forwarded_wins_over_x_forwarded_for() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+        let headers = headers(&[
+            ("x-forwarded-for", "198.51.100.7"),
+            (
+                "forwarded",
+                "for=192.0.2.60;proto=http;by=10.0.0.1, For=\"[2001:db8:cafe::17]:4711\"",
+            ),
+        ]);
+
+        assert_eq!(
+            proxies.resolve(ip("10.0.0.1"), &headers),
+            ip("2001:db8:cafe::17")
+        );
+    }
+
+    #[test]
+    fn forwarded_ipv6_forms() {
+        let headers = headers(&[(
+            "forwarded",
+            "for=\"[2001:db8::1]:4711\", for=\"[2001:db8::2]\";proto=https, for=unknown, for=192.0.2.60:8080",
+        )]);
+
+        assert_eq!(
+            forwarded_for(&headers),
+            Some(vec![ip("2001:db8::1"), ip("2001:db8::2"), ip("192.0.2.60")])
+        );
+    }
+
+    #[test]
+    fn parses_nodes() {
+        for (node, expected) in [
+            ("192.0.2.1", "192.0.2.1"),
+            (" 192.0.2.1:80 ", "192.0.2.1"),
+            ("2001:db8::1", "2001:db8::1"),
+            ("[2001:db8::1]", "2001:db8::1"),
+            ("\"[2001:db8::1]:443\"", "2001:db8::1"),
+        ] {
+            assert_eq!(parse_node(node), Some(ip(expected)), "{}", node);
+        }
+
+        for node in ["", "unknown", "_hidden", "[2001:db8::1", "192.0.2.1:port"] {
+            assert_eq!(parse_node(node), None, "{}", node);
+        }
+    }
+}
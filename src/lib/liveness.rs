@@ -0,0 +1,298 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, Pool},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    net::IpAddr,
+    time::Duration,
+};
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    db::schema::{api_keys, dns_record},
+    lib::{
+        outbox::{self, PayloadSpec, RecordOperation, StoredChanges},
+        providers::{DnsProvider, ProviderRegistry, RecordPayload},
+        utils::get_zone_provider,
+    },
+};
+
+// The client daemon syncs every 5 minutes
+const DEFAULT_SYNC_INTERVAL: Duration = Duration::from_secs(300);
+const DEFAULT_STALE_AFTER: u32 = 3;
+const DEFAULT_OFFLINE_AFTER: u32 = 12;
+
+/// Whether the client behind a record is still syncing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientStatus {
+    Online,
+    /// Missed a few syncs, possibly just a reboot or a flaky connection
+    Stale,
+    /// Gone for long enough that the record's fallback (if any) takes over
+    Offline,
+}
+
+impl ClientStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClientStatus::Online => "online",
+            ClientStatus::Stale => "stale",
+            ClientStatus::Offline => "offline",
+        }
+    }
+}
+
+/// How many missed sync intervals make a client stale or offline.
+/// Set with CLIENT_SYNC_INTERVAL_SECS, STALE_AFTER_INTERVALS and OFFLINE_AFTER_INTERVALS.
+#[derive(Debug, Clone, Copy)]
+pub struct LivenessConfig {
+    pub sync_interval: Duration,
+    pub stale_after: u32,
+    pub offline_after: u32,
+}
+
+impl LivenessConfig {
+    pub fn from_env() -> Result<Self, String> {
+        let sync_interval = env_number("CLIENT_SYNC_INTERVAL_SECS")?
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_SYNC_INTERVAL);
+        let stale_after = env_number("STALE_AFTER_INTERVALS")?
+            .map(|n| n as u32)
+            .unwrap_or(DEFAULT_STALE_AFTER);
+        let offline_after = env_number("OFFLINE_AFTER_INTERVALS")?
+            .map(|n| n as u32)
+            .unwrap_or(DEFAULT_OFFLINE_AFTER);
+
+        if sync_interval.is_zero() || stale_after == 0 || offline_after < stale_after {
+            return Err(
+                "CLIENT_SYNC_INTERVAL_SECS and STALE_AFTER_INTERVALS must be positive \
+                 and OFFLINE_AFTER_INTERVALS at least STALE_AFTER_INTERVALS"
+                    .to_string(),
+            );
+        }
+
+        Ok(Self {
+            sync_interval,
+            stale_after,
+            offline_after,
+        })
+    }
+
+    pub fn status(&self, last_synced_on: NaiveDateTime, now: NaiveDateTime) -> ClientStatus {
+        let silent = (now - last_synced_on).to_std().unwrap_or_default();
+
+        if silent > self.sync_interval * self.offline_after {
+            ClientStatus::Offline
+        } else if silent > self.sync_interval * self.stale_after {
+            ClientStatus::Stale
+        } else {
+            ClientStatus::Online
+        }
+    }
+}
+
+fn env_number(name: &str) -> Result<Option<u64>, String> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{} must be a whole number", name)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// The record type a fallback is served as: addresses keep their family, anything else is a CNAME
+pub fn fallback_record_type(fallback: &str) -> &'static str {
+    match fallback.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => "A",
+        Ok(IpAddr::V6(_)) => "AAAA",
+        Err(_) => "CNAME",
+    }
+}
+
+/// Records synced by a client: the one an API key controls and its A/AAAA sibling.
+/// Records without a client have nothing to go stale.
+pub fn client_managed_names(
+    conn: &mut MysqlConnection,
+    user_id: Option<&str>,
+) -> QueryResult<HashSet<(String, String, String)>> {
+    let mut query = dns_record::table
+        .inner_join(api_keys::table)
+        .select((
+            dns_record::user_id,
+            dns_record::zone_id,
+            dns_record::record_name,
+        ))
+        .into_boxed();
+
+    if let Some(user_id) = user_id {
+        query = query.filter(dns_record::user_id.eq(user_id.to_string()));
+    }
+
+    Ok(query
+        .load::<(String, String, String)>(conn)?
+        .into_iter()
+        .collect())
+}
+
+#[derive(Queryable)]
+struct WatchedRecord {
+    user_id: String,
+    id: String,
+    zone_id: String,
+    record_name: String,
+    ttl: i32,
+    proxied: bool,
+    last_synced_on: NaiveDateTime,
+    fallback_content: Option<String>,
+    fallback_active: bool,
+}
+
+/// Check client liveness once per sync interval and switch offline records to their fallback.
+/// The next successful sync puts the client's address back.
+///
+/// Syncs can't arrive while the server is down, so silence is only counted from when it
+/// started: after downtime clients get a full offline window to check in before any flip.
+pub fn spawn(
+    pool: Pool<ConnectionManager<MysqlConnection>>,
+    providers: ProviderRegistry,
+    config: LivenessConfig,
+) {
+    let up_since = chrono::Utc::now().naive_utc();
+
+    tokio::spawn(async move {
+        let start = tokio::time::Instant::now() + config.sync_interval;
+        let mut ticker = tokio::time::interval_at(start, config.sync_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        // Records already reported as offline, so each outage is only logged once
+        let mut offline = HashSet::new();
+
+        loop {
+            ticker.tick().await;
+
+            if let Err(e) = check_clients(&pool, &providers, &config, up_since, &mut offline).await
+            {
+                tracing::warn!("Stale client check failed: {}", e);
+            }
+        }
+    });
+}
+
+async fn check_clients(
+    pool: &Pool<ConnectionManager<MysqlConnection>>,
+    providers: &ProviderRegistry,
+    config: &LivenessConfig,
+    up_since: NaiveDateTime,
+    offline: &mut HashSet<String>,
+) -> Result<(), String> {
+    let conn = &mut pool.get().map_err(|e| e.to_string())?;
+    let managed = client_managed_names(conn, None).map_err(|e| e.to_string())?;
+
+    let records = dns_record::table
+        .filter(dns_record::orphaned_on.is_null())
+        .select((
+            dns_record::user_id,
+            dns_record::id,
+            dns_record::zone_id,
+            dns_record::record_name,
+            dns_record::ttl,
+            dns_record::proxied,
+            dns_record::last_synced_on,
+            dns_record::fallback_content,
+            dns_record::fallback_active,
+        ))
+        .load::<WatchedRecord>(conn)
+        .map_err(|e| e.to_string())?;
+
+    let now = chrono::Utc::now().naive_utc();
+    let mut zone_providers: HashMap<(String, String), Box<dyn DnsProvider>> = HashMap::new();
+    let mut still_offline = HashSet::new();
+
+    for record in records {
+        let group = (
+            record.user_id.clone(),
+            record.zone_id.clone(),
+            record.record_name.clone(),
+        );
+        let last_seen = record.last_synced_on.max(up_since);
+        if !managed.contains(&group) || config.status(last_seen, now) != ClientStatus::Offline {
+            continue;
+        }
+
+        if !offline.contains(&record.id) {
+            tracing::warn!(
+                "Client for {} has not synced since {}",
+                record.record_name,
+                record.last_synced_on
+            );
+        }
+        still_offline.insert(record.id.clone());
+
+        let Some(fallback) = record.fallback_content.as_deref() else {
+            continue;
+        };
+        if record.fallback_active {
+            continue;
+        }
+
+        let zone = (record.user_id.clone(), record.zone_id.clone());
+        if !zone_providers.contains_key(&zone) {
+            match get_zone_provider(conn, providers, &record.user_id, &record.zone_id) {
+                Ok(provider) => {
+                    zone_providers.insert(zone.clone(), provider);
+                }
+                Err(e) => {
+                    tracing::warn!("No provider for zone {}: {}", record.zone_id, e);
+                    continue;
+                }
+            }
+        }
+        let provider = &zone_providers[&zone];
+
+        let payload = RecordPayload {
+            record_type: fallback_record_type(fallback),
+            name: &record.record_name,
+            content: fallback,
+            ttl: record.ttl,
+            proxied: Some(record.proxied),
+        };
+
+        let operation = RecordOperation::Update {
+            record_id: record.id.clone(),
+            payload: PayloadSpec::new(&payload),
+            stored: StoredChanges {
+                fallback_active: Some(true),
+                ..Default::default()
+            },
+        };
+
+        match outbox::submit(
+            conn,
+            provider.as_ref(),
+            &record.user_id,
+            &record.zone_id,
+            operation,
+        )
+        .await
+        {
+            Ok(_) => tracing::info!(
+                "Switched {} to its fallback {}",
+                record.record_name,
+                fallback
+            ),
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to switch {} to its fallback: {}",
+                    record.record_name,
+                    e
+                )
+            }
+        }
+    }
+
+    *offline = still_offline;
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    core::Collector, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+    Opts, Registry, TextEncoder,
+};
+use r2d2::{
+    event::{CheckoutEvent, TimeoutEvent},
+    HandleEvent,
+};
+use std::time::Instant;
+
+// Our own registry, so only Drago's metrics end up on /metrics
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+// Provider APIs and the DB pool are usually fast, syncs behind a slow provider are not
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Sync attempts by outcome: updated, unchanged, auth_failure, no_host, bad_request,
+/// provider_error or internal_error
+pub static SYNC_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("drago_sync_requests_total", "Sync requests by outcome"),
+            &["outcome"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static PROVIDER_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "drago_provider_request_duration_seconds",
+                "DNS provider API call latency",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["provider", "operation"],
+        )
+        .unwrap(),
+    )
+});
+
+/// Failed provider calls. code is the provider's HTTP-like status, or the kind of failure
+/// (network, decode, config, unsupported) when there is none.
+pub static PROVIDER_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "drago_provider_errors_total",
+                "Failed DNS provider API calls",
+            ),
+            &["provider", "operation", "code"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static DB_POOL_WAIT: Lazy<Histogram> = Lazy::new(|| {
+    register(
+        Histogram::with_opts(
+            HistogramOpts::new(
+                "drago_db_pool_wait_seconds",
+                "Time spent waiting for a DB connection from the pool",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+        )
+        .unwrap(),
+    )
+});
+
+pub static DB_POOL_TIMEOUTS: Lazy<IntCounter> = Lazy::new(|| {
+    register(
+        IntCounter::new(
+            "drago_db_pool_timeouts_total",
+            "Pool checkouts that gave up waiting for a connection",
+        )
+        .unwrap(),
+    )
+});
+
+/// JWKS refreshes from the frontend by result (success or error)
+pub static JWKS_FETCHES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("drago_jwks_fetches_total", "JWKS fetches from the frontend"),
+            &["result"],
+        )
+        .unwrap(),
+    )
+});
+
+pub static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "drago_http_request_duration_seconds",
+                "HTTP request latency by route",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["method", "route", "status"],
+        )
+        .unwrap(),
+    )
+});
+
+fn register<M: Collector + Clone + 'static>(metric: M) -> M {
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metric registered twice");
+    metric
+}
+
+/// Register every metric up front so they show up on /metrics before their first event
+pub fn init() {
+    Lazy::force(&SYNC_REQUESTS);
+    Lazy::force(&PROVIDER_REQUEST_DURATION);
+    Lazy::force(&PROVIDER_ERRORS);
+    Lazy::force(&DB_POOL_WAIT);
+    Lazy::force(&DB_POOL_TIMEOUTS);
+    Lazy::force(&JWKS_FETCHES);
+    Lazy::force(&HTTP_REQUEST_DURATION);
+}
+
+/// Everything in the Prometheus text exposition format
+pub fn render() -> Result<String, String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .map_err(|e| e.to_string())?;
+
+    String::from_utf8(buffer).map_err(|e| e.to_string())
+}
+
+/// r2d2 event handler feeding the pool wait metrics
+#[derive(Debug)]
+pub struct PoolMetrics;
+
+impl HandleEvent for PoolMetrics {
+    fn handle_checkout(&self, event: CheckoutEvent) {
+        DB_POOL_WAIT.observe(event.duration().as_secs_f64());
+    }
+
+    fn handle_timeout(&self, _event: TimeoutEvent) {
+        DB_POOL_TIMEOUTS.inc();
+    }
+}
+
+/// Middleware timing every request. Labelled with the route template rather than the path
+/// so ids in the URL don't create a series each.
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .observe(start.elapsed().as_secs_f64());
+
+    response
+}
@@ -1,4 +1,16 @@
+pub mod api_error;
 pub mod auth;
+pub mod client_ip;
 pub mod encryption;
+pub mod liveness;
+pub mod metrics;
+pub mod openapi;
+pub mod outbox;
+pub mod ownership;
+pub mod providers;
+pub mod reconcile;
+pub mod retention;
 pub mod types;
 pub mod utils;
+pub mod webhooks;
+pub mod zones;
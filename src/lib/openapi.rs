@@ -0,0 +1,83 @@
+use utoipa::{
+    openapi::security::{Http, HttpAuthScheme, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use crate::lib::{api_error, types};
+
+/// The API as served at /openapi.json, built from the handlers' `#[utoipa::path]` attributes
+/// and the types in `types.rs`
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Drago",
+        description = "Dynamic DNS for the records in your own DNS provider accounts"
+    ),
+    paths(
+        crate::health,
+        crate::get_metrics,
+        crate::echo_ip,
+        crate::list_dns_records,
+        crate::refresh_dns_zones,
+        crate::list_provider_records,
+        crate::get_record_history,
+        crate::get_provider_operation,
+        crate::add_dns_record,
+        crate::update_dns_record,
+        crate::adopt_dns_record,
+        crate::delete_dns_record,
+        crate::get_dns_access_tokens,
+        crate::add_dns_access_token,
+        crate::delete_access_token,
+        crate::get_api_keys,
+        crate::add_api_key,
+        crate::delete_api_key,
+        crate::get_webhooks,
+        crate::add_webhook,
+        crate::delete_webhook,
+        crate::get_webhook_deliveries,
+        crate::sync_record,
+        crate::nic_update,
+    ),
+    components(schemas(api_error::ErrorBody, api_error::ErrorDetail, types::ManagedDnsRecord)),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "records", description = "DNS records managed by Drago"),
+        (name = "zones", description = "Zones seen by the user's DNS tokens"),
+        (name = "tokens", description = "DNS provider tokens"),
+        (name = "api_keys", description = "Keys clients sync a record with"),
+        (name = "webhooks", description = "Notifications about address changes"),
+        (name = "sync", description = "Endpoints clients and routers call"),
+        (name = "server", description = "Health, metrics and the caller's address"),
+    )
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        // Better Auth JWT of the signed in user
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::Http(
+                Http::builder()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        // dgo_<prefix>_<secret>, sent as a bearer token by the client
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+        // The API key as the password, how DynDNS2 routers authenticate
+        components.add_security_scheme(
+            "basic",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+        );
+    }
+}
@@ -0,0 +1,661 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, Pool},
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, time::Duration};
+use tokio::time::MissedTickBehavior;
+use uuid::Uuid;
+
+use crate::{
+    db::{
+        models::DnsRecordChanges,
+        schema::{dns_record, provider_operation},
+    },
+    lib::{
+        providers::{DnsProvider, ProviderError, ProviderRegistry, RecordPayload},
+        types::DnsRecord,
+        utils::get_zone_provider,
+    },
+};
+
+const MAX_ATTEMPTS: i32 = 5;
+// Doubled after every failed attempt: 30s, 1m, 2m, 4m
+const INITIAL_BACKOFF_SECS: i64 = 30;
+// How long a run may take before the operation counts as abandoned
+const LEASE_SECS: i64 = 120;
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+const BATCH_SIZE: i64 = 50;
+
+// Stored in provider_operation.status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+    /// An update that a newer update of the same record got ahead of
+    Superseded,
+}
+
+impl OperationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OperationStatus::Pending => "pending",
+            OperationStatus::Running => "running",
+            OperationStatus::Done => "done",
+            OperationStatus::Failed => "failed",
+            OperationStatus::Superseded => "superseded",
+        }
+    }
+}
+
+/// Owned copy of a RecordPayload, so it can be stored with the operation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PayloadSpec {
+    pub record_type: String,
+    pub name: String,
+    pub content: String,
+    pub ttl: i32,
+    pub proxied: Option<bool>,
+}
+
+impl PayloadSpec {
+    pub fn new(payload: &RecordPayload) -> Self {
+        Self {
+            record_type: payload.record_type.to_string(),
+            name: payload.name.to_string(),
+            content: payload.content.to_string(),
+            ttl: payload.ttl,
+            proxied: payload.proxied,
+        }
+    }
+
+    fn payload(&self) -> RecordPayload<'_> {
+        RecordPayload {
+            record_type: &self.record_type,
+            name: &self.name,
+            content: &self.content,
+            ttl: self.ttl,
+            proxied: self.proxied,
+        }
+    }
+}
+
+/// Row fields an update stores besides what the provider returns (id, name, ttl, proxied).
+/// They can differ from the payload, e.g. while a fallback is served.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StoredChanges {
+    pub record_type: Option<String>,
+    pub content: Option<String>,
+    pub fallback_active: Option<bool>,
+    pub drift_policy: Option<String>,
+    pub last_synced_on: Option<NaiveDateTime>,
+}
+
+/// A change to make at the provider and what to write to dns_record once it's made
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecordOperation {
+    Create {
+        payload: PayloadSpec,
+        drift_policy: String,
+        fallback_content: Option<String>,
+        last_synced_on: Option<NaiveDateTime>,
+    },
+    Update {
+        record_id: String,
+        payload: PayloadSpec,
+        stored: StoredChanges,
+    },
+    Delete {
+        record_id: String,
+    },
+}
+
+impl RecordOperation {
+    fn kind(&self) -> &'static str {
+        match self {
+            RecordOperation::Create { .. } => "create",
+            RecordOperation::Update { .. } => "update",
+            RecordOperation::Delete { .. } => "delete",
+        }
+    }
+
+    fn record_id(&self) -> Option<&str> {
+        match self {
+            RecordOperation::Create { .. } => None,
+            RecordOperation::Update { record_id, .. } | RecordOperation::Delete { record_id } => {
+                Some(record_id)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Completed {
+    /// The record as the provider now has it
+    Record(DnsRecord),
+    Deleted,
+    Superseded,
+}
+
+#[derive(Debug)]
+pub enum OutboxError {
+    /// The provider call failed. When `queued` the worker tries again later.
+    Provider {
+        operation_id: String,
+        error: ProviderError,
+        queued: bool,
+    },
+    /// The provider may have been changed but our tables weren't yet, the worker finishes the job
+    Db(String),
+}
+
+impl fmt::Display for OutboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutboxError::Provider { error, .. } => write!(f, "{}", error),
+            OutboxError::Db(msg) => write!(f, "Database error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for OutboxError {}
+
+#[derive(Queryable)]
+struct QueuedOperation {
+    id: String,
+    user_id: String,
+    zone_id: String,
+    operation: String,
+    attempts: i32,
+}
+
+/// Store the operation and run it straight away, so callers still get the provider's answer.
+/// If the server dies halfway, the worker picks the operation up again.
+pub async fn submit(
+    conn: &mut MysqlConnection,
+    provider: &dyn DnsProvider,
+    user_id: &str,
+    zone_id: &str,
+    operation: RecordOperation,
+) -> Result<Completed, OutboxError> {
+    let now = chrono::Utc::now().naive_utc();
+    let id = Uuid::now_v7().to_string();
+    let encoded = serde_json::to_string(&operation).map_err(|e| OutboxError::Db(e.to_string()))?;
+
+    // Inserted as already claimed by us, the worker leaves it alone until the lease runs out
+    diesel::insert_into(provider_operation::table)
+        .values((
+            provider_operation::id.eq(&id),
+            provider_operation::user_id.eq(user_id),
+            provider_operation::zone_id.eq(zone_id),
+            provider_operation::record_id.eq(operation.record_id()),
+            provider_operation::kind.eq(operation.kind()),
+            provider_operation::operation.eq(&encoded),
+            provider_operation::status.eq(OperationStatus::Running.as_str()),
+            provider_operation::attempts.eq(1),
+            provider_operation::next_attempt_on.eq(now),
+            provider_operation::locked_until.eq(now + chrono::Duration::seconds(LEASE_SECS)),
+        ))
+        .execute(conn)
+        .map_err(|e| OutboxError::Db(e.to_string()))?;
+
+    let queued = QueuedOperation {
+        id,
+        user_id: user_id.to_string(),
+        zone_id: zone_id.to_string(),
+        operation: encoded,
+        attempts: 1,
+    };
+
+    run(conn, provider, &queued, operation).await
+}
+
+/// Whether a change to the record is still waiting for, or being sent to, the provider
+pub fn has_queued(conn: &mut MysqlConnection, record_id: &str) -> QueryResult<bool> {
+    provider_operation::table
+        .filter(provider_operation::record_id.eq(record_id))
+        .filter(provider_operation::status.eq_any([
+            OperationStatus::Pending.as_str(),
+            OperationStatus::Running.as_str(),
+        ]))
+        .count()
+        .get_result::<i64>(conn)
+        .map(|queued| queued > 0)
+}
+
+/// Retry due operations and pick up abandoned ones every few seconds
+pub fn spawn(pool: Pool<ConnectionManager<MysqlConnection>>, providers: ProviderRegistry) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            ticker.tick().await;
+
+            if let Err(e) = run_due(&pool, &providers).await {
+                tracing::warn!("Provider operation worker failed: {}", e);
+            }
+        }
+    });
+}
+
+async fn run_due(
+    pool: &Pool<ConnectionManager<MysqlConnection>>,
+    providers: &ProviderRegistry,
+) -> Result<(), String> {
+    let conn = &mut pool.get().map_err(|e| e.to_string())?;
+    let now = chrono::Utc::now().naive_utc();
+
+    let due = provider_operation::table
+        .filter(
+            provider_operation::status
+                .eq(OperationStatus::Pending.as_str())
+                .and(provider_operation::next_attempt_on.le(now))
+                .or(provider_operation::status
+                    .eq(OperationStatus::Running.as_str())
+                    .and(provider_operation::locked_until.lt(now))),
+        )
+        .order(provider_operation::id.asc())
+        .limit(BATCH_SIZE)
+        .select(provider_operation::id)
+        .load::<String>(conn)
+        .map_err(|e| e.to_string())?;
+
+    for id in due {
+        let Some(queued) = claim(conn, &id).map_err(|e| e.to_string())? else {
+            continue;
+        };
+
+        // The last attempt was abandoned, or couldn't even be marked failed
+        if queued.attempts > MAX_ATTEMPTS {
+            give_up(conn, &queued.id, "Abandoned after the last attempt");
+            continue;
+        }
+
+        let operation = match serde_json::from_str::<RecordOperation>(&queued.operation) {
+            Ok(operation) => operation,
+            Err(e) => {
+                give_up(conn, &queued.id, &format!("Unreadable operation: {}", e));
+                continue;
+            }
+        };
+
+        let provider = match get_zone_provider(conn, providers, &queued.user_id, &queued.zone_id) {
+            Ok(provider) => provider,
+            Err(e) => {
+                give_up(conn, &queued.id, &e);
+                continue;
+            }
+        };
+
+        match run(conn, provider.as_ref(), &queued, operation).await {
+            Ok(_) => tracing::info!("Provider operation {} completed on retry", queued.id),
+            Err(e) => tracing::warn!("Provider operation {} failed again: {}", queued.id, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Take the operation for one run, None when someone else got to it first
+fn claim(conn: &mut MysqlConnection, id: &str) -> QueryResult<Option<QueuedOperation>> {
+    let now = chrono::Utc::now().naive_utc();
+
+    let claimed = diesel::update(
+        provider_operation::table
+            .filter(provider_operation::id.eq(id))
+            .filter(
+                provider_operation::status
+                    .eq(OperationStatus::Pending.as_str())
+                    .or(provider_operation::status
+                        .eq(OperationStatus::Running.as_str())
+                        .and(provider_operation::locked_until.lt(now))),
+            ),
+    )
+    .set((
+        provider_operation::status.eq(OperationStatus::Running.as_str()),
+        provider_operation::attempts.eq(provider_operation::attempts + 1),
+        provider_operation::locked_until.eq(now + chrono::Duration::seconds(LEASE_SECS)),
+    ))
+    .execute(conn)?;
+
+    if claimed == 0 {
+        return Ok(None);
+    }
+
+    provider_operation::table
+        .filter(provider_operation::id.eq(id))
+        .select((
+            provider_operation::id,
+            provider_operation::user_id,
+            provider_operation::zone_id,
+            provider_operation::operation,
+            provider_operation::attempts,
+        ))
+        .first::<QueuedOperation>(conn)
+        .optional()
+}
+
+async fn run(
+    conn: &mut MysqlConnection,
+    provider: &dyn DnsProvider,
+    queued: &QueuedOperation,
+    operation: RecordOperation,
+) -> Result<Completed, OutboxError> {
+    if is_superseded(conn, queued, &operation) {
+        finish(conn, &queued.id, OperationStatus::Superseded, None, None)
+            .map_err(|e| OutboxError::Db(e.to_string()))?;
+        return Ok(Completed::Superseded);
+    }
+
+    // Whether this run made a new record at the provider
+    let mut created = false;
+    let result = match &operation {
+        RecordOperation::Create { payload, .. } => {
+            create_once(provider, &queued.zone_id, payload, queued.attempts > 1)
+                .await
+                .map(|(record, new)| {
+                    created = new;
+                    record
+                })
+        }
+        RecordOperation::Update {
+            record_id, payload, ..
+        } => {
+            provider
+                .update_record(&queued.zone_id, record_id, &payload.payload())
+                .await
+        }
+        RecordOperation::Delete { record_id } => {
+            match provider.delete_record(&queued.zone_id, record_id).await {
+                // Gone already, most likely deleted by an earlier attempt
+                Ok(_) | Err(ProviderError::Api { status: 404, .. }) => {
+                    let committed = commit_delete(conn, queued, record_id);
+                    if let Err(e) = &committed {
+                        commit_failed(conn, provider, queued, None, e).await;
+                    }
+                    return committed;
+                }
+                Err(e) => Err(e),
+            }
+        }
+    };
+
+    match result {
+        Ok(record) => {
+            let record_id = record.id.clone();
+            let committed = commit_record(conn, queued, &operation, record);
+            if let Err(e) = &committed {
+                let undo = created.then_some(record_id.as_str());
+                commit_failed(conn, provider, queued, undo, e).await;
+            }
+            committed
+        }
+        Err(error) => {
+            let queued_again = retry_later(conn, queued, &error);
+            Err(OutboxError::Provider {
+                operation_id: queued.id.clone(),
+                error,
+                queued: queued_again,
+            })
+        }
+    }
+}
+
+/// Create the record, unless an earlier attempt already did and only failed to tell us.
+/// Says whether the record is new.
+async fn create_once(
+    provider: &dyn DnsProvider,
+    zone_id: &str,
+    payload: &PayloadSpec,
+    retrying: bool,
+) -> Result<(DnsRecord, bool), ProviderError> {
+    if retrying {
+        let existing = provider.list_records(zone_id).await?;
+        if let Some(record) = existing.into_iter().find(|record| {
+            record
+                .name
+                .eq_ignore_ascii_case(payload.name.trim_end_matches('.'))
+                && record.record_type == payload.record_type
+                && record.content == payload.content
+        }) {
+            return Ok((record, false));
+        }
+    }
+
+    let record = provider.create_record(zone_id, &payload.payload()).await?;
+    Ok((record, true))
+}
+
+/// The provider was changed but our tables weren't. The operation stays running and the worker
+/// runs it again once the lease is up, unless that was the last attempt: then it fails, and the
+/// record this run created (`undo`) is deleted so the provider isn't left with one we don't track.
+async fn commit_failed(
+    conn: &mut MysqlConnection,
+    provider: &dyn DnsProvider,
+    queued: &QueuedOperation,
+    undo: Option<&str>,
+    error: &OutboxError,
+) {
+    if queued.attempts < MAX_ATTEMPTS {
+        return;
+    }
+
+    if let Some(record_id) = undo {
+        if let Err(e) = provider.delete_record(&queued.zone_id, record_id).await {
+            tracing::warn!(
+                "Failed to delete record {} of failed operation {}: {}",
+                record_id,
+                queued.id,
+                e
+            );
+        }
+    }
+    give_up(conn, &queued.id, &error.to_string());
+}
+
+// A newer update of the record already went through, this one would only undo it
+fn is_superseded(
+    conn: &mut MysqlConnection,
+    queued: &QueuedOperation,
+    operation: &RecordOperation,
+) -> bool {
+    let RecordOperation::Update { record_id, .. } = operation else {
+        return false;
+    };
+
+    provider_operation::table
+        .filter(provider_operation::record_id.eq(record_id))
+        .filter(provider_operation::kind.eq("update"))
+        .filter(provider_operation::status.eq(OperationStatus::Done.as_str()))
+        // v7 ids sort by creation time
+        .filter(provider_operation::id.gt(&queued.id))
+        .count()
+        .get_result::<i64>(conn)
+        .is_ok_and(|newer| newer > 0)
+}
+
+/// Write the provider's answer to dns_record and close the operation, all or nothing
+fn commit_record(
+    conn: &mut MysqlConnection,
+    queued: &QueuedOperation,
+    operation: &RecordOperation,
+    record: DnsRecord,
+) -> Result<Completed, OutboxError> {
+    let response = serde_json::to_string(&record).ok();
+
+    conn.transaction(|conn| {
+        match operation {
+            RecordOperation::Create {
+                drift_policy,
+                fallback_content,
+                last_synced_on,
+                ..
+            } => {
+                diesel::insert_into(dns_record::table)
+                    .values((
+                        dns_record::id.eq(&record.id),
+                        dns_record::user_id.eq(&queued.user_id),
+                        dns_record::record_name.eq(&record.name),
+                        dns_record::zone_id.eq(&queued.zone_id),
+                        dns_record::content.eq(&record.content),
+                        dns_record::ttl.eq(record.ttl),
+                        dns_record::record_type.eq(&record.record_type),
+                        dns_record::proxied.eq(record.proxied),
+                        dns_record::drift_policy.eq(drift_policy),
+                        dns_record::fallback_content.eq(fallback_content),
+                        dns_record::last_synced_on
+                            .eq(last_synced_on.unwrap_or_else(|| chrono::Utc::now().naive_utc())),
+                    ))
+                    .execute(conn)?;
+            }
+            RecordOperation::Update {
+                record_id, stored, ..
+            } => {
+                diesel::update(
+                    dns_record::table
+                        .filter(dns_record::id.eq(record_id))
+                        .filter(dns_record::user_id.eq(&queued.user_id)),
+                )
+                .set(DnsRecordChanges {
+                    id: &record.id,
+                    record_name: &record.name,
+                    ttl: record.ttl,
+                    proxied: record.proxied,
+                    record_type: stored.record_type.as_deref(),
+                    content: stored.content.as_deref(),
+                    fallback_active: stored.fallback_active,
+                    drift_policy: stored.drift_policy.as_deref(),
+                    last_synced_on: stored.last_synced_on,
+                })
+                .execute(conn)?;
+            }
+            RecordOperation::Delete { .. } => {
+                unreachable!("deletes are committed by commit_delete")
+            }
+        }
+
+        finish(
+            conn,
+            &queued.id,
+            OperationStatus::Done,
+            Some(&record.id),
+            response.as_deref(),
+        )
+    })
+    .map_err(|e| OutboxError::Db(e.to_string()))?;
+
+    Ok(Completed::Record(record))
+}
+
+fn commit_delete(
+    conn: &mut MysqlConnection,
+    queued: &QueuedOperation,
+    record_id: &str,
+) -> Result<Completed, OutboxError> {
+    conn.transaction(|conn| {
+        diesel::delete(
+            dns_record::table
+                .filter(dns_record::id.eq(record_id))
+                .filter(dns_record::user_id.eq(&queued.user_id)),
+        )
+        .execute(conn)?;
+
+        finish(
+            conn,
+            &queued.id,
+            OperationStatus::Done,
+            Some(record_id),
+            None,
+        )
+    })
+    .map_err(|e| OutboxError::Db(e.to_string()))?;
+
+    Ok(Completed::Deleted)
+}
+
+fn finish(
+    conn: &mut MysqlConnection,
+    id: &str,
+    status: OperationStatus,
+    record_id: Option<&str>,
+    result: Option<&str>,
+) -> QueryResult<()> {
+    diesel::update(provider_operation::table.filter(provider_operation::id.eq(id)))
+        .set((
+            provider_operation::status.eq(status.as_str()),
+            provider_operation::result.eq(result),
+            provider_operation::locked_until.eq(None::<NaiveDateTime>),
+            provider_operation::completed_on.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn)?;
+
+    // A create only learns its record id from the provider, an update may change it
+    if let Some(record_id) = record_id {
+        diesel::update(provider_operation::table.filter(provider_operation::id.eq(id)))
+            .set(provider_operation::record_id.eq(record_id))
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
+
+/// Put a failed operation back in the queue with some backoff, or give up on it.
+/// Returns whether it will be tried again.
+fn retry_later(
+    conn: &mut MysqlConnection,
+    queued: &QueuedOperation,
+    error: &ProviderError,
+) -> bool {
+    if !is_retryable(error) || queued.attempts >= MAX_ATTEMPTS {
+        give_up(conn, &queued.id, &error.to_string());
+        return false;
+    }
+
+    let backoff = INITIAL_BACKOFF_SECS << (queued.attempts - 1).clamp(0, 16);
+    let result =
+        diesel::update(provider_operation::table.filter(provider_operation::id.eq(&queued.id)))
+            .set((
+                provider_operation::status.eq(OperationStatus::Pending.as_str()),
+                provider_operation::last_error.eq(error.to_string()),
+                provider_operation::locked_until.eq(None::<NaiveDateTime>),
+                provider_operation::next_attempt_on
+                    .eq(chrono::Utc::now().naive_utc() + chrono::Duration::seconds(backoff)),
+            ))
+            .execute(conn);
+
+    if let Err(e) = result {
+        // Still marked running, the worker retries once the lease runs out
+        tracing::warn!("Failed to requeue provider operation {}: {}", queued.id, e);
+    }
+
+    true
+}
+
+fn give_up(conn: &mut MysqlConnection, id: &str, error: &str) {
+    let result = diesel::update(provider_operation::table.filter(provider_operation::id.eq(id)))
+        .set((
+            provider_operation::status.eq(OperationStatus::Failed.as_str()),
+            provider_operation::last_error.eq(error),
+            provider_operation::locked_until.eq(None::<NaiveDateTime>),
+            provider_operation::completed_on.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn);
+
+    if let Err(e) = result {
+        tracing::warn!("Failed to mark provider operation {} failed: {}", id, e);
+    }
+}
+
+// Worth another try: the provider was unreachable, overloaded, or we lost its answer
+fn is_retryable(error: &ProviderError) -> bool {
+    match error {
+        ProviderError::Network(_) | ProviderError::Decode(_) => true,
+        ProviderError::Api { status, .. } => *status >= 500 || *status == 429 || *status == 408,
+        ProviderError::Unsupported(_) | ProviderError::Config(_) => false,
+    }
+}
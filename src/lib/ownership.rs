@@ -0,0 +1,147 @@
+use diesel::prelude::*;
+
+use crate::{
+    db::schema::{api_keys, dns_record, dns_token, dns_zone, webhook},
+    lib::api_error::{ApiError, Resource},
+};
+
+// A resource of another account is reported exactly like a missing one,
+// so ids can't be probed across accounts
+fn found<T>(resource: Resource, result: QueryResult<Option<T>>) -> Result<T, ApiError> {
+    match result {
+        Ok(Some(value)) => Ok(value),
+        Ok(None) => Err(ApiError::NotFound(resource)),
+        Err(err) => Err(ApiError::Database(err)),
+    }
+}
+
+/// One of the user's zones, returns its name
+pub fn owned_zone(
+    conn: &mut MysqlConnection,
+    user_id: &str,
+    zone_id: &str,
+) -> Result<String, ApiError> {
+    found(
+        Resource::Zone,
+        dns_zone::table
+            .filter(dns_zone::id.eq(zone_id))
+            .filter(dns_zone::user_id.eq(user_id))
+            .select(dns_zone::zone_name)
+            .first::<String>(conn)
+            .optional(),
+    )
+}
+
+/// One of the user's records, returns the id of its zone
+pub fn owned_record(
+    conn: &mut MysqlConnection,
+    user_id: &str,
+    record_id: &str,
+) -> Result<String, ApiError> {
+    found(
+        Resource::Record,
+        dns_record::table
+            .filter(dns_record::id.eq(record_id))
+            .filter(dns_record::user_id.eq(user_id))
+            .select(dns_record::zone_id)
+            .first::<String>(conn)
+            .optional(),
+    )
+}
+
+/// One of the user's records, which also has to be in the given zone, itself one of the user's
+pub fn owned_zone_record(
+    conn: &mut MysqlConnection,
+    user_id: &str,
+    zone_id: &str,
+    record_id: &str,
+) -> Result<(), ApiError> {
+    in_zone(&owned_record(conn, user_id, record_id)?, zone_id)?;
+    owned_zone(conn, user_id, zone_id)?;
+
+    Ok(())
+}
+
+// A record named with another zone than its own is as good as missing
+fn in_zone(record_zone_id: &str, zone_id: &str) -> Result<(), ApiError> {
+    if record_zone_id != zone_id {
+        return Err(ApiError::NotFound(Resource::Record));
+    }
+
+    Ok(())
+}
+
+/// One of the user's DNS tokens
+pub fn owned_token(
+    conn: &mut MysqlConnection,
+    user_id: &str,
+    token_id: &str,
+) -> Result<String, ApiError> {
+    found(
+        Resource::DnsToken,
+        dns_token::table
+            .filter(dns_token::id.eq(token_id))
+            .filter(dns_token::user_id.eq(user_id))
+            .select(dns_token::id)
+            .first::<String>(conn)
+            .optional(),
+    )
+}
+
+/// One of the user's API keys
+pub fn owned_api_key(
+    conn: &mut MysqlConnection,
+    user_id: &str,
+    key_id: &str,
+) -> Result<String, ApiError> {
+    found(
+        Resource::ApiKey,
+        api_keys::table
+            .filter(api_keys::id.eq(key_id))
+            .filter(api_keys::user_id.eq(user_id))
+            .select(api_keys::id)
+            .first::<String>(conn)
+            .optional(),
+    )
+}
+
+/// One of the user's webhooks
+pub fn owned_webhook(
+    conn: &mut MysqlConnection,
+    user_id: &str,
+    webhook_id: &str,
+) -> Result<String, ApiError> {
+    found(
+        Resource::Webhook,
+        webhook::table
+            .filter(webhook::id.eq(webhook_id))
+            .filter(webhook::user_id.eq(user_id))
+            .select(webhook::id)
+            .first::<String>(conn)
+            .optional(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_in_its_own_zone() {
+        assert!(in_zone("zone-a", "zone-a").is_ok());
+    }
+
+    #[test]
+    fn record_in_another_zone() {
+        for zone_id in ["zone-b", "", "zone-a ", "ZONE-A"] {
+            assert!(
+                matches!(
+                    in_zone("zone-a", zone_id),
+                    Err(ApiError::NotFound(Resource::Record))
+                ),
+                "{:?}",
+                zone_id
+            );
+        }
+    }
+}
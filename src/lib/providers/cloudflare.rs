@@ -0,0 +1,220 @@
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{DnsProvider, ProviderError, RecordPayload};
+use crate::lib::types::{DnsRecord, Zone};
+
+const API_BASE: &str = "https://api.cloudflare.com/client/v4";
+// The most Cloudflare hands out per page of zones
+const ZONES_PER_PAGE: usize = 50;
+const RECORDS_PER_PAGE: usize = 100;
+
+pub struct Cloudflare {
+    token: String,
+    client: Client,
+}
+
+// Every Cloudflare v4 response is wrapped in this envelope
+#[derive(Debug, Deserialize)]
+struct CloudflareResponse<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<CloudflareMessage>,
+    result: Option<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CloudflareMessage {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeletedDnsRecord {
+    id: String,
+}
+
+#[derive(Serialize, Debug)]
+struct DnsRecordPayload<'a> {
+    r#type: &'a str,
+    name: &'a str,
+    content: &'a str,
+    ttl: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxied: Option<bool>,
+}
+
+impl<'a> From<&RecordPayload<'a>> for DnsRecordPayload<'a> {
+    fn from(record: &RecordPayload<'a>) -> Self {
+        Self {
+            r#type: record.record_type,
+            name: record.name,
+            content: record.content,
+            ttl: record.ttl,
+            proxied: record.proxied,
+        }
+    }
+}
+
+impl Cloudflare {
+    pub fn new(token: &str, client: Client) -> Self {
+        Self {
+            // Defensive trim to remove newlines or accidental quotes from pasted tokens
+            token: token
+                .trim()
+                .trim_matches('"')
+                .trim_matches('\'')
+                .to_string(),
+            client,
+        }
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, ProviderError> {
+        let resp = request
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .map_err(|e| ProviderError::Network(e.to_string()))?;
+
+        let status = resp.status().as_u16();
+        let body: CloudflareResponse<T> = resp
+            .json()
+            .await
+            .map_err(|e| ProviderError::Decode(e.to_string()))?;
+
+        if !body.success {
+            let message = body
+                .errors
+                .iter()
+                .map(|e| format!("[{}] {}", e.code, e.message))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            return Err(ProviderError::Api { status, message });
+        }
+
+        body.result
+            .ok_or_else(|| ProviderError::Decode("missing result".to_string()))
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Cloudflare {
+    async fn verify_credentials(&self) -> Result<(), ProviderError> {
+        // Listing a single zone is enough, success: true means the token is valid
+        let request = self
+            .client
+            .get(format!("{}/zones", API_BASE))
+            .query(&[("per_page", "1")]);
+
+        self.send::<Vec<Zone>>(request).await.map(|_| ())
+    }
+
+    async fn list_zones(&self) -> Result<Vec<Zone>, ProviderError> {
+        // Zones come in pages, a partial list would make the rest look deleted
+        let mut zones = Vec::new();
+
+        for page in 1.. {
+            let request = self
+                .client
+                .get(format!("{}/zones", API_BASE))
+                .header("Content-Type", "application/json")
+                .query(&[
+                    ("page", page.to_string()),
+                    ("per_page", ZONES_PER_PAGE.to_string()),
+                ]);
+
+            let batch: Vec<Zone> = self.send(request).await?;
+            let last = batch.len() < ZONES_PER_PAGE;
+            zones.extend(batch);
+
+            if last {
+                break;
+            }
+        }
+
+        Ok(zones)
+    }
+
+    async fn list_records(&self, zone_id: &str) -> Result<Vec<DnsRecord>, ProviderError> {
+        let mut records = Vec::new();
+
+        for page in 1.. {
+            let request = self
+                .client
+                .get(format!("{}/zones/{}/dns_records", API_BASE, zone_id))
+                .query(&[
+                    ("page", page.to_string()),
+                    ("per_page", RECORDS_PER_PAGE.to_string()),
+                ]);
+
+            let batch: Vec<DnsRecord> = self.send(request).await?;
+            let last = batch.len() < RECORDS_PER_PAGE;
+            records.extend(batch);
+
+            if last {
+                break;
+            }
+        }
+
+        Ok(records)
+    }
+
+    async fn get_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+    ) -> Result<Option<DnsRecord>, ProviderError> {
+        let request = self.client.get(format!(
+            "{}/zones/{}/dns_records/{}",
+            API_BASE, zone_id, record_id
+        ));
+
+        match self.send(request).await {
+            Ok(record) => Ok(Some(record)),
+            Err(ProviderError::Api { status: 404, .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn create_record(
+        &self,
+        zone_id: &str,
+        record: &RecordPayload<'_>,
+    ) -> Result<DnsRecord, ProviderError> {
+        let request = self
+            .client
+            .post(format!("{}/zones/{}/dns_records", API_BASE, zone_id))
+            .json(&DnsRecordPayload::from(record));
+
+        self.send(request).await
+    }
+
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+        record: &RecordPayload<'_>,
+    ) -> Result<DnsRecord, ProviderError> {
+        let request = self
+            .client
+            .put(format!(
+                "{}/zones/{}/dns_records/{}",
+                API_BASE, zone_id, record_id
+            ))
+            .json(&DnsRecordPayload::from(record));
+
+        self.send(request).await
+    }
+
+    async fn delete_record(&self, zone_id: &str, record_id: &str) -> Result<String, ProviderError> {
+        let request = self.client.delete(format!(
+            "{}/zones/{}/dns_records/{}",
+            API_BASE, zone_id, record_id
+        ));
+
+        let deleted: DeletedDnsRecord = self.send(request).await?;
+        Ok(deleted.id)
+    }
+}
@@ -0,0 +1,256 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use std::{collections::HashMap, fmt, future::Future, sync::Arc};
+
+use crate::lib::{
+    metrics,
+    types::{DnsRecord, Zone},
+};
+
+pub mod cloudflare;
+pub mod rfc2136;
+
+// Provider used for tokens that were stored before the provider column existed
+pub const DEFAULT_PROVIDER: &str = "cloudflare";
+
+#[derive(Debug)]
+pub enum ProviderError {
+    /// The request never got a usable response (DNS, TLS, timeout...)
+    Network(String),
+    /// The provider answered but rejected the request
+    Api { status: u16, message: String },
+    /// The provider answered with something we couldn't parse
+    Decode(String),
+    /// No provider registered under the requested name
+    Unsupported(String),
+    /// The stored credentials or settings can't be used to build the provider
+    Config(String),
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderError::Network(msg) => write!(f, "DNS provider unreachable: {}", msg),
+            ProviderError::Api { status, message } => {
+                write!(f, "DNS provider error ({}): {}", status, message)
+            }
+            ProviderError::Decode(msg) => write!(f, "Unexpected DNS provider response: {}", msg),
+            ProviderError::Unsupported(name) => write!(f, "Unsupported DNS provider '{}'", name),
+            ProviderError::Config(msg) => write!(f, "Invalid DNS provider settings: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+impl ProviderError {
+    /// Short label for metrics: the provider's status code, or what kind of failure it was
+    pub fn code(&self) -> String {
+        match self {
+            ProviderError::Network(_) => "network".to_string(),
+            ProviderError::Api { status, .. } => status.to_string(),
+            ProviderError::Decode(_) => "decode".to_string(),
+            ProviderError::Unsupported(_) => "unsupported".to_string(),
+            ProviderError::Config(_) => "config".to_string(),
+        }
+    }
+}
+
+/// The record fields a provider needs to create or overwrite a record
+#[derive(Debug, Clone, Copy)]
+pub struct RecordPayload<'a> {
+    pub record_type: &'a str,
+    pub name: &'a str,
+    pub content: &'a str,
+    pub ttl: i32,
+    // None leaves the provider's default in place
+    pub proxied: Option<bool>,
+}
+
+/// Everything Drago needs from a DNS host. One instance is built per dns_token row.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// Cheap call used to check a token before we store it
+    async fn verify_credentials(&self) -> Result<(), ProviderError>;
+
+    async fn list_zones(&self) -> Result<Vec<Zone>, ProviderError>;
+
+    /// Every record in the zone, whether Drago manages it or not
+    async fn list_records(&self, zone_id: &str) -> Result<Vec<DnsRecord>, ProviderError>;
+
+    /// The record as the provider currently serves it, None when it no longer exists
+    async fn get_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+    ) -> Result<Option<DnsRecord>, ProviderError>;
+
+    async fn create_record(
+        &self,
+        zone_id: &str,
+        record: &RecordPayload<'_>,
+    ) -> Result<DnsRecord, ProviderError>;
+
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+        record: &RecordPayload<'_>,
+    ) -> Result<DnsRecord, ProviderError>;
+
+    /// Returns the id of the deleted record
+    async fn delete_record(&self, zone_id: &str, record_id: &str) -> Result<String, ProviderError>;
+}
+
+/// Wraps every built provider to record call latency and errors per provider and operation
+struct Instrumented {
+    name: String,
+    inner: Box<dyn DnsProvider>,
+}
+
+impl Instrumented {
+    async fn observe<T>(
+        &self,
+        operation: &str,
+        call: impl Future<Output = Result<T, ProviderError>>,
+    ) -> Result<T, ProviderError> {
+        let timer = metrics::PROVIDER_REQUEST_DURATION
+            .with_label_values(&[&self.name, operation])
+            .start_timer();
+        let result = call.await;
+        timer.observe_duration();
+
+        if let Err(e) = &result {
+            metrics::PROVIDER_ERRORS
+                .with_label_values(&[&self.name, operation, &e.code()])
+                .inc();
+        }
+
+        result
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Instrumented {
+    async fn verify_credentials(&self) -> Result<(), ProviderError> {
+        self.observe("verify_credentials", self.inner.verify_credentials())
+            .await
+    }
+
+    async fn list_zones(&self) -> Result<Vec<Zone>, ProviderError> {
+        self.observe("list_zones", self.inner.list_zones()).await
+    }
+
+    async fn list_records(&self, zone_id: &str) -> Result<Vec<DnsRecord>, ProviderError> {
+        self.observe("list_records", self.inner.list_records(zone_id))
+            .await
+    }
+
+    async fn get_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+    ) -> Result<Option<DnsRecord>, ProviderError> {
+        self.observe("get_record", self.inner.get_record(zone_id, record_id))
+            .await
+    }
+
+    async fn create_record(
+        &self,
+        zone_id: &str,
+        record: &RecordPayload<'_>,
+    ) -> Result<DnsRecord, ProviderError> {
+        self.observe("create_record", self.inner.create_record(zone_id, record))
+            .await
+    }
+
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+        record: &RecordPayload<'_>,
+    ) -> Result<DnsRecord, ProviderError> {
+        self.observe(
+            "update_record",
+            self.inner.update_record(zone_id, record_id, record),
+        )
+        .await
+    }
+
+    async fn delete_record(&self, zone_id: &str, record_id: &str) -> Result<String, ProviderError> {
+        self.observe(
+            "delete_record",
+            self.inner.delete_record(zone_id, record_id),
+        )
+        .await
+    }
+}
+
+// Takes the id and the decrypted secret of a dns_token row
+type ProviderBuilder =
+    Arc<dyn Fn(&str, &str, &Client) -> Result<Box<dyn DnsProvider>, ProviderError> + Send + Sync>;
+
+/// Maps the provider name stored on a dns_token row to a constructor.
+/// Shared through AppState so every handler builds providers the same way.
+#[derive(Clone)]
+pub struct ProviderRegistry {
+    http_client: Client,
+    builders: HashMap<String, ProviderBuilder>,
+}
+
+impl ProviderRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self {
+            http_client: Client::new(),
+            builders: HashMap::new(),
+        };
+
+        registry.register(DEFAULT_PROVIDER, |_, secret, client| {
+            Ok(Box::new(cloudflare::Cloudflare::new(
+                secret,
+                client.clone(),
+            )))
+        });
+        registry.register(rfc2136::PROVIDER_NAME, |token_id, secret, _| {
+            Ok(Box::new(rfc2136::Rfc2136::from_secret(token_id, secret)?))
+        });
+
+        registry
+    }
+
+    /// Add (or replace) a provider. Also how a fake provider gets swapped in.
+    pub fn register<F>(&mut self, name: &str, builder: F)
+    where
+        F: Fn(&str, &str, &Client) -> Result<Box<dyn DnsProvider>, ProviderError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.builders.insert(name.to_string(), Arc::new(builder));
+    }
+
+    /// Build a provider from the provider name, id and decrypted secret of a dns_token row
+    pub fn build(
+        &self,
+        name: &str,
+        token_id: &str,
+        secret: &str,
+    ) -> Result<Box<dyn DnsProvider>, ProviderError> {
+        let builder = self
+            .builders
+            .get(name)
+            .ok_or_else(|| ProviderError::Unsupported(name.to_string()))?;
+
+        let inner = builder(token_id, secret, &self.http_client)?;
+        Ok(Box::new(Instrumented {
+            name: name.to_string(),
+            inner,
+        }))
+    }
+}
+
+impl Default for ProviderRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,973 @@
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hickory_proto::{
+    op::{Message, MessageType, OpCode, Query, ResponseCode, UpdateMessage},
+    rr::{
+        dnssec::{rdata::tsig::TsigAlgorithm, tsig::TSigner},
+        rdata::{A, AAAA, CNAME, NULL, TXT},
+        DNSClass, Name, RData, Record, RecordType,
+    },
+};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{lookup_host, TcpStream, UdpSocket},
+};
+
+use super::{DnsProvider, ProviderError, RecordPayload};
+use crate::lib::types::{DnsRecord, Zone};
+
+pub const PROVIDER_NAME: &str = "rfc2136";
+
+// Allowed clock skew between us and the name server, in seconds
+const TSIG_FUDGE: u16 = 300;
+const UPDATE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_RESPONSE_LEN: usize = 4096;
+
+/// Settings stored (encrypted) as the token of an rfc2136 dns_token row, e.g.
+/// {"server":"ns1.example.com:53","key_name":"drago","key_secret":"<base64>","zones":["example.com"]}
+#[derive(Debug, Deserialize)]
+struct Rfc2136Settings {
+    server: String,
+    key_name: String,
+    // Base64 secret, as found in a BIND/Knot key file
+    key_secret: String,
+    // Name servers can't enumerate their zones for us, so they are listed up front
+    zones: Vec<String>,
+}
+
+/// Applies record changes with RFC 2136 DNS UPDATE messages signed with TSIG (HMAC-SHA256)
+pub struct Rfc2136 {
+    // Prefixes every zone and record id, see `scoped`
+    token_id: String,
+    server: String,
+    signer: TSigner,
+    zones: Vec<Name>,
+}
+
+impl Rfc2136 {
+    pub fn from_secret(token_id: &str, secret: &str) -> Result<Self, ProviderError> {
+        let settings: Rfc2136Settings =
+            serde_json::from_str(secret).map_err(|e| ProviderError::Config(e.to_string()))?;
+
+        let key = STANDARD
+            .decode(settings.key_secret.trim())
+            .map_err(|_| ProviderError::Config("key_secret is not valid base64".to_string()))?;
+
+        let key_name = parse_name(&settings.key_name)?;
+        let signer = TSigner::new(key, TsigAlgorithm::HmacSha256, key_name, TSIG_FUDGE)
+            .map_err(|e| ProviderError::Config(e.to_string()))?;
+
+        let zones = settings
+            .zones
+            .iter()
+            .map(|zone| parse_name(zone))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if zones.is_empty() {
+            return Err(ProviderError::Config(
+                "at least one zone is required".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            token_id: token_id.to_string(),
+            server: settings.server,
+            signer,
+            zones,
+        })
+    }
+
+    /// Zones and records have no provider side ids, so they are identified by the zone name or
+    /// the record's name and type. Those are only unique per name server, and accounts may well
+    /// manage the same names, so the ids are prefixed with the token's id: `<token id>:<name>`.
+    fn scoped(&self, id: &str) -> String {
+        format!("{}:{}", self.token_id, id)
+    }
+
+    // The name part of an id this token handed out
+    fn unscoped<'a>(&self, id: &'a str) -> Result<&'a str, ProviderError> {
+        id.strip_prefix(self.token_id.as_str())
+            .and_then(|id| id.strip_prefix(':'))
+            .ok_or_else(|| ProviderError::Api {
+                status: 404,
+                message: format!("'{}' does not belong to this key", id),
+            })
+    }
+
+    fn record_id(&self, fqdn: &str, record_type: &str) -> String {
+        self.scoped(&format!("{}/{}", fqdn, record_type))
+    }
+
+    fn split_record_id<'a>(&self, record_id: &'a str) -> Result<(&'a str, &'a str), ProviderError> {
+        self.unscoped(record_id)?.rsplit_once('/').ok_or_else(|| {
+            ProviderError::Config(format!("'{}' is not an rfc2136 record id", record_id))
+        })
+    }
+
+    fn zone(&self, zone_id: &str) -> Result<&Name, ProviderError> {
+        let wanted = parse_name(self.unscoped(zone_id)?)?;
+        self.zones
+            .iter()
+            .find(|zone| **zone == wanted)
+            .ok_or_else(|| ProviderError::Api {
+                status: 404,
+                message: format!("zone '{}' is not configured for this key", zone_id),
+            })
+    }
+
+    async fn server_addr(&self) -> Result<SocketAddr, ProviderError> {
+        // Default to the standard DNS port when none is given
+        if let Ok(ip) = self.server.parse::<IpAddr>() {
+            return Ok(SocketAddr::new(ip, 53));
+        }
+
+        let host = if self.server.contains(':') {
+            self.server.clone()
+        } else {
+            format!("{}:53", self.server)
+        };
+
+        lookup_host(host)
+            .await
+            .map_err(|e| ProviderError::Network(e.to_string()))?
+            .next()
+            .ok_or_else(|| ProviderError::Network(format!("could not resolve {}", self.server)))
+    }
+
+    /// Sign and send a message, returning the verified response
+    async fn exchange(&self, mut message: Message) -> Result<Message, ProviderError> {
+        let now = chrono::Utc::now().timestamp() as u32;
+        let mut verifier = message
+            .finalize(&self.signer, now)
+            .map_err(|e| ProviderError::Config(e.to_string()))?;
+
+        let request = message
+            .to_vec()
+            .map_err(|e| ProviderError::Config(e.to_string()))?;
+
+        let server = self.server_addr().await?;
+        let bind_addr = if server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+
+        let socket = UdpSocket::bind(bind_addr)
+            .await
+            .map_err(|e| ProviderError::Network(e.to_string()))?;
+        socket
+            .connect(server)
+            .await
+            .map_err(|e| ProviderError::Network(e.to_string()))?;
+        socket
+            .send(&request)
+            .await
+            .map_err(|e| ProviderError::Network(e.to_string()))?;
+
+        let mut buf = vec![0u8; MAX_RESPONSE_LEN];
+        let len = loop {
+            let len = tokio::time::timeout(UPDATE_TIMEOUT, socket.recv(&mut buf))
+                .await
+                .map_err(|_| ProviderError::Network("name server timed out".to_string()))?
+                .map_err(|e| ProviderError::Network(e.to_string()))?;
+
+            // Ignore stray datagrams that don't answer our message
+            if len >= 2 && u16::from_be_bytes([buf[0], buf[1]]) == message.id() {
+                break len;
+            }
+        };
+
+        let response =
+            Message::from_vec(&buf[..len]).map_err(|e| ProviderError::Decode(e.to_string()))?;
+
+        // An unsigned error (e.g. BADKEY) can't be verified, report the rcode as is.
+        // NXDOMAIN is a normal answer to a lookup, callers decide what it means.
+        if !matches!(
+            response.response_code(),
+            ResponseCode::NoError | ResponseCode::NXDomain
+        ) {
+            return Err(rcode_error(response.response_code()));
+        }
+
+        if let Some(verify) = verifier.as_mut() {
+            verify(&buf[..len]).map_err(|e| ProviderError::Decode(e.to_string()))?;
+        }
+
+        Ok(response)
+    }
+
+    /// Transfer the whole zone (AXFR). Zone transfers need TCP, and the server has to sign
+    /// every message of the answer with our key.
+    async fn transfer(&self, zone: &Name) -> Result<Vec<Record>, ProviderError> {
+        let mut message = Message::new();
+        message
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(false);
+        message.add_query(Query::query(zone.clone(), RecordType::AXFR));
+
+        let now = chrono::Utc::now().timestamp() as u32;
+        let mut verifier = message
+            .finalize(&self.signer, now)
+            .map_err(|e| ProviderError::Config(e.to_string()))?;
+
+        let request = message
+            .to_vec()
+            .map_err(|e| ProviderError::Config(e.to_string()))?;
+
+        let network = |e: std::io::Error| ProviderError::Network(e.to_string());
+        let timed_out = |_| ProviderError::Network("name server timed out".to_string());
+
+        let server = self.server_addr().await?;
+        let mut stream = tokio::time::timeout(UPDATE_TIMEOUT, TcpStream::connect(server))
+            .await
+            .map_err(timed_out)?
+            .map_err(network)?;
+
+        // Over TCP every message is prefixed with its length
+        stream
+            .write_all(&(request.len() as u16).to_be_bytes())
+            .await
+            .map_err(network)?;
+        stream.write_all(&request).await.map_err(network)?;
+
+        // The transfer starts and ends with the zone's SOA
+        let mut soa_seen = 0;
+        let mut records = Vec::new();
+
+        loop {
+            let mut len = [0u8; 2];
+            tokio::time::timeout(UPDATE_TIMEOUT, stream.read_exact(&mut len))
+                .await
+                .map_err(timed_out)?
+                .map_err(network)?;
+
+            let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+            tokio::time::timeout(UPDATE_TIMEOUT, stream.read_exact(&mut buf))
+                .await
+                .map_err(timed_out)?
+                .map_err(network)?;
+
+            let response =
+                Message::from_vec(&buf).map_err(|e| ProviderError::Decode(e.to_string()))?;
+            if response.id() != message.id() {
+                return Err(ProviderError::Decode(
+                    "zone transfer answered another message".to_string(),
+                ));
+            }
+            if response.response_code() != ResponseCode::NoError {
+                return Err(rcode_error(response.response_code()));
+            }
+            if let Some(verify) = verifier.as_mut() {
+                verify(&buf).map_err(|e| ProviderError::Decode(e.to_string()))?;
+            }
+
+            for answer in response.answers() {
+                if answer.record_type() == RecordType::SOA {
+                    soa_seen += 1;
+                    if soa_seen == 2 {
+                        return Ok(records);
+                    }
+                } else {
+                    records.push(answer.clone());
+                }
+            }
+        }
+    }
+
+    /// Sign, send and check a single UPDATE message
+    async fn send_update(&self, message: Message) -> Result<(), ProviderError> {
+        let response = self.exchange(message).await?;
+
+        if response.response_code() != ResponseCode::NoError {
+            return Err(rcode_error(response.response_code()));
+        }
+
+        Ok(())
+    }
+
+    fn update_message(zone: &Name) -> Message {
+        // For updates the query section names the zone
+        let mut zone_query = Query::new();
+        zone_query
+            .set_name(zone.clone())
+            .set_query_class(DNSClass::IN)
+            .set_query_type(RecordType::SOA);
+
+        let mut message = Message::new();
+        message
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Update)
+            .set_recursion_desired(false);
+        message.add_zone(zone_query);
+
+        message
+    }
+
+    /// Replace the whole RRset for the record's name and type with a single record.
+    /// `previous` is the name and type an updated record had, its RRset is deleted in the same
+    /// message when the update changes either, so a rename doesn't leave the old one behind.
+    async fn replace_rrset(
+        &self,
+        zone_id: &str,
+        record: &RecordPayload<'_>,
+        previous: Option<(Name, RecordType)>,
+    ) -> Result<DnsRecord, ProviderError> {
+        let zone = self.zone(zone_id)?;
+        let name = record_name(record.name, zone)?;
+        let record_type = parse_type(record.record_type)?;
+        let rdata = parse_rdata(record_type, record.content)?;
+
+        let mut message = Self::update_message(zone);
+        if let Some((previous_name, previous_type)) =
+            previous.filter(|(previous_name, previous_type)| {
+                *previous_name != name || *previous_type != record_type
+            })
+        {
+            message.add_update(delete_rrset_record(previous_name, previous_type));
+        }
+        // A CNAME (e.g. an offline fallback) can't live next to the record we are writing
+        if record_type != RecordType::CNAME {
+            message.add_update(delete_rrset_record(name.clone(), RecordType::CNAME));
+        }
+        message.add_update(delete_rrset_record(name.clone(), record_type));
+        message.add_update(Record::from_rdata(name.clone(), record.ttl as u32, rdata));
+
+        self.send_update(message).await?;
+
+        let fqdn = display_name(&name);
+        Ok(DnsRecord {
+            id: self.record_id(&fqdn, record.record_type),
+            name: fqdn,
+            record_type: record.record_type.to_string(),
+            content: record.content.to_string(),
+            ttl: record.ttl,
+            // Plain DNS has no proxying
+            proxied: false,
+        })
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Rfc2136 {
+    async fn verify_credentials(&self) -> Result<(), ProviderError> {
+        // An update whose only prerequisite is "the zone apex exists" changes nothing,
+        // but the server still has to accept our key to evaluate it
+        for zone in &self.zones {
+            let mut message = Self::update_message(zone);
+            let mut prerequisite = Record::with(zone.clone(), RecordType::ANY, 0);
+            prerequisite.set_dns_class(DNSClass::ANY);
+            message.add_pre_requisite(prerequisite);
+
+            self.send_update(message).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn list_zones(&self) -> Result<Vec<Zone>, ProviderError> {
+        Ok(self
+            .zones
+            .iter()
+            .map(|zone| {
+                let name = display_name(zone);
+                Zone {
+                    id: self.scoped(&name),
+                    name,
+                    status: "active".to_string(),
+                    name_servers: Vec::new(),
+                }
+            })
+            .collect())
+    }
+
+    async fn list_records(&self, zone_id: &str) -> Result<Vec<DnsRecord>, ProviderError> {
+        let zone = self.zone(zone_id)?;
+        let mut seen = HashSet::new();
+
+        Ok(self
+            .transfer(zone)
+            .await?
+            .iter()
+            // Only the types we can write, one record per RRset like everywhere else
+            .filter(|record| parse_type(&record.record_type().to_string()).is_ok())
+            .filter_map(|record| {
+                let name = display_name(record.name());
+                let record_type = record.record_type().to_string();
+                let id = self.record_id(&name, &record_type);
+
+                if !seen.insert(id.clone()) {
+                    return None;
+                }
+
+                Some(DnsRecord {
+                    id,
+                    name,
+                    content: record_content(record)?,
+                    record_type,
+                    ttl: record.ttl() as i32,
+                    proxied: false,
+                })
+            })
+            .collect())
+    }
+
+    async fn get_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+    ) -> Result<Option<DnsRecord>, ProviderError> {
+        let zone = self.zone(zone_id)?;
+        let (name, type_name) = self.split_record_id(record_id)?;
+        let name = record_name(name, zone)?;
+        let record_type = parse_type(type_name)?;
+
+        let mut message = Message::new();
+        message
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(false);
+        message.add_query(Query::query(name.clone(), record_type));
+
+        let response = self.exchange(message).await?;
+
+        // Only the first record of the RRset, Drago always writes a single one
+        let Some(answer) = response
+            .answers()
+            .iter()
+            .find(|answer| answer.record_type() == record_type)
+        else {
+            return Ok(None);
+        };
+
+        let content = record_content(answer)
+            .ok_or_else(|| ProviderError::Decode(format!("unexpected {} data", record_type)))?;
+
+        let fqdn = display_name(&name);
+        Ok(Some(DnsRecord {
+            id: record_id.to_string(),
+            name: fqdn,
+            record_type: record_type.to_string(),
+            content,
+            ttl: answer.ttl() as i32,
+            proxied: false,
+        }))
+    }
+
+    async fn create_record(
+        &self,
+        zone_id: &str,
+        record: &RecordPayload<'_>,
+    ) -> Result<DnsRecord, ProviderError> {
+        self.replace_rrset(zone_id, record, None).await
+    }
+
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        record_id: &str,
+        record: &RecordPayload<'_>,
+    ) -> Result<DnsRecord, ProviderError> {
+        let zone = self.zone(zone_id)?;
+        let (previous_name, previous_type) = self.split_record_id(record_id)?;
+        let previous = (
+            record_name(previous_name, zone)?,
+            parse_type(previous_type)?,
+        );
+
+        self.replace_rrset(zone_id, record, Some(previous)).await
+    }
+
+    async fn delete_record(&self, zone_id: &str, record_id: &str) -> Result<String, ProviderError> {
+        let zone = self.zone(zone_id)?;
+        let (name, record_type) = self.split_record_id(record_id)?;
+
+        let name = record_name(name, zone)?;
+        let mut message = Self::update_message(zone);
+        message.add_update(delete_rrset_record(name, parse_type(record_type)?));
+
+        self.send_update(message).await?;
+        Ok(record_id.to_string())
+    }
+}
+
+fn parse_name(name: &str) -> Result<Name, ProviderError> {
+    let mut name =
+        Name::from_ascii(name).map_err(|e| ProviderError::Config(format!("{}: {}", name, e)))?;
+    name.set_fqdn(true);
+    Ok(name)
+}
+
+/// Accepts both "home" and "home.example.com" for a record in example.com
+fn record_name(name: &str, zone: &Name) -> Result<Name, ProviderError> {
+    let parsed = parse_name(name)?;
+    if zone.zone_of(&parsed) {
+        return Ok(parsed);
+    }
+
+    let mut relative =
+        Name::from_ascii(name).map_err(|e| ProviderError::Config(format!("{}: {}", name, e)))?;
+    relative.set_fqdn(false);
+    relative
+        .append_domain(zone)
+        .map_err(|e| ProviderError::Config(e.to_string()))
+}
+
+fn display_name(name: &Name) -> String {
+    name.to_ascii().trim_end_matches('.').to_string()
+}
+
+// Record data the way Drago stores it as content, None for types we don't manage
+fn record_content(record: &Record) -> Option<String> {
+    match record.data()? {
+        RData::A(ip) => Some(ip.to_string()),
+        RData::AAAA(ip) => Some(ip.to_string()),
+        RData::CNAME(target) => Some(display_name(target)),
+        RData::TXT(txt) => Some(
+            txt.txt_data()
+                .iter()
+                .map(|part| String::from_utf8_lossy(part))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn parse_type(record_type: &str) -> Result<RecordType, ProviderError> {
+    match record_type.to_ascii_uppercase().as_str() {
+        "A" => Ok(RecordType::A),
+        "AAAA" => Ok(RecordType::AAAA),
+        "CNAME" => Ok(RecordType::CNAME),
+        "TXT" => Ok(RecordType::TXT),
+        other => Err(ProviderError::Config(format!(
+            "record type {} is not supported over rfc2136",
+            other
+        ))),
+    }
+}
+
+fn parse_rdata(record_type: RecordType, content: &str) -> Result<RData, ProviderError> {
+    let invalid =
+        |_| ProviderError::Config(format!("'{}' is not a valid {}", content, record_type));
+
+    match record_type {
+        RecordType::A => Ok(RData::A(A(content.parse().map_err(invalid)?))),
+        RecordType::AAAA => Ok(RData::AAAA(AAAA(content.parse().map_err(invalid)?))),
+        RecordType::CNAME => Ok(RData::CNAME(CNAME(parse_name(content)?))),
+        RecordType::TXT => Ok(RData::TXT(TXT::new(vec![content.to_string()]))),
+        other => Err(ProviderError::Config(format!(
+            "record type {} is not supported over rfc2136",
+            other
+        ))),
+    }
+}
+
+/// RFC 2136 2.5.2: class ANY, TTL 0 and empty rdata deletes the whole RRset
+fn delete_rrset_record(name: Name, record_type: RecordType) -> Record {
+    let mut record = Record::from_rdata(name, 0, RData::NULL(NULL::new()));
+    record.set_record_type(record_type);
+    record.set_dns_class(DNSClass::ANY);
+    record
+}
+
+fn rcode_error(code: ResponseCode) -> ProviderError {
+    ProviderError::Api {
+        status: rcode_status(code),
+        message: format!("name server answered {}", code),
+    }
+}
+
+// Rough HTTP equivalents so callers can treat every provider's errors the same way
+fn rcode_status(code: ResponseCode) -> u16 {
+    match code {
+        ResponseCode::NotAuth | ResponseCode::BADKEY | ResponseCode::BADSIG => 401,
+        ResponseCode::Refused => 403,
+        ResponseCode::NotZone | ResponseCode::NXDomain => 404,
+        _ => 502,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::dnssec::rdata::tsig::{make_tsig_record, message_tbs, TSIG};
+    use tokio::sync::mpsc;
+
+    const KEY: [u8; 32] = [7; 32];
+    const TOKEN: &str = "0190b6c4-token-a";
+
+    fn signer(key: &[u8]) -> TSigner {
+        TSigner::new(
+            key.to_vec(),
+            TsigAlgorithm::HmacSha256,
+            parse_name("drago").unwrap(),
+            TSIG_FUDGE,
+        )
+        .unwrap()
+    }
+
+    fn rfc2136(token_id: &str, server: SocketAddr, key: &[u8]) -> Rfc2136 {
+        let settings = serde_json::json!({
+            "server": server.to_string(),
+            "key_name": "drago",
+            "key_secret": STANDARD.encode(key),
+            "zones": ["example.com"],
+        });
+        Rfc2136::from_secret(token_id, &settings.to_string()).unwrap()
+    }
+
+    /// A name server on a local port that checks each message's TSIG and answers `rcode`,
+    /// signed like a real server would. Verified messages are passed on for the test to inspect.
+    async fn name_server(rcode: ResponseCode) -> (SocketAddr, mpsc::UnboundedReceiver<Message>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let signer = signer(&KEY);
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; MAX_RESPONSE_LEN];
+            while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(request.op_code());
+
+                match signer.verify_message_byte(None, &buf[..len], true) {
+                    Ok((request_mac, _, _)) => {
+                        response.set_response_code(rcode);
+                        let now = chrono::Utc::now().timestamp() as u64;
+                        let pre_tsig = TSIG::new(
+                            TsigAlgorithm::HmacSha256,
+                            now,
+                            TSIG_FUDGE,
+                            Vec::new(),
+                            response.id(),
+                            0,
+                            Vec::new(),
+                        );
+                        let tbs = message_tbs(
+                            Some(&request_mac),
+                            &response,
+                            &pre_tsig,
+                            signer.signer_name(),
+                        )
+                        .unwrap();
+                        let mac = signer.sign(&tbs).unwrap();
+                        response.add_tsig(make_tsig_record(
+                            signer.signer_name().clone(),
+                            pre_tsig.set_mac(mac),
+                        ));
+                        let _ = tx.send(request);
+                    }
+                    // Unsigned, like BIND answers a key it doesn't know
+                    Err(_) => {
+                        response.set_response_code(ResponseCode::NotAuth);
+                    }
+                }
+
+                let _ = socket.send_to(&response.to_vec().unwrap(), from).await;
+            }
+        });
+
+        (addr, rx)
+    }
+
+    // The update section as (name, type, class, content)
+    fn updates(message: &Message) -> Vec<(String, RecordType, DNSClass, Option<String>)> {
+        message
+            .updates()
+            .iter()
+            .map(|record| {
+                (
+                    display_name(record.name()),
+                    record.record_type(),
+                    record.dns_class(),
+                    record_content(record),
+                )
+            })
+            .collect()
+    }
+
+    fn payload<'a>(name: &'a str, record_type: &'a str, content: &'a str) -> RecordPayload<'a> {
+        RecordPayload {
+            record_type,
+            name,
+            content,
+            ttl: 300,
+            proxied: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn ids_are_scoped_to_the_token() {
+        let (server, _) = name_server(ResponseCode::NoError).await;
+        let provider = rfc2136(TOKEN, server, &KEY);
+
+        let zones = provider.list_zones().await.unwrap();
+        assert_eq!(zones[0].id, format!("{}:example.com", TOKEN));
+        assert_eq!(zones[0].name, "example.com");
+
+        let record = provider
+            .create_record(&zones[0].id, &payload("home", "A", "192.0.2.1"))
+            .await
+            .unwrap();
+        assert_eq!(record.id, format!("{}:home.example.com/A", TOKEN));
+        assert_eq!(record.name, "home.example.com");
+
+        // Another account with the same zone gets other ids
+        let other = rfc2136("0190b6c4-token-b", server, &KEY);
+        assert_ne!(other.list_zones().await.unwrap()[0].id, zones[0].id);
+    }
+
+    #[tokio::test]
+    async fn signed_update_replaces_the_rrset() {
+        let (server, mut received) = name_server(ResponseCode::NoError).await;
+        let provider = rfc2136(TOKEN, server, &KEY);
+        let zone_id = format!("{}:example.com", TOKEN);
+
+        provider
+            .create_record(&zone_id, &payload("home.example.com", "A", "192.0.2.1"))
+            .await
+            .unwrap();
+
+        let update = received.recv().await.unwrap();
+        assert_eq!(update.op_code(), OpCode::Update);
+        assert_eq!(display_name(update.queries()[0].name()), "example.com");
+        assert_eq!(
+            updates(&update),
+            vec![
+                (
+                    "home.example.com".to_string(),
+                    RecordType::CNAME,
+                    DNSClass::ANY,
+                    None
+                ),
+                (
+                    "home.example.com".to_string(),
+                    RecordType::A,
+                    DNSClass::ANY,
+                    None
+                ),
+                (
+                    "home.example.com".to_string(),
+                    RecordType::A,
+                    DNSClass::IN,
+                    Some("192.0.2.1".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ids_of_other_tokens_are_refused() {
+        let (server, mut received) = name_server(ResponseCode::NoError).await;
+        let provider = rfc2136(TOKEN, server, &KEY);
+        let zone_id = format!("{}:example.com", TOKEN);
+
+        for record_id in [
+            "0190b6c4-token-b:home.example.com/A",
+            // Ids from before they were scoped
+            "home.example.com/A",
+        ] {
+            let result = provider.delete_record(&zone_id, record_id).await;
+            assert!(
+                matches!(result, Err(ProviderError::Api { status: 404, .. })),
+                "{:?}",
+                result
+            );
+        }
+
+        let other_zone = "0190b6c4-token-b:example.com";
+        let result = provider
+            .create_record(other_zone, &payload("home", "A", "192.0.2.1"))
+            .await;
+        assert!(matches!(
+            result,
+            Err(ProviderError::Api { status: 404, .. })
+        ));
+
+        // Only an id of our own reaches the name server
+        provider
+            .delete_record(&zone_id, &format!("{}:home.example.com/A", TOKEN))
+            .await
+            .unwrap();
+        let update = received.recv().await.unwrap();
+        assert_eq!(
+            updates(&update),
+            vec![(
+                "home.example.com".to_string(),
+                RecordType::A,
+                DNSClass::ANY,
+                None
+            )]
+        );
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn renames_delete_the_old_rrset() {
+        let (server, mut received) = name_server(ResponseCode::NoError).await;
+        let provider = rfc2136(TOKEN, server, &KEY);
+        let zone_id = format!("{}:example.com", TOKEN);
+        let record_id = format!("{}:home.example.com/A", TOKEN);
+
+        let record = provider
+            .update_record(&zone_id, &record_id, &payload("office", "A", "192.0.2.1"))
+            .await
+            .unwrap();
+        assert_eq!(record.id, format!("{}:office.example.com/A", TOKEN));
+
+        let update = received.recv().await.unwrap();
+        assert_eq!(
+            updates(&update),
+            vec![
+                (
+                    "home.example.com".to_string(),
+                    RecordType::A,
+                    DNSClass::ANY,
+                    None
+                ),
+                (
+                    "office.example.com".to_string(),
+                    RecordType::CNAME,
+                    DNSClass::ANY,
+                    None
+                ),
+                (
+                    "office.example.com".to_string(),
+                    RecordType::A,
+                    DNSClass::ANY,
+                    None
+                ),
+                (
+                    "office.example.com".to_string(),
+                    RecordType::A,
+                    DNSClass::IN,
+                    Some("192.0.2.1".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn type_changes_delete_the_old_rrset() {
+        let (server, mut received) = name_server(ResponseCode::NoError).await;
+        let provider = rfc2136(TOKEN, server, &KEY);
+        let zone_id = format!("{}:example.com", TOKEN);
+        let record_id = format!("{}:home.example.com/A", TOKEN);
+
+        provider
+            .update_record(
+                &zone_id,
+                &record_id,
+                &payload("home", "CNAME", "backup.example.net"),
+            )
+            .await
+            .unwrap();
+
+        let update = received.recv().await.unwrap();
+        assert_eq!(
+            updates(&update),
+            vec![
+                (
+                    "home.example.com".to_string(),
+                    RecordType::A,
+                    DNSClass::ANY,
+                    None
+                ),
+                (
+                    "home.example.com".to_string(),
+                    RecordType::CNAME,
+                    DNSClass::ANY,
+                    None
+                ),
+                (
+                    "home.example.com".to_string(),
+                    RecordType::CNAME,
+                    DNSClass::IN,
+                    Some("backup.example.net".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn content_changes_keep_the_name() {
+        let (server, mut received) = name_server(ResponseCode::NoError).await;
+        let provider = rfc2136(TOKEN, server, &KEY);
+        let zone_id = format!("{}:example.com", TOKEN);
+        let record_id = format!("{}:home.example.com/A", TOKEN);
+
+        provider
+            .update_record(&zone_id, &record_id, &payload("HOME", "A", "192.0.2.2"))
+            .await
+            .unwrap();
+
+        // Names compare without case, so there's no separate delete at home.example.com
+        let update = received.recv().await.unwrap();
+        assert_eq!(
+            updates(&update),
+            vec![
+                (
+                    "HOME.example.com".to_string(),
+                    RecordType::CNAME,
+                    DNSClass::ANY,
+                    None
+                ),
+                (
+                    "HOME.example.com".to_string(),
+                    RecordType::A,
+                    DNSClass::ANY,
+                    None
+                ),
+                (
+                    "HOME.example.com".to_string(),
+                    RecordType::A,
+                    DNSClass::IN,
+                    Some("192.0.2.2".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_key_is_rejected() {
+        let (server, mut received) = name_server(ResponseCode::NoError).await;
+        let provider = rfc2136(TOKEN, server, &[8; 32]);
+
+        let result = provider.verify_credentials().await;
+        assert!(matches!(
+            result,
+            Err(ProviderError::Api { status: 401, .. })
+        ));
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_reported() {
+        let (server, _) = name_server(ResponseCode::Refused).await;
+        let provider = rfc2136(TOKEN, server, &KEY);
+
+        let result = provider
+            .create_record(
+                &format!("{}:example.com", TOKEN),
+                &payload("home", "A", "192.0.2.1"),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(ProviderError::Api { status: 403, .. })
+        ));
+    }
+}
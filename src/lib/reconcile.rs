@@ -0,0 +1,280 @@
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, Pool},
+};
+use std::{collections::BTreeMap, net::IpAddr, time::Duration};
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    db::schema::dns_record,
+    lib::{
+        outbox::{self, PayloadSpec, RecordOperation, StoredChanges},
+        providers::{DnsProvider, ProviderRegistry, RecordPayload},
+        types::DnsRecord,
+        utils::get_zone_provider,
+    },
+};
+
+/// What the reconciler does when the provider's copy of a record differs from ours
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriftPolicy {
+    /// Never check the record
+    Ignore,
+    /// Report the drift but leave the provider alone
+    Log,
+    /// Push our copy back to the provider
+    Repair,
+}
+
+pub const DEFAULT_DRIFT_POLICY: DriftPolicy = DriftPolicy::Log;
+
+impl DriftPolicy {
+    pub fn parse(policy: &str) -> Option<Self> {
+        match policy {
+            "ignore" => Some(DriftPolicy::Ignore),
+            "log" => Some(DriftPolicy::Log),
+            "repair" => Some(DriftPolicy::Repair),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DriftPolicy::Ignore => "ignore",
+            DriftPolicy::Log => "log",
+            DriftPolicy::Repair => "repair",
+        }
+    }
+}
+
+// Stored in dns_record.reconcile_status
+enum ReconcileStatus {
+    InSync,
+    Drifted,
+    Repaired,
+    Missing,
+    Error,
+}
+
+impl ReconcileStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ReconcileStatus::InSync => "in_sync",
+            ReconcileStatus::Drifted => "drifted",
+            ReconcileStatus::Repaired => "repaired",
+            ReconcileStatus::Missing => "missing",
+            ReconcileStatus::Error => "error",
+        }
+    }
+}
+
+#[derive(Queryable)]
+struct ManagedRecord {
+    user_id: String,
+    id: String,
+    zone_id: String,
+    record_name: String,
+    record_type: String,
+    content: String,
+    ttl: i32,
+    proxied: bool,
+    drift_policy: String,
+}
+
+/// Check every managed record against its provider every `interval`
+pub fn spawn(
+    pool: Pool<ConnectionManager<MysqlConnection>>,
+    providers: ProviderRegistry,
+    interval: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        // The first tick fires straight away, give the server a full interval to settle
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            if let Err(e) = reconcile_all(&pool, &providers).await {
+                tracing::warn!("Drift reconciliation failed: {}", e);
+            }
+        }
+    });
+}
+
+async fn reconcile_all(
+    pool: &Pool<ConnectionManager<MysqlConnection>>,
+    providers: &ProviderRegistry,
+) -> Result<(), String> {
+    let conn = &mut pool.get().map_err(|e| e.to_string())?;
+
+    let records = dns_record::table
+        .filter(dns_record::drift_policy.ne(DriftPolicy::Ignore.as_str()))
+        // The provider is meant to differ while a fallback is served
+        .filter(dns_record::fallback_active.eq(false))
+        // Their zone is gone, there's nothing left to compare against
+        .filter(dns_record::orphaned_on.is_null())
+        .select((
+            dns_record::user_id,
+            dns_record::id,
+            dns_record::zone_id,
+            dns_record::record_name,
+            dns_record::record_type,
+            dns_record::content,
+            dns_record::ttl,
+            dns_record::proxied,
+            dns_record::drift_policy,
+        ))
+        .load::<ManagedRecord>(conn)
+        .map_err(|e| e.to_string())?;
+
+    // Group by zone so each token is only decrypted once per pass
+    let mut by_zone: BTreeMap<(String, String), Vec<ManagedRecord>> = BTreeMap::new();
+    for record in records {
+        by_zone
+            .entry((record.user_id.clone(), record.zone_id.clone()))
+            .or_default()
+            .push(record);
+    }
+
+    for ((user_id, zone_id), records) in by_zone {
+        let provider = match get_zone_provider(conn, providers, &user_id, &zone_id) {
+            Ok(provider) => provider,
+            Err(e) => {
+                for record in &records {
+                    save_result(conn, &record.id, ReconcileStatus::Error, Some(&e));
+                }
+                continue;
+            }
+        };
+
+        for record in &records {
+            let (status, detail) = reconcile_record(conn, provider.as_ref(), record).await;
+            save_result(conn, &record.id, status, detail.as_deref());
+        }
+    }
+
+    Ok(())
+}
+
+async fn reconcile_record(
+    conn: &mut MysqlConnection,
+    provider: &dyn DnsProvider,
+    record: &ManagedRecord,
+) -> (ReconcileStatus, Option<String>) {
+    let remote = match provider.get_record(&record.zone_id, &record.id).await {
+        Ok(Some(remote)) => remote,
+        Ok(None) => {
+            tracing::warn!("Record {} no longer exists at its provider", record.id);
+            return (
+                ReconcileStatus::Missing,
+                Some("Record no longer exists at the provider".to_string()),
+            );
+        }
+        Err(e) => return (ReconcileStatus::Error, Some(e.to_string())),
+    };
+
+    let drift = describe_drift(record, &remote);
+    if drift.is_empty() {
+        return (ReconcileStatus::InSync, None);
+    }
+
+    let detail = drift.join(", ");
+    let policy = DriftPolicy::parse(&record.drift_policy).unwrap_or(DEFAULT_DRIFT_POLICY);
+
+    if policy != DriftPolicy::Repair {
+        tracing::warn!("Record {} drifted: {}", record.id, detail);
+        return (ReconcileStatus::Drifted, Some(detail));
+    }
+
+    // A sync may have changed the record while we were asking the provider, or be waiting to,
+    // don't overwrite it with what is now stale content. Anything queued after the repair
+    // supersedes it in the outbox.
+    let current = dns_record::table
+        .filter(dns_record::id.eq(&record.id))
+        .select(dns_record::content)
+        .first::<String>(conn);
+    if current.as_deref() != Ok(record.content.as_str())
+        || outbox::has_queued(conn, &record.id).unwrap_or(true)
+    {
+        return (ReconcileStatus::Drifted, Some(detail));
+    }
+
+    let payload = RecordPayload {
+        record_type: &record.record_type,
+        name: &record.record_name,
+        content: &record.content,
+        ttl: record.ttl,
+        proxied: Some(record.proxied),
+    };
+
+    let operation = RecordOperation::Update {
+        record_id: record.id.clone(),
+        payload: PayloadSpec::new(&payload),
+        stored: StoredChanges::default(),
+    };
+
+    match outbox::submit(conn, provider, &record.user_id, &record.zone_id, operation).await {
+        Ok(_) => {
+            tracing::info!("Repaired drifted record {}: {}", record.id, detail);
+            (ReconcileStatus::Repaired, Some(detail))
+        }
+        Err(e) => (
+            ReconcileStatus::Error,
+            Some(format!("{}; repair failed: {}", detail, e)),
+        ),
+    }
+}
+
+/// Human readable list of the fields that differ, empty when the record matches
+fn describe_drift(record: &ManagedRecord, remote: &DnsRecord) -> Vec<String> {
+    let mut drift = Vec::new();
+
+    if !same_content(&record.content, &remote.content) {
+        drift.push(format!(
+            "content {} (expected {})",
+            remote.content, record.content
+        ));
+    }
+    if record.ttl != remote.ttl {
+        drift.push(format!("ttl {} (expected {})", remote.ttl, record.ttl));
+    }
+    if record.proxied != remote.proxied {
+        drift.push(format!(
+            "proxied {} (expected {})",
+            remote.proxied, record.proxied
+        ));
+    }
+
+    drift
+}
+
+// IPv6 addresses can be written several ways and names may come back with a trailing dot
+fn same_content(ours: &str, theirs: &str) -> bool {
+    match (ours.parse::<IpAddr>(), theirs.parse::<IpAddr>()) {
+        (Ok(ours), Ok(theirs)) => ours == theirs,
+        _ => ours
+            .trim_end_matches('.')
+            .eq_ignore_ascii_case(theirs.trim_end_matches('.')),
+    }
+}
+
+fn save_result(
+    conn: &mut MysqlConnection,
+    record_id: &str,
+    status: ReconcileStatus,
+    detail: Option<&str>,
+) {
+    let result = diesel::update(dns_record::table.filter(dns_record::id.eq(record_id)))
+        .set((
+            dns_record::last_reconciled_on.eq(chrono::Utc::now().naive_utc()),
+            dns_record::reconcile_status.eq(status.as_str()),
+            dns_record::reconcile_detail.eq(detail),
+        ))
+        .execute(conn);
+
+    if let Err(e) = result {
+        tracing::warn!("Failed to save reconcile result for {}: {}", record_id, e);
+    }
+}
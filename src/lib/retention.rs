@@ -0,0 +1,73 @@
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, Pool},
+};
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    db::schema::{provider_operation, sync_event, webhook_delivery},
+    lib::outbox::OperationStatus,
+};
+
+// Old rows pile up slowly, no need to look more often than this
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Every hour, delete sync history, finished provider operations and webhook deliveries
+/// older than `retention`
+pub fn spawn(pool: Pool<ConnectionManager<MysqlConnection>>, retention: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(PURGE_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            ticker.tick().await;
+
+            if let Err(e) = purge(&pool, retention) {
+                tracing::warn!("Purging old history failed: {}", e);
+            }
+        }
+    });
+}
+
+fn purge(
+    pool: &Pool<ConnectionManager<MysqlConnection>>,
+    retention: Duration,
+) -> Result<(), String> {
+    let conn = &mut pool.get().map_err(|e| e.to_string())?;
+    let retention = chrono::Duration::from_std(retention).map_err(|e| e.to_string())?;
+    let cutoff = chrono::Utc::now().naive_utc() - retention;
+
+    let events = diesel::delete(sync_event::table.filter(sync_event::created_on.lt(cutoff)))
+        .execute(conn)
+        .map_err(|e| e.to_string())?;
+
+    // Pending and running operations are still needed, however old
+    let operations = diesel::delete(
+        provider_operation::table
+            .filter(provider_operation::status.eq_any([
+                OperationStatus::Done.as_str(),
+                OperationStatus::Failed.as_str(),
+                OperationStatus::Superseded.as_str(),
+            ]))
+            .filter(provider_operation::completed_on.lt(cutoff)),
+    )
+    .execute(conn)
+    .map_err(|e| e.to_string())?;
+
+    let deliveries =
+        diesel::delete(webhook_delivery::table.filter(webhook_delivery::created_on.lt(cutoff)))
+            .execute(conn)
+            .map_err(|e| e.to_string())?;
+
+    if events + operations + deliveries > 0 {
+        tracing::info!(
+            "Purged {} sync events, {} provider operations and {} webhook deliveries",
+            events,
+            operations,
+            deliveries
+        );
+    }
+
+    Ok(())
+}
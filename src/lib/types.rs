@@ -1,142 +1,123 @@
 use chrono::NaiveDateTime;
+use diesel::Queryable;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct AddDnsRecord {
-    pub zone_id: String,
-    pub zone_name: String,
-    pub record_type: String,
-    pub name: String,
-    pub content: String,
-    pub ttl: i32,
-    pub proxied: bool,
-}
+// Bodies the client sends or reads live in drago-proto so both sides agree on them
+pub use drago_proto::v1::{
+    AddAccessToken, AddApiKey, AddDnsRecord, AdoptDnsRecord, AdoptedDnsRecord, ApiKey,
+    DnsAccessToken, DnsRecord, ManagedDnsRecord, ReconcileResult, SyncRequest, SyncResponse,
+    UpdateDnsRecord, Zone, ZoneRefresh,
+};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct DeleteDnsRecord {
     pub record_id: String,
     pub zone_id: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct AddAccessToken {
-    pub name: String,
-    pub token: String,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct DeleteAccessToken {
     pub token_id: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct DnsRecord {
-    pub id: String,
-    pub name: String,
-    #[serde(rename = "type")]
-    pub record_type: String,
-    pub content: String,
-    pub ttl: i32,
-    pub proxied: bool,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct DeletedDnsRecord {
-    pub id: String,
+#[derive(Debug, Deserialize, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteApiKeyParams {
+    pub key_id: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Zone {
-    pub id: String,
-    pub name: String,
-    pub status: String,
+// DynDNS2 /nic/update query, as sent by routers
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NicUpdateParams {
+    pub hostname: Option<String>,
+    pub myip: Option<String>,
+    pub myipv6: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct DnsAccessToken {
-    pub name: String,
+// One row of a record's sync history
+#[derive(Debug, Serialize, Queryable, ToSchema)]
+pub struct SyncEvent {
     pub id: String,
+    pub api_key_id: Option<String>,
+    pub old_content: Option<String>,
+    pub new_content: Option<String>,
+    // updated, unchanged or error
+    pub outcome: String,
+    pub provider_response: Option<String>,
     pub created_on: NaiveDateTime,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct ApiKey {
+// A provider change as the outbox tracks it
+#[derive(Debug, Serialize, Queryable, ToSchema)]
+pub struct ProviderOperation {
     pub id: String,
+    pub zone_id: String,
+    pub record_id: Option<String>,
+    // create, update or delete
+    pub kind: String,
+    // pending, running, done, failed or superseded
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_on: NaiveDateTime,
     pub created_on: NaiveDateTime,
-    pub last_used: Option<NaiveDateTime>,
-    pub name: String,
-    pub record_name: String,
+    pub completed_on: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct DeleteApiKeyParams {
-    pub key_id: String,
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryParams {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
 }
 
-// From Cloudflare
-#[derive(Debug, Deserialize, Serialize)]
-pub struct DnsZonesResponse {
-    pub result: Vec<Zone>,
-    pub success: bool,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct CreateRecordResponse {
-    pub result: DnsRecord,
-    pub success: bool,
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SyncHistory {
+    pub events: Vec<SyncEvent>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct RecordResponse {
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Webhook {
     pub id: String,
+    pub name: String,
+    pub url: String,
+    pub created_on: NaiveDateTime,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct PutRecordResponse {
-    pub result: RecordResponse,
-    pub success: bool,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct DeleteRecordResponse {
-    pub result: DeletedDnsRecord,
-    pub success: bool,
-}
-
-#[derive(Serialize)]
-pub struct DnsRecordPayload<'a> {
-    pub r#type: &'a String,
-    pub name: &'a String,
-    pub content: &'a String,
-    pub ttl: &'a i32,
-    pub proxied: &'a bool,
-}
-
-#[derive(Serialize, Debug)]
-pub struct PutDnsRecordPayload<'a> {
-    pub r#type: &'a String,
-    pub name: &'a String,
-    pub content: &'a str,
-    pub ttl: &'a i32,
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AddWebhook {
+    pub name: String,
+    pub url: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct AddApiKey {
-    pub name: String,
-    pub scope: String,
+// The signing secret is only ever shown here
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedWebhook {
+    pub id: String,
+    pub secret: String,
 }
 
-// API key is sent in the authorization header
-#[derive(Debug, Deserialize)]
-pub struct SyncRequest {
-    pub ip_address: String,
-    // Used as the new time updated and time api key used
-    pub time_synced: NaiveDateTime,
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteWebhookParams {
+    pub webhook_id: String,
 }
 
-#[derive(Debug, Serialize)]
-pub struct SyncResponse {
+#[derive(Debug, Serialize, Queryable, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub event_id: String,
+    pub attempt: i32,
+    // None when the receiver couldn't be reached
+    pub status_code: Option<i32>,
+    pub response: Option<String>,
     pub success: bool,
-    pub updated: bool,
-    pub message: String,
+    pub created_on: NaiveDateTime,
 }
@@ -1,36 +1,126 @@
-use aes_gcm::aead::OsRng;
-use argon2::{Argon2, PasswordHasher, password_hash::SaltString};
+use anyhow::Context;
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use diesel::{ExpressionMethods, MysqlConnection, OptionalExtension, QueryDsl, RunQueryDsl};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{env, sync::OnceLock};
 
-use crate::{db::schema::dns_token, lib::encryption::decrypt};
+use crate::{
+    db::schema::{dns_token, dns_zone},
+    lib::{
+        encryption::decrypt,
+        providers::{DnsProvider, ProviderRegistry},
+    },
+};
 
-pub fn hash_raw_string(raw_string: &String) -> Result<String, argon2::password_hash::Error> {
-    let salt = SaltString::generate(&mut OsRng);
+// Marks key hashes made with the peppered HMAC, anything else is a legacy argon2 PHC string
+const HMAC_HASH_PREFIX: &str = "hmac-sha256$";
 
-    // Argon2 with default params (Argon2id v19)
-    let argon2 = Argon2::default();
+pub enum ApiKeyMatch {
+    Valid,
+    // Valid, but stored with the old argon2 scheme and should be rehashed
+    ValidLegacy,
+    Invalid,
+}
+
+static PEPPER: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// The API key pepper from the environment. Read on first use and kept, main asks for it at
+/// startup so a missing or short one fails there.
+pub fn get_pepper() -> anyhow::Result<&'static [u8]> {
+    if let Some(pepper) = PEPPER.get() {
+        return Ok(pepper);
+    }
+
+    let pepper = check_pepper(env::var("API_KEY_PEPPER").context("API_KEY_PEPPER not set")?)?;
+    Ok(PEPPER.get_or_init(|| pepper))
+}
+
+fn check_pepper(pepper: String) -> anyhow::Result<Vec<u8>> {
+    if pepper.len() < 32 {
+        return Err(anyhow::anyhow!(
+            "API_KEY_PEPPER must be at least 32 characters"
+        ));
+    }
+
+    Ok(pepper.into_bytes())
+}
+
+fn keyed_mac(api_key: &str) -> anyhow::Result<Hmac<Sha256>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(get_pepper()?)
+        .map_err(|e| anyhow::anyhow!("Invalid pepper: {}", e))?;
+    mac.update(api_key.as_bytes());
+    Ok(mac)
+}
+
+/// HMAC-SHA256 of an API key with the server side pepper.
+/// API keys are long random strings, so a slow password hash buys nothing on the sync hot path.
+pub fn api_key_digest(api_key: &str) -> anyhow::Result<Vec<u8>> {
+    Ok(keyed_mac(api_key)?.finalize().into_bytes().to_vec())
+}
+
+pub fn hash_api_key(api_key: &str) -> anyhow::Result<String> {
+    Ok(format!(
+        "{}{}",
+        HMAC_HASH_PREFIX,
+        hex::encode(api_key_digest(api_key)?)
+    ))
+}
+
+/// Check a full dgo_<prefix>_<secret> key against the stored key_hash in constant time
+pub fn verify_api_key(api_key: &str, stored_hash: &str) -> anyhow::Result<ApiKeyMatch> {
+    if let Some(hex_digest) = stored_hash.strip_prefix(HMAC_HASH_PREFIX) {
+        let Ok(expected) = hex::decode(hex_digest) else {
+            return Ok(ApiKeyMatch::Invalid);
+        };
+
+        return Ok(match keyed_mac(api_key)?.verify_slice(&expected) {
+            Ok(()) => ApiKeyMatch::Valid,
+            Err(_) => ApiKeyMatch::Invalid,
+        });
+    }
+
+    // Keys created before the switch were hashed with argon2
+    let Ok(parsed_hash) = PasswordHash::new(stored_hash) else {
+        return Ok(ApiKeyMatch::Invalid);
+    };
 
-    // Hash password to PHC string 97 bytes
-    let password_hash = argon2
-        .hash_password(raw_string.as_bytes(), &salt)?
-        .to_string();
-    Ok(password_hash)
+    Ok(
+        match Argon2::default().verify_password(api_key.as_bytes(), &parsed_hash) {
+            Ok(()) => ApiKeyMatch::ValidLegacy,
+            Err(_) => ApiKeyMatch::Invalid,
+        },
+    )
 }
 
-pub fn get_user_token(conn: &mut MysqlConnection, user_id: &String) -> Result<String, String> {
-    // Simplified to just return the decrypted token
-    // Get the user's dns access token from our db
-    let token_data = dns_token::table
+/// The provider for one of the user's zones, built from the token that discovered the zone
+pub fn get_zone_provider(
+    conn: &mut MysqlConnection,
+    providers: &ProviderRegistry,
+    user_id: &str,
+    zone_id: &str,
+) -> Result<Box<dyn DnsProvider>, String> {
+    // Get the zone's dns access token from our db
+    let token_data = dns_zone::table
+        .inner_join(dns_token::table)
+        .filter(dns_zone::id.eq(zone_id))
+        .filter(dns_zone::user_id.eq(user_id))
         .filter(dns_token::user_id.eq(user_id))
-        .select((dns_token::token_encrypted, dns_token::nonce, dns_token::tag))
-        .first::<(Vec<u8>, Vec<u8>, Vec<u8>)>(conn)
+        .select((
+            dns_token::id,
+            dns_token::provider,
+            dns_token::token_encrypted,
+            dns_token::nonce,
+            dns_token::tag,
+        ))
+        .first::<(String, String, Vec<u8>, Vec<u8>, Vec<u8>)>(conn)
         .optional();
 
     // Handle DB error or Missing Token
-    let (ciphertext, nonce, tag) = match token_data {
+    let (token_id, provider, ciphertext, nonce, tag) = match token_data {
         Ok(Some(data)) => data,
         Ok(None) => {
-            return Err("No token found for account".to_string());
+            return Err(format!("No token found for zone {}", zone_id));
         }
         Err(e) => {
             eprintln!("Token Query failed: {:?}", e);
@@ -38,11 +128,120 @@ pub fn get_user_token(conn: &mut MysqlConnection, user_id: &String) -> Result<St
         }
     };
 
-    // Decrypt the token
-    let decrypted_token = decrypt(&nonce, &ciphertext, &tag).map_err(|e| {
+    build_provider(providers, &token_id, &provider, &nonce, &ciphertext, &tag)
+}
+
+/// Decrypt a stored dns_token and build the provider it belongs to
+pub fn build_provider(
+    providers: &ProviderRegistry,
+    token_id: &str,
+    provider: &str,
+    nonce: &[u8],
+    ciphertext: &[u8],
+    tag: &[u8],
+) -> Result<Box<dyn DnsProvider>, String> {
+    let decrypted_token = decrypt(nonce, ciphertext, tag).map_err(|e| {
         eprintln!("Decryption failed: {:?}", e);
         format!("Decryption error: {}", e)
     })?;
 
-    Ok(decrypted_token)
+    providers
+        .build(provider, token_id, &decrypted_token)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argon2::{password_hash::SaltString, PasswordHasher};
+    use std::sync::Once;
+
+    const API_KEY: &str = "dgo_0123456789abcdef_secretsecretsecretsecret";
+
+    // The pepper is read once per process, every test sets the same one before using it
+    fn set_pepper() {
+        static SET: Once = Once::new();
+        SET.call_once(|| env::set_var("API_KEY_PEPPER", "p".repeat(32)));
+    }
+
+    #[test]
+    fn short_pepper_is_refused() {
+        assert!(check_pepper("p".repeat(31)).is_err());
+        assert_eq!(check_pepper("p".repeat(32)).unwrap(), vec![b'p'; 32]);
+    }
+
+    #[test]
+    fn hash_fits_the_key_hash_column() {
+        set_pepper();
+        let hash = hash_api_key(API_KEY).unwrap();
+
+        assert!(hash.starts_with(HMAC_HASH_PREFIX));
+        assert!(hash.len() <= 97, "{} characters", hash.len());
+    }
+
+    #[test]
+    fn hashed_key_verifies() {
+        set_pepper();
+        let hash = hash_api_key(API_KEY).unwrap();
+
+        assert!(matches!(
+            verify_api_key(API_KEY, &hash).unwrap(),
+            ApiKeyMatch::Valid
+        ));
+    }
+
+    #[test]
+    fn other_key_is_invalid() {
+        set_pepper();
+        let hash = hash_api_key(API_KEY).unwrap();
+        let other = API_KEY.replace("secret", "Secret");
+
+        assert!(matches!(
+            verify_api_key(&other, &hash).unwrap(),
+            ApiKeyMatch::Invalid
+        ));
+    }
+
+    #[test]
+    fn malformed_hashes_are_invalid() {
+        set_pepper();
+        let digest = hex::encode(api_key_digest(API_KEY).unwrap());
+
+        for stored in [
+            format!("{}not hex", HMAC_HASH_PREFIX),
+            format!("{}{}", HMAC_HASH_PREFIX, &digest[..62]),
+            format!("{}{}00", HMAC_HASH_PREFIX, digest),
+            HMAC_HASH_PREFIX.to_string(),
+            "".to_string(),
+            "$argon2id$garbage".to_string(),
+        ] {
+            assert!(
+                matches!(
+                    verify_api_key(API_KEY, &stored).unwrap(),
+                    ApiKeyMatch::Invalid
+                ),
+                "{}",
+                stored
+            );
+        }
+    }
+
+    #[test]
+    fn argon2_hash_is_valid_legacy() {
+        set_pepper();
+        let salt = SaltString::from_b64("c29tZXNhbHRzb21lc2FsdA").unwrap();
+        let legacy = Argon2::default()
+            .hash_password(API_KEY.as_bytes(), &salt)
+            .unwrap()
+            .to_string();
+
+        assert!(matches!(
+            verify_api_key(API_KEY, &legacy).unwrap(),
+            ApiKeyMatch::ValidLegacy
+        ));
+        assert!(matches!(
+            verify_api_key("dgo_0123456789abcdef_wrong", &legacy).unwrap(),
+            ApiKeyMatch::Invalid
+        ));
+    }
 }
@@ -0,0 +1,448 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, Pool},
+};
+use hmac::{Hmac, Mac};
+use hyper::client::connect::dns::Name;
+use rand::{distr::Alphanumeric, Rng};
+use reqwest::{
+    dns::{Addrs, Resolve, Resolving},
+    redirect, Client, Response, StatusCode, Url,
+};
+use serde::Serialize;
+use sha2::Sha256;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use uuid::Uuid;
+
+use crate::{
+    db::schema::{webhook, webhook_delivery},
+    lib::encryption::decrypt,
+};
+
+pub const EVENT_HEADER: &str = "X-Drago-Event";
+pub const TIMESTAMP_HEADER: &str = "X-Drago-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Drago-Signature";
+
+pub const RECORD_IP_CHANGED: &str = "record.ip_changed";
+
+const MAX_ATTEMPTS: u32 = 5;
+// Doubled after every failed attempt: 2s, 4s, 8s, 16s
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REDIRECTS: usize = 5;
+// Enough of the receiver's answer to debug with, without storing whole error pages
+const MAX_LOGGED_RESPONSE: usize = 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookRecord {
+    pub id: String,
+    pub zone_id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+}
+
+/// Body POSTed to every webhook of the record's owner
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordChangedEvent {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: &'static str,
+    pub occurred_at: NaiveDateTime,
+    pub record: WebhookRecord,
+    // None when the record was just created
+    pub old_ip: Option<String>,
+    pub new_ip: String,
+}
+
+impl RecordChangedEvent {
+    pub fn new(record: WebhookRecord, old_ip: Option<&str>, new_ip: &str) -> Self {
+        Self {
+            id: Uuid::now_v7().to_string(),
+            event_type: RECORD_IP_CHANGED,
+            occurred_at: chrono::Utc::now().naive_utc(),
+            record,
+            old_ip: old_ip.map(str::to_string),
+            new_ip: new_ip.to_string(),
+        }
+    }
+}
+
+/// Shared secret handed to the user once, used to sign every delivery
+pub fn generate_webhook_secret() -> String {
+    let secret: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    format!("whsec_{}", secret)
+}
+
+/// Hex HMAC-SHA256 of "<timestamp>.<body>". Including the timestamp lets receivers reject replays.
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// The webhook's URL, if it's http(s) and doesn't point into our own network.
+/// Host names are checked again every time a delivery resolves them.
+pub fn check_url(url: &str) -> Result<Url, String> {
+    let invalid = || "Webhook url must be an http(s) URL".to_string();
+
+    let url = Url::parse(url).map_err(|_| invalid())?;
+    if url.scheme() != "https" && url.scheme() != "http" {
+        return Err(invalid());
+    }
+    let host = url.host_str().ok_or_else(invalid)?;
+
+    // IPv6 hosts come in brackets
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    let internal = match literal.parse::<IpAddr>() {
+        Ok(ip) => !is_public(ip),
+        Err(_) => host.eq_ignore_ascii_case("localhost") || host.ends_with(".localhost"),
+    };
+    if internal {
+        return Err(format!("Webhook url must not point at {}", host));
+    }
+
+    Ok(url)
+}
+
+// Anything a delivery could use to reach the server's own network or services
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // "This network" 0.0.0.0/8 and carrier-grade NAT 100.64.0.0/10
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local fc00::/7 and link-local fe80::/10
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Resolves webhook hosts, refusing names with any address that isn't public. Runs for every
+/// connection, so a name can't be pointed at an internal address after the webhook was added.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+                return Err(format!("{} resolves to internal address {}", host, addr.ip()).into());
+            }
+
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+// The resolver doesn't see IP literals, so redirects to them are checked here
+fn redirect_policy() -> redirect::Policy {
+    redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+        match check_url(attempt.url().as_str()) {
+            Ok(_) => attempt.follow(),
+            Err(e) => attempt.error(e),
+        }
+    })
+}
+
+struct Subscriber {
+    id: String,
+    url: String,
+    secret: String,
+}
+
+/// Sends record events to the user's webhooks in the background, so a slow or
+/// unreachable receiver never holds up a sync
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+    pool: Pool<ConnectionManager<MysqlConnection>>,
+    client: Client,
+}
+
+impl WebhookDispatcher {
+    pub fn new(pool: Pool<ConnectionManager<MysqlConnection>>) -> Self {
+        Self {
+            pool,
+            client: Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .dns_resolver(Arc::new(PublicResolver))
+                .redirect(redirect_policy())
+                .build()
+                .expect("Could not build webhook HTTP client"),
+        }
+    }
+
+    pub fn dispatch(&self, user_id: &str, event: RecordChangedEvent) {
+        let dispatcher = self.clone();
+        let user_id = user_id.to_string();
+
+        tokio::spawn(async move {
+            let subscribers = match dispatcher.subscribers(&user_id) {
+                Ok(subscribers) => subscribers,
+                Err(e) => {
+                    tracing::warn!("Failed to load webhooks for {}: {}", user_id, e);
+                    return;
+                }
+            };
+
+            let body = match serde_json::to_vec(&event) {
+                Ok(body) => body,
+                Err(e) => {
+                    tracing::warn!("Failed to serialize webhook event {}: {}", event.id, e);
+                    return;
+                }
+            };
+
+            for subscriber in subscribers {
+                tokio::spawn(dispatcher.clone().deliver(
+                    subscriber,
+                    event.id.clone(),
+                    body.clone(),
+                ));
+            }
+        });
+    }
+
+    fn subscribers(&self, user_id: &str) -> Result<Vec<Subscriber>, String> {
+        let conn = &mut self.pool.get().map_err(|e| e.to_string())?;
+
+        let rows = webhook::table
+            .filter(webhook::user_id.eq(user_id))
+            .select((
+                webhook::id,
+                webhook::url,
+                webhook::nonce,
+                webhook::secret_encrypted,
+                webhook::tag,
+            ))
+            .load::<(String, String, Vec<u8>, Vec<u8>, Vec<u8>)>(conn)
+            .map_err(|e| e.to_string())?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(id, url, nonce, ciphertext, tag)| {
+                match decrypt(&nonce, &ciphertext, &tag) {
+                    Ok(secret) => Some(Subscriber { id, url, secret }),
+                    Err(e) => {
+                        tracing::warn!("Failed to decrypt secret of webhook {}: {}", id, e);
+                        None
+                    }
+                }
+            })
+            .collect())
+    }
+
+    async fn deliver(self, subscriber: Subscriber, event_id: String, body: Vec<u8>) {
+        // Webhooks added before URLs were checked may point anywhere
+        if let Err(e) = check_url(&subscriber.url) {
+            self.log_delivery(&subscriber.id, &event_id, 1, None, &e, false);
+            return;
+        }
+
+        let mut backoff = INITIAL_BACKOFF;
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let timestamp = chrono::Utc::now().timestamp();
+            let signature = sign(&subscriber.secret, timestamp, &body);
+
+            let result = self
+                .client
+                .post(&subscriber.url)
+                .header("Content-Type", "application/json")
+                .header(EVENT_HEADER, RECORD_IP_CHANGED)
+                .header(TIMESTAMP_HEADER, timestamp)
+                .header(SIGNATURE_HEADER, format!("sha256={}", signature))
+                .body(body.clone())
+                .send()
+                .await;
+
+            let (status, response, retry) = match result {
+                Ok(resp) => {
+                    let status = resp.status();
+                    let text = read_logged_response(resp).await;
+                    (Some(status), text, should_retry(status))
+                }
+                Err(e) => (None, e.to_string(), true),
+            };
+
+            let success = status.is_some_and(|status| status.is_success());
+            self.log_delivery(
+                &subscriber.id,
+                &event_id,
+                attempt,
+                status,
+                &response,
+                success,
+            );
+
+            if success || !retry {
+                return;
+            }
+
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+
+        tracing::warn!(
+            "Giving up on webhook {} for event {} after {} attempts",
+            subscriber.id,
+            event_id,
+            MAX_ATTEMPTS
+        );
+    }
+
+    fn log_delivery(
+        &self,
+        webhook_id: &str,
+        event_id: &str,
+        attempt: u32,
+        status: Option<StatusCode>,
+        response: &str,
+        success: bool,
+    ) {
+        let mut response = response.to_string();
+        if response.len() > MAX_LOGGED_RESPONSE {
+            let mut end = MAX_LOGGED_RESPONSE;
+            while !response.is_char_boundary(end) {
+                end -= 1;
+            }
+            response.truncate(end);
+        }
+
+        let result = self
+            .pool
+            .get()
+            .map_err(|e| e.to_string())
+            .and_then(|mut conn| {
+                diesel::insert_into(webhook_delivery::table)
+                    .values((
+                        webhook_delivery::id.eq(Uuid::now_v7().to_string()),
+                        webhook_delivery::webhook_id.eq(webhook_id),
+                        webhook_delivery::event_id.eq(event_id),
+                        webhook_delivery::attempt.eq(attempt as i32),
+                        webhook_delivery::status_code.eq(status.map(|s| s.as_u16() as i32)),
+                        webhook_delivery::response.eq(response),
+                        webhook_delivery::success.eq(success),
+                    ))
+                    .execute(&mut conn)
+                    .map_err(|e| e.to_string())
+            });
+
+        if let Err(e) = result {
+            tracing::warn!("Failed to log delivery of webhook {}: {}", webhook_id, e);
+        }
+    }
+}
+
+// Only reads as much of the body as gets logged, a receiver can answer with any amount
+async fn read_logged_response(mut resp: Response) -> String {
+    let mut body = Vec::new();
+    while body.len() < MAX_LOGGED_RESPONSE {
+        match resp.chunk().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            Ok(None) | Err(_) => break,
+        }
+    }
+    body.truncate(MAX_LOGGED_RESPONSE);
+
+    String::from_utf8_lossy(&body).into_owned()
+}
+
+// Client errors won't fix themselves, except for rate limiting
+fn should_retry(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_public_urls() {
+        for url in [
+            "https://hooks.example.com/drago",
+            "http://203.0.113.7:8080/hook",
+            "https://[2001:db8::1]/hook",
+        ] {
+            assert!(check_url(url).is_ok(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn rejects_internal_urls() {
+        for url in [
+            "http://127.0.0.1/",
+            "http://2130706433/",
+            "http://localhost:3000/",
+            "http://api.localhost/",
+            "http://10.0.0.1/",
+            "http://172.16.5.4/",
+            "http://192.168.1.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://0.0.0.0/",
+            "http://100.64.0.1/",
+            "http://[::1]/",
+            "http://[::]/",
+            "http://[fd00::1]/",
+            "http://[fe80::1]/",
+            "http://[::ffff:127.0.0.1]/",
+        ] {
+            assert!(check_url(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn rejects_other_schemes() {
+        for url in ["ftp://example.com/", "file:///etc/passwd", "not a url"] {
+            assert!(check_url(url).is_err(), "{}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn resolver_refuses_internal_names() {
+        let name: Name = "localhost".parse().unwrap();
+        assert!(PublicResolver.resolve(name).await.is_err());
+    }
+}
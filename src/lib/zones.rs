@@ -0,0 +1,267 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, Pool},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    time::Duration,
+};
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    db::schema::{dns_record, dns_token, dns_zone},
+    lib::{
+        providers::ProviderRegistry,
+        types::{Zone, ZoneRefresh},
+        utils::build_provider,
+    },
+};
+
+/// What we keep of the provider's view of a zone, stored as JSON in dns_zone.meta
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ZoneMeta {
+    pub status: String,
+    #[serde(default)]
+    pub name_servers: Vec<String>,
+}
+
+impl ZoneMeta {
+    pub fn parse(meta: Option<&str>) -> Option<Self> {
+        meta.and_then(|meta| serde_json::from_str(meta).ok())
+    }
+}
+
+struct KnownZone {
+    token_id: String,
+    name: String,
+    orphaned_on: Option<NaiveDateTime>,
+}
+
+/// Refresh every user's zones every `interval`
+pub fn spawn(
+    pool: Pool<ConnectionManager<MysqlConnection>>,
+    providers: ProviderRegistry,
+    interval: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        // Zones were just listed when the tokens were added, no need to do it again at startup
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            if let Err(e) = refresh_all(&pool, &providers).await {
+                tracing::warn!("Zone refresh failed: {}", e);
+            }
+        }
+    });
+}
+
+async fn refresh_all(
+    pool: &Pool<ConnectionManager<MysqlConnection>>,
+    providers: &ProviderRegistry,
+) -> Result<(), String> {
+    let conn = &mut pool.get().map_err(|e| e.to_string())?;
+
+    let users = dns_token::table
+        .select(dns_token::user_id)
+        .distinct()
+        .load::<String>(conn)
+        .map_err(|e| e.to_string())?;
+
+    for user_id in users {
+        match refresh_zones(conn, providers, &user_id).await {
+            Ok(refresh) if !refresh.orphaned.is_empty() => tracing::warn!(
+                "Zones of {} no longer at their provider: {}",
+                user_id,
+                refresh.orphaned.join(", ")
+            ),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to refresh zones of {}: {}", user_id, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// List the zones of every token the user has and bring dns_zone in line: new zones are added,
+/// known ones get fresh meta, and zones a token no longer sees are marked orphaned along with
+/// their records. Zones of a token that can't be listed are left alone.
+pub async fn refresh_zones(
+    conn: &mut MysqlConnection,
+    providers: &ProviderRegistry,
+    user_id: &str,
+) -> Result<ZoneRefresh, String> {
+    let tokens = dns_token::table
+        .filter(dns_token::user_id.eq(user_id))
+        .select((
+            dns_token::id,
+            dns_token::provider,
+            dns_token::token_encrypted,
+            dns_token::nonce,
+            dns_token::tag,
+        ))
+        .load::<(String, String, Vec<u8>, Vec<u8>, Vec<u8>)>(conn)
+        .map_err(|e| e.to_string())?;
+
+    let known: HashMap<String, KnownZone> = dns_zone::table
+        .filter(dns_zone::user_id.eq(user_id))
+        .select((
+            dns_zone::id,
+            dns_zone::token_id,
+            dns_zone::zone_name,
+            dns_zone::orphaned_on,
+        ))
+        .load::<(String, String, String, Option<NaiveDateTime>)>(conn)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|(id, token_id, name, orphaned_on)| {
+            (
+                id,
+                KnownZone {
+                    token_id,
+                    name,
+                    orphaned_on,
+                },
+            )
+        })
+        .collect();
+
+    let mut refresh = ZoneRefresh {
+        tokens: tokens.len(),
+        ..Default::default()
+    };
+    let mut listed_tokens = HashSet::new();
+    // Zone id to the token that manages it and what the provider says about it
+    let mut listed: HashMap<String, (String, Zone)> = HashMap::new();
+
+    for (token_id, provider, ciphertext, nonce, tag) in &tokens {
+        let result = match build_provider(providers, token_id, provider, nonce, ciphertext, tag) {
+            Ok(provider) => provider.list_zones().await.map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+
+        let zones = match result {
+            Ok(zones) => zones,
+            Err(e) => {
+                tracing::warn!("Failed to list zones of token {}: {}", token_id, e);
+                refresh.failed_tokens.push(token_id.clone());
+                continue;
+            }
+        };
+        listed_tokens.insert(token_id.clone());
+
+        for zone in zones {
+            match listed.entry(zone.id.clone()) {
+                Entry::Vacant(entry) => {
+                    entry.insert((token_id.clone(), zone));
+                }
+                // Several tokens can see the same zone, stick with the one we already use
+                Entry::Occupied(mut entry) => {
+                    if known
+                        .get(&zone.id)
+                        .is_some_and(|known| &known.token_id == token_id)
+                    {
+                        entry.insert((token_id.clone(), zone));
+                    }
+                }
+            }
+        }
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+
+    conn.transaction(|conn| {
+        for (zone_id, (token_id, zone)) in &listed {
+            let meta = serde_json::to_string(&ZoneMeta {
+                status: zone.status.clone(),
+                name_servers: zone.name_servers.clone(),
+            })
+            .ok();
+
+            match known.get(zone_id) {
+                Some(existing) => {
+                    diesel::update(
+                        dns_zone::table
+                            .filter(dns_zone::id.eq(zone_id))
+                            .filter(dns_zone::user_id.eq(user_id)),
+                    )
+                    .set((
+                        dns_zone::token_id.eq(token_id),
+                        dns_zone::zone_name.eq(&zone.name),
+                        dns_zone::meta.eq(&meta),
+                        dns_zone::last_synced_on.eq(now),
+                        dns_zone::orphaned_on.eq(None::<NaiveDateTime>),
+                    ))
+                    .execute(conn)?;
+
+                    // The zone came back, so did its records
+                    if existing.orphaned_on.is_some() {
+                        diesel::update(
+                            dns_record::table
+                                .filter(dns_record::zone_id.eq(zone_id))
+                                .filter(dns_record::user_id.eq(user_id)),
+                        )
+                        .set(dns_record::orphaned_on.eq(None::<NaiveDateTime>))
+                        .execute(conn)?;
+                    }
+
+                    refresh.refreshed.push(zone.name.clone());
+                }
+                None => {
+                    // Ignored when another account already manages the zone
+                    let inserted = diesel::insert_or_ignore_into(dns_zone::table)
+                        .values((
+                            dns_zone::id.eq(zone_id),
+                            dns_zone::user_id.eq(user_id),
+                            dns_zone::zone_name.eq(&zone.name),
+                            dns_zone::token_id.eq(token_id),
+                            dns_zone::last_synced_on.eq(now),
+                            dns_zone::meta.eq(&meta),
+                        ))
+                        .execute(conn)?;
+
+                    if inserted > 0 {
+                        refresh.added.push(zone.name.clone());
+                    }
+                }
+            }
+        }
+
+        for (zone_id, zone) in &known {
+            if listed.contains_key(zone_id)
+                || !listed_tokens.contains(&zone.token_id)
+                || zone.orphaned_on.is_some()
+            {
+                continue;
+            }
+
+            diesel::update(
+                dns_zone::table
+                    .filter(dns_zone::id.eq(zone_id))
+                    .filter(dns_zone::user_id.eq(user_id)),
+            )
+            .set(dns_zone::orphaned_on.eq(now))
+            .execute(conn)?;
+
+            diesel::update(
+                dns_record::table
+                    .filter(dns_record::zone_id.eq(zone_id))
+                    .filter(dns_record::user_id.eq(user_id)),
+            )
+            .set(dns_record::orphaned_on.eq(now))
+            .execute(conn)?;
+
+            refresh.orphaned.push(zone.name.clone());
+        }
+
+        Ok::<_, diesel::result::Error>(())
+    })
+    .map_err(|e| e.to_string())?;
+
+    Ok(refresh)
+}
@@ -1,40 +1,100 @@
 mod db;
 mod lib;
+#[cfg(test)]
+mod tests;
 
 use crate::{
-    db::schema::{api_keys, dns_record, dns_token, dns_zone},
+    db::schema::{
+        api_keys, dns_record, dns_token, dns_zone, provider_operation, sync_event, webhook,
+        webhook_delivery,
+    },
     lib::{
+        api_error::{ApiError, ErrorBody, Resource},
         auth::User,
-        encryption::{decrypt, encrypt},
+        client_ip::{ClientIp, TrustedProxies},
+        encryption::encrypt,
+        liveness::{self, client_managed_names, fallback_record_type, LivenessConfig},
+        metrics,
+        openapi::ApiDoc,
+        outbox::{self, Completed, PayloadSpec, RecordOperation, StoredChanges},
+        ownership::{
+            owned_api_key, owned_record, owned_token, owned_webhook, owned_zone, owned_zone_record,
+        },
+        providers::{DnsProvider, ProviderRegistry, RecordPayload, DEFAULT_PROVIDER},
+        reconcile::{self, DriftPolicy, DEFAULT_DRIFT_POLICY},
+        retention,
         types::*,
-        utils::{get_user_token, hash_raw_string},
+        utils::{
+            api_key_digest, get_pepper, get_zone_provider, hash_api_key, verify_api_key,
+            ApiKeyMatch,
+        },
+        webhooks::{
+            check_url, generate_webhook_secret, RecordChangedEvent, WebhookDispatcher,
+            WebhookRecord,
+        },
+        zones::{self, refresh_zones, ZoneMeta},
     },
 };
 use crate::{
     db::{models::NewDnsAccessToken, *},
-    lib::auth::{generate_api_key, AuthState},
+    lib::auth::{generate_api_key, ApiKeyCache, AuthState},
 };
 use axum::{
-    extract::{FromRef, Query, State},
-    http::{HeaderMap, Method, StatusCode},
+    extract::{FromRef, Path, Query, State},
+    http::{
+        header::{CONTENT_TYPE, WWW_AUTHENTICATE},
+        HeaderMap, Method, StatusCode,
+    },
+    middleware,
     response::IntoResponse,
     routing::{delete, get, post, put},
     Json, Router,
 };
+use base64::{engine::general_purpose::STANDARD, Engine as _};
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool};
 use dotenv::dotenv;
+use std::collections::HashSet;
 use std::env;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use subtle::ConstantTimeEq;
 use tower_http::cors::{Any, CorsLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use utoipa::OpenApi;
+use utoipa_scalar::{Scalar, Servable};
 use uuid::Uuid;
 
+// How long a verified API key is trusted before it is checked against the DB again
+const API_KEY_CACHE_TTL: Duration = Duration::from_secs(60);
+
+// Page size for GET /records/{id}/history
+const HISTORY_PAGE_SIZE: i64 = 50;
+const HISTORY_MAX_PAGE_SIZE: i64 = 200;
+
+// How often managed records are compared with the provider, unless RECONCILE_INTERVAL_SECS is set
+const DEFAULT_RECONCILE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+// How often every user's zones are listed again, unless ZONE_REFRESH_INTERVAL_SECS is set
+const DEFAULT_ZONE_REFRESH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+// How long sync history, finished provider operations and webhook deliveries are kept,
+// unless HISTORY_RETENTION_DAYS is set
+const DEFAULT_HISTORY_RETENTION: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+// Most recent delivery attempts returned by GET /webhooks/{id}/deliveries
+const WEBHOOK_DELIVERY_LOG_SIZE: i64 = 100;
+
 #[derive(Clone)]
 struct AppState {
     pool: Pool<ConnectionManager<MysqlConnection>>,
     auth: AuthState,
+    providers: ProviderRegistry,
+    api_key_cache: ApiKeyCache,
+    trusted_proxies: TrustedProxies,
+    webhooks: WebhookDispatcher,
+    liveness: LivenessConfig,
 }
 
 impl FromRef<AppState> for AuthState {
@@ -43,6 +103,12 @@ impl FromRef<AppState> for AuthState {
     }
 }
 
+impl FromRef<AppState> for TrustedProxies {
+    fn from_ref(input: &AppState) -> Self {
+        input.trusted_proxies.clone()
+    }
+}
+
 impl FromRef<AppState> for Pool<ConnectionManager<MysqlConnection>> {
     fn from_ref(input: &AppState) -> Self {
         input.pool.clone()
@@ -63,22 +129,87 @@ async fn main() {
     let frontend_url = env::var("FRONTEND_URL").expect("FRONTEND_URL must be set");
     let api_url = env::var("API_URL").expect("API_URL must be set");
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    // Checked here so a missing or short pepper fails at startup rather than on the first sync
+    get_pepper().expect("API_KEY_PEPPER must be set to at least 32 characters");
 
     let addr: SocketAddr = api_url.parse().expect("Invalid API_URL format");
 
+    metrics::init();
+
     let manager = ConnectionManager::<MysqlConnection>::new(db_url);
     let pool = Pool::builder()
         .test_on_check_out(true)
+        .event_handler(Box::new(metrics::PoolMetrics))
         .build(manager)
         .expect("Could not build connection pool");
 
     let auth_state = crate::lib::auth::AuthState::new(&frontend_url);
+    let trusted_proxies = TrustedProxies::from_env().expect("Invalid TRUSTED_PROXIES");
+    let liveness_config = LivenessConfig::from_env().expect("Invalid client liveness settings");
+
+    // 0 turns drift reconciliation off
+    let reconcile_interval = env::var("RECONCILE_INTERVAL_SECS")
+        .map(|secs| {
+            Duration::from_secs(
+                secs.parse()
+                    .expect("RECONCILE_INTERVAL_SECS must be a number of seconds"),
+            )
+        })
+        .unwrap_or(DEFAULT_RECONCILE_INTERVAL);
+
+    // 0 turns the periodic zone refresh off, POST /zones/refresh still works
+    let zone_refresh_interval = env::var("ZONE_REFRESH_INTERVAL_SECS")
+        .map(|secs| {
+            Duration::from_secs(
+                secs.parse()
+                    .expect("ZONE_REFRESH_INTERVAL_SECS must be a number of seconds"),
+            )
+        })
+        .unwrap_or(DEFAULT_ZONE_REFRESH_INTERVAL);
+
+    // 0 keeps everything
+    let history_retention = env::var("HISTORY_RETENTION_DAYS")
+        .map(|days| {
+            let days: u64 = days
+                .parse()
+                .expect("HISTORY_RETENTION_DAYS must be a number of days");
+            Duration::from_secs(days.saturating_mul(24 * 60 * 60))
+        })
+        .unwrap_or(DEFAULT_HISTORY_RETENTION);
 
     let state = AppState {
+        webhooks: WebhookDispatcher::new(pool.clone()),
         pool,
         auth: auth_state,
+        providers: ProviderRegistry::new(),
+        api_key_cache: ApiKeyCache::new(API_KEY_CACHE_TTL),
+        trusted_proxies,
+        liveness: liveness_config,
     };
 
+    if !reconcile_interval.is_zero() {
+        reconcile::spawn(
+            state.pool.clone(),
+            state.providers.clone(),
+            reconcile_interval,
+        );
+    }
+
+    if !zone_refresh_interval.is_zero() {
+        zones::spawn(
+            state.pool.clone(),
+            state.providers.clone(),
+            zone_refresh_interval,
+        );
+    }
+
+    if !history_retention.is_zero() {
+        retention::spawn(state.pool.clone(), history_retention);
+    }
+
+    liveness::spawn(state.pool.clone(), state.providers.clone(), state.liveness);
+    outbox::spawn(state.pool.clone(), state.providers.clone());
+
     let cors = CorsLayer::new()
         .allow_origin(frontend_url.parse::<axum::http::HeaderValue>().unwrap())
         .allow_methods([
@@ -93,8 +224,18 @@ async fn main() {
 
     let app = Router::new()
         .route("/health", get(health))
+        .route("/openapi.json", get(openapi_json))
+        .merge(Scalar::with_url("/docs", ApiDoc::openapi()))
+        .route("/metrics", get(get_metrics))
+        .route("/ip", get(echo_ip))
         .route("/records", get(list_dns_records))
+        .route("/zones/refresh", post(refresh_dns_zones))
+        .route("/zones/{id}/provider-records", get(list_provider_records))
+        .route("/records/{id}/history", get(get_record_history))
+        .route("/operations/{id}", get(get_provider_operation))
         .route("/record", post(add_dns_record))
+        .route("/record", put(update_dns_record))
+        .route("/record/adopt", post(adopt_dns_record))
         .route("/record", delete(delete_dns_record))
         .route("/access_tokens", get(get_dns_access_tokens))
         .route("/access_token", post(add_dns_access_token))
@@ -102,16 +243,33 @@ async fn main() {
         .route("/api_keys", get(get_api_keys))
         .route("/api_key", post(add_api_key))
         .route("/api_key", delete(delete_api_key))
+        .route("/webhooks", get(get_webhooks))
+        .route("/webhook", post(add_webhook))
+        .route("/webhook", delete(delete_webhook))
+        .route("/webhooks/{id}/deliveries", get(get_webhook_deliveries))
         .route("/sync", put(sync_record))
+        .route("/nic/update", get(nic_update))
+        .route_layer(middleware::from_fn(metrics::track_http))
         .with_state(state)
         .layer(cors);
 
     tracing::info!("listening on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
 
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "server",
+    responses((status = 200, description = "The server is up", body = String))
+)]
 async fn health() -> impl IntoResponse {
     (
         StatusCode::OK,
@@ -119,12 +277,78 @@ async fn health() -> impl IntoResponse {
     )
 }
 
+/// The OpenAPI document of this API, also rendered at /docs
+async fn openapi_json() -> impl IntoResponse {
+    Json(ApiDoc::openapi())
+}
+
+/// Prometheus scrape endpoint. When METRICS_TOKEN is set scrapers have to send it as a bearer token.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "server",
+    responses(
+        (status = 200, description = "Prometheus text format", body = String, content_type = "text/plain"),
+        (status = 401, description = "METRICS_TOKEN is set and wasn't sent", body = ErrorBody)
+    )
+)]
+async fn get_metrics(headers: HeaderMap) -> impl IntoResponse {
+    if let Ok(token) = env::var("METRICS_TOKEN") {
+        let sent = headers
+            .get("authorization")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|s| s.strip_prefix("Bearer "))
+            .unwrap_or_default();
+
+        if !bool::from(sent.as_bytes().ct_eq(token.as_bytes())) {
+            return ApiError::Unauthorized("Invalid metrics token".to_string()).into_response();
+        }
+    }
+
+    match metrics::render() {
+        Ok(body) => (
+            StatusCode::OK,
+            [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+            body,
+        )
+            .into_response(),
+        Err(e) => ApiError::Internal(e).into_response(),
+    }
+}
+
+/// Plain text echo of the caller's public address, used by the client as its IP source
+#[utoipa::path(
+    get,
+    path = "/ip",
+    tag = "server",
+    responses((status = 200, description = "The caller's address", body = String, content_type = "text/plain"))
+)]
+async fn echo_ip(ClientIp(client_ip): ClientIp) -> impl IntoResponse {
+    (StatusCode::OK, client_ip.to_string())
+}
+
+#[utoipa::path(
+    put,
+    path = "/sync",
+    tag = "sync",
+    request_body = SyncRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "Record synced or unchanged", body = SyncResponse),
+        (status = 202, description = "The provider failed, the change is queued and retried", body = ErrorBody),
+        (status = 400, description = "No address to sync for the record", body = ErrorBody),
+        (status = 401, description = "Invalid API key", body = ErrorBody),
+        (status = 404, description = "The key has no record", body = ErrorBody),
+        (status = 502, description = "The DNS provider failed", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
 async fn sync_record(
     State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
     headers: HeaderMap,
     Json(body): Json<SyncRequest>,
 ) -> impl IntoResponse {
-    let ip_addr = body.ip_address;
     let time_synced = body.time_synced;
 
     let api_key = match headers
@@ -134,80 +358,372 @@ async fn sync_record(
     {
         Some(key) => key,
         None => {
-            return (
-                StatusCode::UNAUTHORIZED,
-                Json(SyncResponse {
-                    success: false,
-                    updated: false,
-                    message: "Missing or invalid Authorization header (expected Bearer token)"
-                        .to_string(),
-                }),
+            count_sync(SyncMetric::AuthFailure);
+            return ApiError::InvalidApiKey(
+                "Missing or invalid Authorization header (expected Bearer token)".to_string(),
             )
-                .into_response();
+            .into_response();
         }
     };
 
     // Only accept API keys (format: dgo_<prefix>_<secret>)
     if !api_key.starts_with("dgo_") {
-        return (
-            StatusCode::UNAUTHORIZED,
+        count_sync(SyncMetric::AuthFailure);
+        return ApiError::InvalidApiKey(
+            "Invalid API key format. Expected dgo_<prefix>_<secret>".to_string(),
+        )
+        .into_response();
+    }
+
+    let addresses = match SyncAddresses::from_request(&body, client_ip) {
+        Ok(addresses) => addresses,
+        Err(message) => {
+            count_sync(SyncMetric::BadRequest);
+            return ApiError::Invalid(message).into_response();
+        }
+    };
+
+    let result = sync_with_api_key(&state, api_key, None, &addresses, &time_synced).await;
+    count_sync(SyncMetric::from(&result));
+
+    match result {
+        Ok(SyncOutcome::Unchanged) => (
+            StatusCode::OK,
             Json(SyncResponse {
-                success: false,
+                success: true,
                 updated: false,
-                message: "Invalid API key format. Expected dgo_<prefix>_<secret>".to_string(),
+                message: "Record unchanged".to_string(),
+                sync_interval_secs: Some(state.liveness.sync_interval.as_secs()),
             }),
         )
-            .into_response();
+            .into_response(),
+        Ok(SyncOutcome::Updated) => (
+            StatusCode::OK,
+            Json(SyncResponse {
+                success: true,
+                updated: true,
+                message: "Record synced successfully".to_string(),
+                sync_interval_secs: Some(state.liveness.sync_interval.as_secs()),
+            }),
+        )
+            .into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+/// DynDNS2 compatible endpoint so routers can sync without the drago client.
+/// The API key is sent as the HTTP Basic password, the username is ignored.
+#[utoipa::path(
+    get,
+    path = "/nic/update",
+    tag = "sync",
+    params(NicUpdateParams),
+    security(("basic" = [])),
+    responses(
+        (status = 200, description = "A DynDNS2 result line per hostname: good, nochg, nohost, notfqdn or 911", body = String, content_type = "text/plain"),
+        (status = 401, description = "badauth", body = String, content_type = "text/plain")
+    )
+)]
+async fn nic_update(
+    State(state): State<AppState>,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
+    Query(params): Query<NicUpdateParams>,
+) -> impl IntoResponse {
+    let api_key = match headers
+        .get("authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(basic_auth_password)
+    {
+        Some(key) if key.starts_with("dgo_") => key,
+        _ => {
+            count_sync(SyncMetric::AuthFailure);
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(WWW_AUTHENTICATE, "Basic realm=\"drago\"")],
+                "badauth".to_string(),
+            )
+                .into_response();
+        }
+    };
+
+    let hostnames = match params.hostname {
+        Some(hostnames) if !hostnames.trim().is_empty() => hostnames,
+        _ => return (StatusCode::OK, "notfqdn".to_string()).into_response(),
+    };
+
+    // Routers that don't know their public address leave myip out, use the caller's.
+    // Dual stack routers send "myip=<v4>,<v6>" or a separate myipv6.
+    let inferred = params.myip.is_none().then_some(client_ip);
+    let ip_list = params.myip.unwrap_or_else(|| client_ip.to_string());
+    let mut addresses = match SyncAddresses::from_list(&ip_list) {
+        Ok(addresses) => addresses,
+        Err(_) => return (StatusCode::OK, "911".to_string()).into_response(),
+    };
+    addresses.inferred = inferred;
+
+    if let Some(ipv6) = params.myipv6.as_deref() {
+        match ipv6.trim().parse::<Ipv6Addr>() {
+            Ok(ip) => {
+                addresses.ipv6 = Some(ip);
+                addresses.inferred = inferred.filter(IpAddr::is_ipv4);
+            }
+            Err(_) => return (StatusCode::OK, "911".to_string()).into_response(),
+        }
+    }
+
+    let ip_addr = addresses.to_string();
+    let time_synced = chrono::Utc::now().naive_utc();
+
+    // Several hostnames can be sent at once, each gets its own result line
+    let mut results = Vec::new();
+    for hostname in hostnames.split(',').map(str::trim) {
+        let result =
+            sync_with_api_key(&state, &api_key, Some(hostname), &addresses, &time_synced).await;
+        count_sync(SyncMetric::from(&result));
+
+        let line = match result {
+            Ok(SyncOutcome::Updated) => format!("good {}", ip_addr),
+            Ok(SyncOutcome::Unchanged) => format!("nochg {}", ip_addr),
+            Err(ApiError::InvalidApiKey(_)) => "badauth".to_string(),
+            Err(ApiError::NotFound(_) | ApiError::HostMismatch(_)) => "nohost".to_string(),
+            Err(err) => {
+                tracing::warn!("DynDNS update for {} failed: {}", hostname, err.code());
+                "911".to_string()
+            }
+        };
+
+        results.push(line);
+    }
+
+    (StatusCode::OK, results.join("\n")).into_response()
+}
+
+/// Pull the password out of a `Basic base64(user:password)` Authorization header
+fn basic_auth_password(header: &str) -> Option<String> {
+    let encoded = header.strip_prefix("Basic ")?.trim();
+    let decoded = STANDARD.decode(encoded).ok()?;
+    let credentials = String::from_utf8(decoded).ok()?;
+    let (_, password) = credentials.split_once(':')?;
+
+    Some(password.to_string())
+}
+
+/// The addresses a client reported, at most one per family
+struct SyncAddresses {
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+    // The address the request came from, when it stood in for one the client didn't send
+    inferred: Option<IpAddr>,
+}
+
+impl SyncAddresses {
+    /// Explicit ipv4/ipv6 fields win, the older ip_address fills in whichever family it is.
+    /// A request without any address syncs the address the request came from.
+    fn from_request(body: &SyncRequest, client_ip: IpAddr) -> Result<Self, String> {
+        let ipv4 = body
+            .ipv4
+            .as_deref()
+            .map(|ip| {
+                ip.trim()
+                    .parse::<Ipv4Addr>()
+                    .map_err(|_| format!("'{}' is not an IPv4 address", ip))
+            })
+            .transpose()?;
+
+        let ipv6 = body
+            .ipv6
+            .as_deref()
+            .map(|ip| {
+                ip.trim()
+                    .parse::<Ipv6Addr>()
+                    .map_err(|_| format!("'{}' is not an IPv6 address", ip))
+            })
+            .transpose()?;
+
+        let mut addresses = Self {
+            ipv4,
+            ipv6,
+            inferred: None,
+        };
+        if let Some(ip) = body.ip_address.as_deref() {
+            addresses.fill(ip)?;
+        }
+
+        if addresses.ipv4.is_none() && addresses.ipv6.is_none() {
+            addresses.fill(&client_ip.to_string())?;
+            addresses.inferred = Some(client_ip);
+        }
+
+        Ok(addresses)
+    }
+
+    /// Comma separated list, as used by DynDNS2 myip
+    fn from_list(list: &str) -> Result<Self, String> {
+        let mut addresses = Self {
+            ipv4: None,
+            ipv6: None,
+            inferred: None,
+        };
+
+        for ip in list.split(',').filter(|ip| !ip.trim().is_empty()) {
+            addresses.fill(ip)?;
+        }
+
+        if addresses.ipv4.is_none() && addresses.ipv6.is_none() {
+            return Err("No IP address to sync".to_string());
+        }
+
+        Ok(addresses)
+    }
+
+    fn fill(&mut self, ip: &str) -> Result<(), String> {
+        match ip.trim().parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                self.ipv4.get_or_insert(ip);
+            }
+            Ok(IpAddr::V6(ip)) => {
+                self.ipv6.get_or_insert(ip);
+            }
+            Err(_) => return Err(format!("'{}' is not an IP address", ip)),
+        }
+
+        Ok(())
+    }
+
+    /// Whether `ip` is only here because the request came from it
+    fn is_inferred(&self, ip: &str) -> bool {
+        self.inferred
+            .is_some_and(|inferred| inferred.to_string() == ip)
+    }
+
+    /// The content a record of this type should hold
+    fn for_record_type(&self, record_type: &str) -> Option<String> {
+        match record_type {
+            "A" => self.ipv4.map(|ip| ip.to_string()),
+            "AAAA" => self.ipv6.map(|ip| ip.to_string()),
+            // Anything else keeps the old behaviour of taking whatever address we got
+            _ => self
+                .ipv4
+                .map(|ip| ip.to_string())
+                .or_else(|| self.ipv6.map(|ip| ip.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for SyncAddresses {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ips: Vec<String> = [
+            self.ipv4.map(|ip| ip.to_string()),
+            self.ipv6.map(|ip| ip.to_string()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        write!(f, "{}", ips.join(","))
     }
+}
 
-    sync_with_api_key(&state, api_key, &ip_addr, &time_synced).await
+// The record type holding the other address family for the same name
+fn sibling_record_type(record_type: &str) -> Option<&'static str> {
+    match record_type {
+        "A" => Some("AAAA"),
+        "AAAA" => Some("A"),
+        _ => None,
+    }
+}
+
+enum SyncOutcome {
+    Unchanged,
+    Updated,
+}
+
+// Stored in sync_event.outcome, one row per record a sync touched
+enum SyncEventOutcome {
+    Updated,
+    Unchanged,
+    Error,
+}
+
+impl SyncEventOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SyncEventOutcome::Updated => "updated",
+            SyncEventOutcome::Unchanged => "unchanged",
+            SyncEventOutcome::Error => "error",
+        }
+    }
 }
 
-/// Sync using API key - updates the specific record linked to the key
+// Label values of drago_sync_requests_total
+enum SyncMetric {
+    Updated,
+    Unchanged,
+    AuthFailure,
+    NoHost,
+    BadRequest,
+    ProviderError,
+    InternalError,
+}
+
+impl From<&Result<SyncOutcome, ApiError>> for SyncMetric {
+    fn from(result: &Result<SyncOutcome, ApiError>) -> Self {
+        match result {
+            Ok(SyncOutcome::Updated) => SyncMetric::Updated,
+            Ok(SyncOutcome::Unchanged) => SyncMetric::Unchanged,
+            Err(ApiError::InvalidApiKey(_)) => SyncMetric::AuthFailure,
+            Err(ApiError::NotFound(_) | ApiError::HostMismatch(_)) => SyncMetric::NoHost,
+            Err(ApiError::Invalid(_)) => SyncMetric::BadRequest,
+            Err(ApiError::Provider(_) | ApiError::Queued { .. }) => SyncMetric::ProviderError,
+            Err(_) => SyncMetric::InternalError,
+        }
+    }
+}
+
+fn count_sync(outcome: SyncMetric) {
+    let label = match outcome {
+        SyncMetric::Updated => "updated",
+        SyncMetric::Unchanged => "unchanged",
+        SyncMetric::AuthFailure => "auth_failure",
+        SyncMetric::NoHost => "no_host",
+        SyncMetric::BadRequest => "bad_request",
+        SyncMetric::ProviderError => "provider_error",
+        SyncMetric::InternalError => "internal_error",
+    };
+
+    metrics::SYNC_REQUESTS.with_label_values(&[label]).inc();
+}
+
+/// Sync using API key - updates the specific record linked to the key, plus its A/AAAA
+/// sibling when the client reports both address families.
+/// When a hostname is given it has to match the record the key controls.
 async fn sync_with_api_key(
     state: &AppState,
     api_key: &str,
-    ip_addr: &str,
+    hostname: Option<&str>,
+    addresses: &SyncAddresses,
     time_synced: &NaiveDateTime,
-) -> axum::response::Response {
-    let key_parts: Vec<&str> = api_key.split("_").collect();
+) -> Result<SyncOutcome, ApiError> {
+    let key_parts: Vec<&str> = api_key.splitn(3, '_').collect();
 
-    let public_prefix = match key_parts.get(1) {
-        Some(key) => key,
-        None => {
-            return (
-                StatusCode::UNAUTHORIZED,
-                Json(SyncResponse {
-                    success: false,
-                    updated: false,
-                    message: "Malformed API key.".to_string(),
-                }),
-            )
-                .into_response();
-        }
+    let public_prefix = match key_parts.as_slice() {
+        ["dgo", prefix, secret] if !prefix.is_empty() && !secret.is_empty() => *prefix,
+        _ => return Err(ApiError::InvalidApiKey("Malformed API key".to_string())),
     };
 
-    let conn = &mut state.pool.get().expect("Failed to get DB connection");
+    let digest = api_key_digest(api_key)
+        .map_err(|e| ApiError::Internal(format!("Failed to hash API key: {}", e)))?;
 
-    // query the db for that value
-    let api_key_query_response = api_keys::table
-        .select(api_keys::id)
-        .filter(api_keys::prefix_id.eq(public_prefix))
-        .first::<String>(conn);
+    let conn = &mut state.pool.get().expect("Failed to get DB connection");
 
     // Well use this id to update the api key entry for last used
-    let api_key_id = match api_key_query_response {
-        Ok(id) => id,
-        Err(_) => {
-            return (
-                StatusCode::UNAUTHORIZED,
-                Json(SyncResponse {
-                    success: false,
-                    updated: false,
-                    message: "Invalid authorization".to_string(),
-                }),
-            )
-                .into_response();
+    let api_key_id = match state.api_key_cache.get(public_prefix, &digest).await {
+        Some(id) => id,
+        None => {
+            let id = verify_api_key_row(conn, api_key, public_prefix)?;
+            state.api_key_cache.insert(public_prefix, &id, digest).await;
+            id
         }
     };
 
@@ -224,152 +740,481 @@ async fn sync_with_api_key(
             dns_record::record_name,
             dns_record::ttl,
             dns_record::record_type,
+            dns_record::proxied,
+            dns_record::fallback_active,
         ))
         .first::<models::PutDnsRecord>(conn);
 
     let connected_record_data = match connected_record {
         Ok(data) => data,
-        Err(_) => {
-            return (
-                StatusCode::NOT_FOUND,
-                Json(SyncResponse {
-                    success: false,
-                    updated: false,
-                    message: "No record accociated to API key".to_string(),
-                }),
-            )
-                .into_response();
+        Err(_) => return Err(ApiError::NotFound(Resource::Record)),
+    };
+
+    if let Some(hostname) = hostname {
+        if !hostname.eq_ignore_ascii_case(&connected_record_data.record_name) {
+            return Err(ApiError::HostMismatch(hostname.to_string()));
         }
+    }
+
+    // The client is alive even when the provider is down and its change has to be queued,
+    // otherwise liveness would switch the record to its fallback behind the client's back
+    mark_client_seen(conn, &connected_record_data);
+
+    if let Err(e) = diesel::update(api_keys::table.filter(api_keys::id.eq(&api_key_id)))
+        .set(api_keys::last_used.eq(time_synced))
+        .execute(conn)
+    {
+        tracing::warn!(
+            "Failed to update last_used for API key {}: {}",
+            api_key_id,
+            e
+        );
+    }
+
+    let primary_ip = addresses.for_record_type(&connected_record_data.record_type);
+    // An address taken from the connection only ever goes in the key's own record, it says
+    // nothing about the other family of a dual stack client
+    let sibling = match sibling_record_type(&connected_record_data.record_type) {
+        Some(sibling_type) => addresses
+            .for_record_type(sibling_type)
+            .filter(|ip| !addresses.is_inferred(ip))
+            .map(|ip| (sibling_type, ip)),
+        None => None,
     };
 
-    // Check if the incoming ip is the same as the existing one.
-    if connected_record_data.content == ip_addr {
-        (
-            StatusCode::OK,
-            Json(SyncResponse {
-                success: true,
-                updated: false,
-                message: "Record unchanged".to_string(),
-            }),
-        )
-            .into_response()
-    } else {
-        // If not, post to cloudlfare with the new ip
-        let decrypted_token = match get_user_token(conn, &connected_record_data.user_id) {
-            Ok(token) => token,
-            Err(_) => {
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(SyncResponse {
-                        success: false,
-                        updated: false,
-                        message: "Failed to get user token".to_string(),
-                    }),
-                )
-                    .into_response();
-            }
+    if primary_ip.is_none() && sibling.is_none() {
+        let message = match addresses.inferred {
+            Some(ip) => format!(
+                "The request came from {}, which can't go in a {} record. Send the address to sync.",
+                ip, connected_record_data.record_type
+            ),
+            None => format!(
+                "No address to sync for a {} record",
+                connected_record_data.record_type
+            ),
         };
+        record_sync_event(
+            conn,
+            models::NewSyncEvent {
+                dns_record_id: &connected_record_data.id,
+                api_key_id: Some(&api_key_id),
+                old_content: Some(&connected_record_data.content),
+                new_content: None,
+                outcome: SyncEventOutcome::Error.as_str(),
+                provider_response: Some(&message),
+            },
+        );
+        return Err(ApiError::Invalid(message));
+    }
+
+    // The sibling isn't tied to the key, find it by name and type
+    let sibling = match sibling {
+        Some((sibling_type, ip)) => {
+            let existing = dns_record::table
+                .filter(dns_record::user_id.eq(&connected_record_data.user_id))
+                .filter(dns_record::zone_id.eq(&connected_record_data.zone_id))
+                .filter(dns_record::record_name.eq(&connected_record_data.record_name))
+                .filter(dns_record::record_type.eq(sibling_type))
+                .select((
+                    dns_record::user_id,
+                    dns_record::id,
+                    dns_record::zone_id,
+                    dns_record::content,
+                    dns_record::record_name,
+                    dns_record::ttl,
+                    dns_record::record_type,
+                    dns_record::proxied,
+                    dns_record::fallback_active,
+                ))
+                .first::<models::PutDnsRecord>(conn)
+                .optional()?;
 
-        let client = reqwest::Client::new();
-        let url = format!(
-            "https://api.cloudflare.com/client/v4/zones/{}/dns_records/{}",
-            connected_record_data.zone_id, connected_record_data.id
+            Some((sibling_type, existing, ip))
+        }
+        None => None,
+    };
+
+    // Check if the incoming ips are the same as the existing ones.
+    // A record serving its offline fallback is pushed again even when the ip is the same.
+    let primary_changed = primary_ip.as_ref().is_some_and(|ip| {
+        *ip != connected_record_data.content || connected_record_data.fallback_active
+    });
+    let sibling_changed = sibling.as_ref().is_some_and(|(_, existing, ip)| {
+        existing
+            .as_ref()
+            .is_none_or(|record| record.content != *ip || record.fallback_active)
+    });
+
+    // Records that were reported but already point at the right address
+    let unchanged = [
+        primary_ip
+            .as_ref()
+            .filter(|_| !primary_changed)
+            .map(|_| &connected_record_data),
+        sibling
+            .as_ref()
+            .filter(|_| !sibling_changed)
+            .and_then(|(_, existing, _)| existing.as_ref()),
+    ];
+    for record in unchanged.into_iter().flatten() {
+        record_sync_event(
+            conn,
+            models::NewSyncEvent {
+                dns_record_id: &record.id,
+                api_key_id: Some(&api_key_id),
+                old_content: Some(&record.content),
+                new_content: Some(&record.content),
+                outcome: SyncEventOutcome::Unchanged.as_str(),
+                provider_response: None,
+            },
         );
+    }
 
-        let payload = PutDnsRecordPayload {
-            // We only want to change the content, but type, name and ttl are required on the endpoint
-            r#type: &connected_record_data.record_type,
-            name: &connected_record_data.record_name,
-            // The content is all that really changes in this call
-            content: ip_addr,
-            ttl: &connected_record_data.ttl,
-        };
+    if !primary_changed && !sibling_changed {
+        return Ok(SyncOutcome::Unchanged);
+    }
 
-        let resp = match client
-            .put(&url)
-            .bearer_auth(&decrypted_token)
-            .json(&payload)
-            .send()
-            .await
-        {
-            Ok(r) => r,
-            Err(e) => {
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(SyncResponse {
-                        success: false,
-                        updated: false,
-                        message: e.to_string(),
-                    }),
+    // If not, push the new ips to the DNS provider
+    let provider = match get_zone_provider(
+        conn,
+        &state.providers,
+        &connected_record_data.user_id,
+        &connected_record_data.zone_id,
+    ) {
+        Ok(provider) => provider,
+        Err(e) => {
+            record_sync_event(
+                conn,
+                models::NewSyncEvent {
+                    dns_record_id: &connected_record_data.id,
+                    api_key_id: Some(&api_key_id),
+                    old_content: Some(&connected_record_data.content),
+                    new_content: primary_ip.as_deref(),
+                    outcome: SyncEventOutcome::Error.as_str(),
+                    provider_response: Some(&e),
+                },
+            );
+            return Err(ApiError::Internal(format!(
+                "Failed to get user token: {}",
+                e
+            )));
+        }
+    };
+
+    if let Some(ip) = primary_ip.as_ref().filter(|_| primary_changed) {
+        push_record_content(
+            conn,
+            provider.as_ref(),
+            &api_key_id,
+            &connected_record_data,
+            ip,
+            time_synced,
+        )
+        .await?;
+
+        notify_ip_changed(
+            state,
+            &connected_record_data,
+            Some(&connected_record_data.content),
+            ip,
+        );
+    }
+
+    if let Some((sibling_type, existing, ip)) = sibling.filter(|_| sibling_changed) {
+        match existing {
+            Some(record) => {
+                push_record_content(
+                    conn,
+                    provider.as_ref(),
+                    &api_key_id,
+                    &record,
+                    &ip,
+                    time_synced,
                 )
-                    .into_response();
-            }
-        };
+                .await?;
 
-        let response = resp.json().await;
-
-        dbg!(&response);
-        let updated_dns_record_response_data: PutRecordResponse = match response {
-            Ok(result) => result,
-            Err(e) => {
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(SyncResponse {
-                        success: false,
-                        updated: false,
-                        message: e.to_string(),
-                    }),
+                notify_ip_changed(state, &record, Some(&record.content), &ip);
+            }
+            None => {
+                let created = create_sibling_record(
+                    conn,
+                    provider.as_ref(),
+                    &api_key_id,
+                    &connected_record_data,
+                    sibling_type,
+                    &ip,
+                    time_synced,
                 )
-                    .into_response();
+                .await?;
+
+                notify_ip_changed(
+                    state,
+                    &models::PutDnsRecord {
+                        user_id: connected_record_data.user_id.clone(),
+                        id: created.id,
+                        zone_id: connected_record_data.zone_id.clone(),
+                        content: created.content,
+                        record_name: created.name,
+                        ttl: created.ttl,
+                        record_type: created.record_type,
+                        proxied: created.proxied,
+                        fallback_active: false,
+                    },
+                    None,
+                    &ip,
+                );
             }
-        };
+        }
+    }
 
-        if updated_dns_record_response_data.success {
-            let update_result = diesel::update(dns_record::table)
-                .set((
-                    dns_record::content.eq(ip_addr),
-                    dns_record::last_synced_on.eq(time_synced),
-                ))
-                .filter(dns_record::user_id.eq(&connected_record_data.user_id))
-                .filter(dns_record::id.eq(&updated_dns_record_response_data.result.id))
-                .execute(conn);
+    Ok(SyncOutcome::Updated)
+}
 
-            match update_result {
-                Ok(_) => (
-                    // Return success
-                    StatusCode::OK,
-                    Json(SyncResponse {
-                        success: true,
-                        updated: true,
-                        message: "Record synced successfully".to_string(),
-                    }),
-                )
-                    .into_response(),
-                Err(err) => (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(SyncResponse {
-                        success: false,
-                        updated: false,
-                        message: format!("DB update failed: {}", err),
-                    }),
-                )
-                    .into_response(),
+/// Record that the client behind a name just synced, for both its A and AAAA records.
+/// Uses the server clock so a client with a wrong clock doesn't look offline.
+fn mark_client_seen(conn: &mut MysqlConnection, record: &models::PutDnsRecord) {
+    let result = diesel::update(
+        dns_record::table
+            .filter(dns_record::user_id.eq(&record.user_id))
+            .filter(dns_record::zone_id.eq(&record.zone_id))
+            .filter(dns_record::record_name.eq(&record.record_name)),
+    )
+    .set(dns_record::last_synced_on.eq(chrono::Utc::now().naive_utc()))
+    .execute(conn);
+
+    if let Err(e) = result {
+        tracing::warn!(
+            "Failed to update last sync of {}: {}",
+            record.record_name,
+            e
+        );
+    }
+}
+
+/// Tell the record owner's webhooks about a change the provider accepted
+fn notify_ip_changed(
+    state: &AppState,
+    record: &models::PutDnsRecord,
+    old_ip: Option<&str>,
+    new_ip: &str,
+) {
+    let event = RecordChangedEvent::new(
+        WebhookRecord {
+            id: record.id.clone(),
+            zone_id: record.zone_id.clone(),
+            name: record.record_name.clone(),
+            record_type: record.record_type.clone(),
+        },
+        old_ip,
+        new_ip,
+    );
+
+    state.webhooks.dispatch(&record.user_id, event);
+}
+
+/// Write one row of a record's sync history. A failed write is logged, it never fails the sync.
+fn record_sync_event(conn: &mut MysqlConnection, event: models::NewSyncEvent) {
+    let result = diesel::insert_into(sync_event::table)
+        .values((sync_event::id.eq(Uuid::now_v7().to_string()), &event))
+        .execute(conn);
+
+    if let Err(e) = result {
+        tracing::warn!(
+            "Failed to record sync event for {}: {}",
+            event.dns_record_id,
+            e
+        );
+    }
+}
+
+/// Point an existing record at a new ip, on the provider first and then in our DB
+async fn push_record_content(
+    conn: &mut MysqlConnection,
+    provider: &dyn DnsProvider,
+    api_key_id: &str,
+    record: &models::PutDnsRecord,
+    ip_addr: &str,
+    time_synced: &NaiveDateTime,
+) -> Result<(), ApiError> {
+    let payload = RecordPayload {
+        // We only want to change the content, but type, name and ttl are required by providers
+        record_type: &record.record_type,
+        name: &record.record_name,
+        // The content is all that really changes in this call
+        content: ip_addr,
+        ttl: record.ttl,
+        // Providers that overwrite the whole record would otherwise turn proxying off
+        proxied: Some(record.proxied),
+    };
+
+    let operation = RecordOperation::Update {
+        record_id: record.id.clone(),
+        payload: PayloadSpec::new(&payload),
+        stored: StoredChanges {
+            content: Some(ip_addr.to_string()),
+            fallback_active: Some(false),
+            last_synced_on: Some(*time_synced),
+            ..Default::default()
+        },
+    };
+
+    let result = outbox::submit(conn, provider, &record.user_id, &record.zone_id, operation).await;
+
+    let (outcome, provider_response) = match &result {
+        Ok(Completed::Record(updated)) => (
+            SyncEventOutcome::Updated,
+            serde_json::to_string(updated).ok(),
+        ),
+        Ok(_) => (SyncEventOutcome::Updated, None),
+        Err(e) => (SyncEventOutcome::Error, Some(e.to_string())),
+    };
+    record_sync_event(
+        conn,
+        models::NewSyncEvent {
+            dns_record_id: &record.id,
+            api_key_id: Some(api_key_id),
+            old_content: Some(&record.content),
+            new_content: Some(ip_addr),
+            outcome: outcome.as_str(),
+            provider_response: provider_response.as_deref(),
+        },
+    );
+
+    result.map(|_| ()).map_err(ApiError::from)
+}
+
+/// Create the missing A/AAAA twin of a synced record with the same name, ttl and proxying
+async fn create_sibling_record(
+    conn: &mut MysqlConnection,
+    provider: &dyn DnsProvider,
+    api_key_id: &str,
+    primary: &models::PutDnsRecord,
+    record_type: &str,
+    ip_addr: &str,
+    time_synced: &NaiveDateTime,
+) -> Result<DnsRecord, ApiError> {
+    let payload = RecordPayload {
+        record_type,
+        name: &primary.record_name,
+        content: ip_addr,
+        ttl: primary.ttl,
+        proxied: Some(primary.proxied),
+    };
+
+    let operation = RecordOperation::Create {
+        payload: PayloadSpec::new(&payload),
+        drift_policy: DEFAULT_DRIFT_POLICY.as_str().to_string(),
+        fallback_content: None,
+        last_synced_on: Some(*time_synced),
+    };
+
+    let created = match outbox::submit(
+        conn,
+        provider,
+        &primary.user_id,
+        &primary.zone_id,
+        operation,
+    )
+    .await
+    {
+        Ok(Completed::Record(created)) => created,
+        Ok(_) => {
+            return Err(ApiError::Internal(
+                "Sibling record was not created".to_string(),
+            ))
+        }
+        Err(e) => {
+            // The sibling doesn't exist yet, so the failure goes in the primary record's history
+            record_sync_event(
+                conn,
+                models::NewSyncEvent {
+                    dns_record_id: &primary.id,
+                    api_key_id: Some(api_key_id),
+                    old_content: None,
+                    new_content: Some(ip_addr),
+                    outcome: SyncEventOutcome::Error.as_str(),
+                    provider_response: Some(&format!("Creating {} record: {}", record_type, e)),
+                },
+            );
+            return Err(e.into());
+        }
+    };
+
+    record_sync_event(
+        conn,
+        models::NewSyncEvent {
+            dns_record_id: &created.id,
+            api_key_id: Some(api_key_id),
+            old_content: None,
+            new_content: Some(&created.content),
+            outcome: SyncEventOutcome::Updated.as_str(),
+            provider_response: serde_json::to_string(&created).ok().as_deref(),
+        },
+    );
+
+    Ok(created)
+}
+
+/// Look up the key by its public prefix and check the full key against the stored hash.
+/// Keys still stored with argon2 are rehashed with the HMAC scheme once they verify.
+fn verify_api_key_row(
+    conn: &mut MysqlConnection,
+    api_key: &str,
+    public_prefix: &str,
+) -> Result<String, ApiError> {
+    let (api_key_id, key_hash) = api_keys::table
+        .select((api_keys::id, api_keys::key_hash))
+        .filter(api_keys::prefix_id.eq(public_prefix))
+        .first::<(String, String)>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::InvalidApiKey("Invalid authorization".to_string()))?;
+
+    let key_match = verify_api_key(api_key, &key_hash)
+        .map_err(|e| ApiError::Internal(format!("Failed to verify API key: {}", e)))?;
+
+    match key_match {
+        ApiKeyMatch::Valid => {}
+        ApiKeyMatch::ValidLegacy => {
+            // Not fatal, the key is valid and we'll try again on the next sync
+            let rehashed = hash_api_key(api_key)
+                .map_err(|e| e.to_string())
+                .and_then(|hash| {
+                    diesel::update(api_keys::table.filter(api_keys::id.eq(&api_key_id)))
+                        .set(api_keys::key_hash.eq(hash))
+                        .execute(conn)
+                        .map_err(|e| e.to_string())
+                });
+
+            if let Err(e) = rehashed {
+                tracing::warn!("Failed to rehash API key {}: {}", api_key_id, e);
             }
-        } else {
-            (
-                StatusCode::OK,
-                Json(SyncResponse {
-                    success: false,
-                    updated: false,
-                    message: "DNS provider error".to_string(),
-                }),
-            )
-                .into_response()
+        }
+        ApiKeyMatch::Invalid => {
+            return Err(ApiError::InvalidApiKey("Invalid authorization".to_string()));
         }
     }
+
+    Ok(api_key_id)
 }
 
 // DNS Record Controls
+#[utoipa::path(
+    post,
+    path = "/record",
+    tag = "records",
+    request_body = AddDnsRecord,
+    security(("session" = [])),
+    responses(
+        (status = 201, description = "Record created", body = String),
+        (status = 202, description = "The provider failed, the change is queued and retried", body = ErrorBody),
+        (status = 400, description = "Invalid drift policy or fallback", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Zone not found", body = ErrorBody),
+        (status = 409, description = "The record exists, here or at the provider", body = ErrorBody),
+        (status = 502, description = "The DNS provider failed", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
 async fn add_dns_record(
     State(state): State<AppState>,
     User(claims): User,
@@ -377,183 +1222,768 @@ async fn add_dns_record(
 ) -> impl IntoResponse {
     let user_id = claims.sub;
     let zone_id = body.zone_id;
-    let zone_name = body.zone_name;
     let record_type = body.record_type;
     let name = body.name;
     let content = body.content;
     let ttl = body.ttl;
     let proxied = body.proxied;
 
-    let conn = &mut state.pool.get().expect("Failed to get DB connection"); // TODO: Dont save the full url as we reference the zone name in the record by id
-    let subdomain = format!("{}.{}", name, zone_name);
+    let drift_policy = match body.drift_policy.as_deref() {
+        None => DEFAULT_DRIFT_POLICY,
+        Some(policy) => match DriftPolicy::parse(policy) {
+            Some(policy) => policy,
+            None => {
+                return ApiError::Invalid(
+                    "drift_policy must be one of ignore, log or repair".to_string(),
+                )
+                .into_response();
+            }
+        },
+    };
+
+    // A CNAME can stand in for anything, an address has to match the record's family
+    if let Some(fallback) = body.fallback_content.as_deref() {
+        let fallback_type = fallback_record_type(fallback);
+        if fallback_type != "CNAME" && fallback_type != record_type {
+            return ApiError::Invalid(format!(
+                "fallback_content must be a hostname or an {} address",
+                record_type
+            ))
+            .into_response();
+        }
+    }
+
+    let conn = &mut state.pool.get().expect("Failed to get DB connection"); // TODO: Dont save the full url as we reference the zone name in the record by id
+
+    let zone_name = match owned_zone(conn, &user_id, &zone_id) {
+        Ok(zone_name) => zone_name,
+        Err(err) => return err.into_response(),
+    };
+    let subdomain = format!("{}.{}", name, zone_name);
+
+    let result = dns_record::table
+        .select(dns_record::id)
+        .filter(dns_record::user_id.eq(&user_id))
+        .filter(dns_record::zone_id.eq(&zone_id))
+        .filter(dns_record::record_name.eq(&subdomain))
+        .first::<String>(conn)
+        .optional();
+
+    let existing_record_id = match result {
+        Ok(v) => v,
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+
+    if existing_record_id.is_some() {
+        return ApiError::RecordExists.into_response();
+    }
+
+    let provider = match get_zone_provider(conn, &state.providers, &user_id, &zone_id) {
+        Ok(provider) => provider,
+        Err(e) => return ApiError::Internal(e).into_response(),
+    };
+
+    // Creating it again would leave two records with the same name at the provider
+    match provider.list_records(&zone_id).await {
+        Ok(existing) => {
+            if let Some(existing) = existing.iter().find(|existing| {
+                existing.name.eq_ignore_ascii_case(&subdomain)
+                    && existing.record_type == record_type
+            }) {
+                return ApiError::RecordAtProvider {
+                    name: subdomain,
+                    record_id: existing.id.clone(),
+                }
+                .into_response();
+            }
+        }
+        Err(e) => tracing::warn!("Failed to list records of zone {}: {}", zone_id, e),
+    }
+
+    let payload = RecordPayload {
+        record_type: &record_type,
+        name: &subdomain,
+        content: &content,
+        ttl,
+        proxied: Some(proxied),
+    };
+
+    let operation = RecordOperation::Create {
+        payload: PayloadSpec::new(&payload),
+        drift_policy: drift_policy.as_str().to_string(),
+        fallback_content: body.fallback_content,
+        last_synced_on: None,
+    };
+
+    match outbox::submit(conn, provider.as_ref(), &user_id, &zone_id, operation).await {
+        Ok(_) => (StatusCode::CREATED, Json("Record added successfully")).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Records in one of the user's zones that exist at the provider but aren't managed by Drago yet
+#[utoipa::path(
+    get,
+    path = "/zones/{id}/provider-records",
+    tag = "zones",
+    params(("id" = String, Path, description = "Zone id")),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Records at the provider Drago doesn't manage", body = Vec<DnsRecord>),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Zone not found", body = ErrorBody),
+        (status = 502, description = "The DNS provider failed", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
+async fn list_provider_records(
+    State(state): State<AppState>,
+    User(claims): User,
+    Path(zone_id): Path<String>,
+) -> impl IntoResponse {
+    let user_id = claims.sub;
+    let conn = &mut state.pool.get().expect("Failed to get DB connection");
+
+    if let Err(err) = owned_zone(conn, &user_id, &zone_id) {
+        return err.into_response();
+    }
+
+    let provider = match get_zone_provider(conn, &state.providers, &user_id, &zone_id) {
+        Ok(provider) => provider,
+        Err(e) => return ApiError::Internal(e).into_response(),
+    };
+
+    let records = match provider.list_records(&zone_id).await {
+        Ok(records) => records,
+        Err(e) => return ApiError::Provider(e).into_response(),
+    };
+
+    let managed: HashSet<String> = match dns_record::table
+        .filter(dns_record::zone_id.eq(&zone_id))
+        .select(dns_record::id)
+        .load::<String>(conn)
+    {
+        Ok(ids) => ids.into_iter().collect(),
+        Err(err) => {
+            return ApiError::from(err).into_response();
+        }
+    };
+
+    let unmanaged: Vec<DnsRecord> = records
+        .into_iter()
+        .filter(|record| !managed.contains(&record.id))
+        .collect();
+
+    (StatusCode::OK, Json(unmanaged)).into_response()
+}
+
+/// Start managing a record that already exists at the provider, optionally with an API key
+/// for it. Nothing is written to the provider.
+#[utoipa::path(
+    post,
+    path = "/record/adopt",
+    tag = "records",
+    request_body = AdoptDnsRecord,
+    security(("session" = [])),
+    responses(
+        (status = 201, description = "Record adopted, with its API key when one was asked for", body = AdoptedDnsRecord),
+        (status = 400, description = "Invalid drift policy or fallback", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Zone or provider record not found", body = ErrorBody),
+        (status = 409, description = "Record already managed", body = ErrorBody),
+        (status = 502, description = "The DNS provider failed", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
+async fn adopt_dns_record(
+    State(state): State<AppState>,
+    User(claims): User,
+    Json(body): Json<AdoptDnsRecord>,
+) -> impl IntoResponse {
+    let user_id = claims.sub;
+
+    let drift_policy = match body.drift_policy.as_deref() {
+        None => DEFAULT_DRIFT_POLICY,
+        Some(policy) => match DriftPolicy::parse(policy) {
+            Some(policy) => policy,
+            None => {
+                return ApiError::Invalid(
+                    "drift_policy must be one of ignore, log or repair".to_string(),
+                )
+                .into_response();
+            }
+        },
+    };
+
+    let conn = &mut state.pool.get().expect("Failed to get DB connection");
+
+    if let Err(err) = owned_zone(conn, &user_id, &body.zone_id) {
+        return err.into_response();
+    }
+
+    let managed = dns_record::table
+        .filter(dns_record::id.eq(&body.record_id))
+        .count()
+        .get_result::<i64>(conn);
+
+    match managed {
+        Ok(0) => {}
+        Ok(_) => return ApiError::RecordAlreadyManaged.into_response(),
+        Err(err) => {
+            return ApiError::from(err).into_response();
+        }
+    }
+
+    let provider = match get_zone_provider(conn, &state.providers, &user_id, &body.zone_id) {
+        Ok(provider) => provider,
+        Err(e) => return ApiError::Internal(e).into_response(),
+    };
+
+    let record = match provider.get_record(&body.zone_id, &body.record_id).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return ApiError::NotFound(Resource::ProviderRecord).into_response(),
+        Err(e) => return ApiError::Provider(e).into_response(),
+    };
+
+    if let Some(fallback) = body.fallback_content.as_deref() {
+        let fallback_type = fallback_record_type(fallback);
+        if fallback_type != "CNAME" && fallback_type != record.record_type {
+            return ApiError::Invalid(format!(
+                "fallback_content must be a hostname or an {} address",
+                record.record_type
+            ))
+            .into_response();
+        }
+    }
+
+    // Syncs find a record's sibling by name and type, so those have to stay unique
+    let duplicate = dns_record::table
+        .filter(dns_record::user_id.eq(&user_id))
+        .filter(dns_record::zone_id.eq(&body.zone_id))
+        .filter(dns_record::record_name.eq(&record.name))
+        .filter(dns_record::record_type.eq(&record.record_type))
+        .count()
+        .get_result::<i64>(conn);
+
+    match duplicate {
+        Ok(0) => {}
+        Ok(_) => return ApiError::RecordExists.into_response(),
+        Err(err) => {
+            return ApiError::from(err).into_response();
+        }
+    }
+
+    let api_key = match body.api_key_name.as_deref() {
+        Some(name) => {
+            let (full_api_key, public_id, _secret) = generate_api_key();
+            match hash_api_key(&full_api_key) {
+                Ok(hash) => Some((name, full_api_key, public_id, hash)),
+                Err(err) => {
+                    return ApiError::Internal(err.to_string()).into_response();
+                }
+            }
+        }
+        None => None,
+    };
+
+    let result = conn.transaction(|conn| {
+        diesel::insert_into(dns_record::table)
+            .values((
+                dns_record::id.eq(&record.id),
+                dns_record::user_id.eq(&user_id),
+                dns_record::record_name.eq(&record.name),
+                dns_record::zone_id.eq(&body.zone_id),
+                dns_record::content.eq(&record.content),
+                dns_record::ttl.eq(&record.ttl),
+                dns_record::record_type.eq(&record.record_type),
+                dns_record::proxied.eq(&record.proxied),
+                dns_record::drift_policy.eq(drift_policy.as_str()),
+                dns_record::fallback_content.eq(&body.fallback_content),
+            ))
+            .execute(conn)?;
+
+        if let Some((name, _, public_id, hash)) = &api_key {
+            diesel::insert_into(api_keys::table)
+                .values((
+                    api_keys::id.eq(Uuid::now_v7().to_string()),
+                    api_keys::name.eq(name),
+                    api_keys::prefix_id.eq(public_id),
+                    api_keys::key_hash.eq(hash),
+                    api_keys::dns_record_id.eq(&record.id),
+                    api_keys::user_id.eq(&user_id),
+                ))
+                .execute(conn)?;
+        }
+
+        Ok::<_, diesel::result::Error>(())
+    });
+
+    match result {
+        Ok(_) => (
+            StatusCode::CREATED,
+            Json(AdoptedDnsRecord {
+                record,
+                api_key: api_key.map(|(_, full_api_key, _, _)| full_api_key),
+            }),
+        )
+            .into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Change a record in place. The API key controlling it stays linked, even when the provider
+/// gives the edited record a new id.
+#[utoipa::path(
+    put,
+    path = "/record",
+    tag = "records",
+    request_body = UpdateDnsRecord,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The record as it is now", body = DnsRecord),
+        (status = 202, description = "The provider failed, the change is queued and retried", body = ErrorBody),
+        (status = 400, description = "The content or fallback doesn't fit the type", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Record not found", body = ErrorBody),
+        (status = 409, description = "Another record is in the way", body = ErrorBody),
+        (status = 502, description = "The DNS provider failed", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
+async fn update_dns_record(
+    State(state): State<AppState>,
+    User(claims): User,
+    Json(body): Json<UpdateDnsRecord>,
+) -> impl IntoResponse {
+    let user_id = claims.sub;
+
+    let drift_policy = match body.drift_policy.as_deref().map(DriftPolicy::parse) {
+        None => None,
+        Some(Some(policy)) => Some(policy),
+        Some(None) => {
+            return ApiError::Invalid(
+                "drift_policy must be one of ignore, log or repair".to_string(),
+            )
+            .into_response();
+        }
+    };
+
+    let conn = &mut state.pool.get().expect("Failed to get DB connection");
+
+    let result = dns_record::table
+        .inner_join(dns_zone::table)
+        .filter(dns_record::id.eq(&body.record_id))
+        .filter(dns_record::user_id.eq(&user_id))
+        .select((
+            (
+                dns_record::id,
+                dns_record::zone_id,
+                dns_record::record_name,
+                dns_record::record_type,
+                dns_record::content,
+                dns_record::ttl,
+                dns_record::proxied,
+                dns_record::fallback_content,
+                dns_record::fallback_active,
+            ),
+            dns_zone::zone_name,
+        ))
+        .first::<(models::EditableDnsRecord, String)>(conn)
+        .optional();
+
+    let (current, zone_name) = match result {
+        Ok(Some(found)) => found,
+        Ok(None) => return ApiError::NotFound(Resource::Record).into_response(),
+        Err(err) => {
+            return ApiError::from(err).into_response();
+        }
+    };
+
+    let mut edited = current.clone();
+    if let Some(name) = body.name.as_deref() {
+        edited.record_name = qualify_record_name(name, &zone_name);
+    }
+    if let Some(record_type) = body.record_type.as_deref() {
+        edited.record_type = record_type.to_ascii_uppercase();
+    }
+    if let Some(content) = body.content {
+        edited.content = content;
+    }
+    if let Some(ttl) = body.ttl {
+        edited.ttl = ttl;
+    }
+    if let Some(proxied) = body.proxied {
+        edited.proxied = proxied;
+    }
+
+    let content_fits = match edited.record_type.as_str() {
+        "A" => edited.content.parse::<Ipv4Addr>().is_ok(),
+        "AAAA" => edited.content.parse::<Ipv6Addr>().is_ok(),
+        _ => true,
+    };
+    if !content_fits {
+        return ApiError::Invalid(format!(
+            "content must be an {} address, send one along with the new type",
+            edited.record_type
+        ))
+        .into_response();
+    }
+
+    if let Some(fallback) = edited.fallback_content.as_deref() {
+        let fallback_type = fallback_record_type(fallback);
+        if fallback_type != "CNAME" && fallback_type != edited.record_type {
+            return ApiError::Invalid(format!(
+                "The record's fallback {} doesn't fit an {} record",
+                fallback, edited.record_type
+            ))
+            .into_response();
+        }
+    }
+
+    let renamed = !edited
+        .record_name
+        .eq_ignore_ascii_case(&current.record_name);
+    let retyped = edited.record_type != current.record_type;
 
-    let result = dns_record::table
-        .select(dns_record::id)
-        .filter(dns_record::user_id.eq(&user_id))
-        .filter(dns_record::zone_id.eq(&zone_id))
-        .filter(dns_record::record_name.eq(&subdomain))
-        .first::<String>(conn)
-        .optional();
+    if !renamed
+        && !retyped
+        && edited.content == current.content
+        && edited.ttl == current.ttl
+        && edited.proxied == current.proxied
+    {
+        if let Some(policy) = drift_policy {
+            let result = diesel::update(dns_record::table.filter(dns_record::id.eq(&current.id)))
+                .set(dns_record::drift_policy.eq(policy.as_str()))
+                .execute(conn);
 
-    let existing_record_id = match result {
-        Ok(v) => v,
-        Err(err) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(err.to_string())).into_response();
+            if let Err(err) = result {
+                return ApiError::from(err).into_response();
+            }
         }
-    };
 
-    if existing_record_id.is_some() {
-        return (StatusCode::CONFLICT, Json("Record already exists")).into_response();
+        return (StatusCode::OK, Json(DnsRecord::from(current))).into_response();
     }
 
-    let decrypted_token = match get_user_token(conn, &user_id) {
-        Ok(token) => token,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json("Failed to get user token"),
-            )
-                .into_response();
+    // A client syncs both address families under one name, so a rename takes the other one along
+    let sibling = match sibling_record_type(&current.record_type).filter(|_| renamed && !retyped) {
+        Some(sibling_type) => {
+            let result = dns_record::table
+                .filter(dns_record::user_id.eq(&user_id))
+                .filter(dns_record::zone_id.eq(&current.zone_id))
+                .filter(dns_record::record_name.eq(&current.record_name))
+                .filter(dns_record::record_type.eq(sibling_type))
+                .select((
+                    dns_record::id,
+                    dns_record::zone_id,
+                    dns_record::record_name,
+                    dns_record::record_type,
+                    dns_record::content,
+                    dns_record::ttl,
+                    dns_record::proxied,
+                    dns_record::fallback_content,
+                    dns_record::fallback_active,
+                ))
+                .first::<models::EditableDnsRecord>(conn)
+                .optional();
+
+            match result {
+                Ok(sibling) => sibling,
+                Err(err) => {
+                    return ApiError::from(err).into_response();
+                }
+            }
         }
+        None => None,
     };
 
-    let client = reqwest::Client::new();
-    let url = format!(
-        "https://api.cloudflare.com/client/v4/zones/{}/dns_records",
-        zone_id
-    );
+    if renamed || retyped {
+        let mut moving = vec![current.id.clone()];
+        moving.extend(sibling.iter().map(|sibling| sibling.id.clone()));
+
+        let result = dns_record::table
+            .filter(dns_record::user_id.eq(&user_id))
+            .filter(dns_record::zone_id.eq(&current.zone_id))
+            .filter(dns_record::record_name.eq(&edited.record_name))
+            .filter(dns_record::id.ne_all(&moving))
+            .select(dns_record::record_type)
+            .load::<String>(conn);
+
+        let taken = match result {
+            Ok(taken) => taken,
+            Err(err) => {
+                return ApiError::from(err).into_response();
+            }
+        };
 
-    let payload = DnsRecordPayload {
-        r#type: &record_type,
-        name: &name,
-        content: &content,
-        ttl: &ttl,
-        proxied: &proxied,
+        // A CNAME can't share its name with anything
+        let conflict = taken.iter().any(|taken_type| {
+            *taken_type == edited.record_type
+                || taken_type == "CNAME"
+                || edited.record_type == "CNAME"
+                || sibling
+                    .as_ref()
+                    .is_some_and(|sibling| *taken_type == sibling.record_type)
+        });
+        if conflict {
+            return ApiError::RecordConflict(format!(
+                "{} already has a record that conflicts with an {} record",
+                edited.record_name, edited.record_type
+            ))
+            .into_response();
+        }
+    }
+
+    let provider = match get_zone_provider(conn, &state.providers, &user_id, &current.zone_id) {
+        Ok(provider) => provider,
+        Err(e) => return ApiError::Internal(e).into_response(),
     };
 
-    let resp = match client
-        .post(&url)
-        .bearer_auth(&decrypted_token)
-        .json(&payload)
-        .send()
-        .await
+    let updated = match apply_record_edit(
+        conn,
+        provider.as_ref(),
+        &user_id,
+        &current,
+        &edited,
+        drift_policy,
+    )
+    .await
+    {
+        Ok(updated) => updated,
+        Err(e) => return e.into_response(),
+    };
+
+    if let Some(sibling) = sibling {
+        let mut moved = sibling.clone();
+        moved.record_name = edited.record_name.clone();
+
+        if let Err(e) =
+            apply_record_edit(conn, provider.as_ref(), &user_id, &sibling, &moved, None).await
+        {
+            tracing::warn!(
+                "Record {} updated, but its {} record is still at {}: {}",
+                updated.id,
+                sibling.record_type,
+                sibling.record_name,
+                e.code()
+            );
+            return e.into_response();
+        }
+    }
+
+    (StatusCode::OK, Json(updated)).into_response()
+}
+
+/// Push an edited record to its provider, then store it under whatever id the provider now uses
+async fn apply_record_edit(
+    conn: &mut MysqlConnection,
+    provider: &dyn DnsProvider,
+    user_id: &str,
+    current: &models::EditableDnsRecord,
+    edited: &models::EditableDnsRecord,
+    drift_policy: Option<DriftPolicy>,
+) -> Result<DnsRecord, ApiError> {
+    // The provider keeps serving the fallback until the client is back, only the rest changes
+    let (record_type, content) = match edited
+        .fallback_content
+        .as_deref()
+        .filter(|_| edited.fallback_active)
     {
-        Ok(r) => r,
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())).into_response(),
+        Some(fallback) => (fallback_record_type(fallback), fallback),
+        None => (edited.record_type.as_str(), edited.content.as_str()),
     };
 
-    let response = resp.json::<CreateRecordResponse>().await;
-    let new_token = match response {
-        Ok(r) => r.result,
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(e.to_string())).into_response(),
+    let payload = RecordPayload {
+        record_type,
+        name: &edited.record_name,
+        content,
+        ttl: edited.ttl,
+        proxied: Some(edited.proxied),
     };
 
-    let result = conn.transaction(|conn| {
-        diesel::insert_into(dns_record::table)
-            .values((
-                dns_record::id.eq(&new_token.id),
-                dns_record::user_id.eq(&user_id),
-                dns_record::record_name.eq(&new_token.name),
-                dns_record::zone_id.eq(&zone_id),
-                dns_record::content.eq(&new_token.content),
-                dns_record::ttl.eq(&new_token.ttl),
-                dns_record::record_type.eq(&new_token.record_type),
-                dns_record::proxied.eq(&new_token.proxied),
-            ))
-            .execute(conn)?;
-        Ok::<_, diesel::result::Error>(())
-    });
+    // api_keys and sync_event follow an id change through ON UPDATE CASCADE
+    let operation = RecordOperation::Update {
+        record_id: current.id.clone(),
+        payload: PayloadSpec::new(&payload),
+        stored: StoredChanges {
+            record_type: Some(edited.record_type.clone()),
+            content: Some(edited.content.clone()),
+            drift_policy: drift_policy.map(|policy| policy.as_str().to_string()),
+            ..Default::default()
+        },
+    };
 
-    match result {
-        Ok(_) => (StatusCode::CREATED, Json("Record added successfully")).into_response(),
-        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, Json(err.to_string())).into_response(),
+    match outbox::submit(conn, provider, user_id, &current.zone_id, operation).await {
+        Ok(Completed::Record(updated)) => Ok(DnsRecord {
+            record_type: edited.record_type.clone(),
+            content: edited.content.clone(),
+            ..updated
+        }),
+        Ok(_) => Err(ApiError::RecordConflict(
+            "Record was changed by a newer edit".to_string(),
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// "home" and "home.example.com" name the same record in example.com
+fn qualify_record_name(name: &str, zone_name: &str) -> String {
+    let name = name.trim_end_matches('.');
+    let suffix = format!(".{}", zone_name.to_ascii_lowercase());
+
+    if name == "@" || name.eq_ignore_ascii_case(zone_name) {
+        zone_name.to_string()
+    } else if name.to_ascii_lowercase().ends_with(&suffix) {
+        name.to_string()
+    } else {
+        format!("{}.{}", name, zone_name)
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/records",
+    tag = "records",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Each zone with its managed records", body = Vec<(Zone, Vec<ManagedDnsRecord>)>),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "The user has no DNS token", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
 async fn list_dns_records(State(state): State<AppState>, User(claims): User) -> impl IntoResponse {
     let curr_user_id = claims.sub;
     let conn = &mut state.pool.get().expect("Failed to get DB connection");
 
-    let zones: Vec<(String, String)> = dns_zone::table
-        .filter(dns_zone::user_id.eq(&curr_user_id))
-        .select((dns_zone::id, dns_zone::zone_name))
-        .load(conn)
-        .unwrap_or_default();
-
-    if zones.is_empty() {
-        let token_data = dns_token::table
-            .filter(dns_token::user_id.eq(&curr_user_id))
+    let load_zones = |conn: &mut MysqlConnection| {
+        dns_zone::table
+            .filter(dns_zone::user_id.eq(&curr_user_id))
             .select((
-                dns_token::id,
-                dns_token::token_encrypted,
-                dns_token::nonce,
-                dns_token::tag,
+                dns_zone::id,
+                dns_zone::zone_name,
+                dns_zone::meta,
+                dns_zone::orphaned_on,
             ))
-            .first::<(String, Vec<u8>, Vec<u8>, Vec<u8>)>(conn)
-            .optional();
-
-        let (token_id, ciphertext, nonce, tag) = match token_data {
-            Ok(Some(data)) => data,
-            Ok(None) => return (StatusCode::NOT_FOUND, "No DNS Token found").into_response(),
-            Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "DB Error").into_response(),
-        };
+            .load::<(String, String, Option<String>, Option<NaiveDateTime>)>(conn)
+            .unwrap_or_default()
+    };
 
-        let decrypted_token = match decrypt(&nonce, &ciphertext, &tag) {
-            Ok(t) => t,
-            Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Security Error").into_response(),
-        };
+    let mut zones = load_zones(conn);
 
-        let zones = match initialize_zones(conn, &curr_user_id, &decrypted_token, &token_id).await {
-            Ok(z) => z,
-            Err(_) => {
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Error fetching DNS Zones",
-                )
+    // First visit, find out which zones the user's tokens can see
+    if zones.is_empty() {
+        match refresh_zones(conn, &state.providers, &curr_user_id).await {
+            Ok(refresh) if refresh.tokens == 0 => {
+                return ApiError::NotFound(Resource::DnsToken).into_response()
+            }
+            Ok(refresh) if refresh.failed_tokens.len() < refresh.tokens => {}
+            Ok(_) => {
+                return ApiError::Internal("No DNS token could list its zones".to_string())
                     .into_response()
             }
-        };
-
-        let zone_dns_data: Vec<(Zone, Vec<DnsRecord>)> =
-            zones.into_iter().map(|z| (z, Vec::new())).collect();
+            Err(e) => return ApiError::Internal(e).into_response(),
+        }
 
-        return (StatusCode::OK, Json(&zone_dns_data)).into_response();
+        zones = load_zones(conn);
     }
 
-    let zone_dns_data: Vec<(Zone, Vec<DnsRecord>)> = zones
+    let managed_names = client_managed_names(conn, Some(&curr_user_id)).unwrap_or_default();
+    let now = chrono::Utc::now().naive_utc();
+
+    let zone_dns_data: Vec<(Zone, Vec<ManagedDnsRecord>)> = zones
         .into_iter()
-        .map(|(z_id, z_name)| {
+        .map(|(z_id, z_name, z_meta, z_orphaned_on)| {
             let records = dns_record::table
                 .filter(dns_record::zone_id.eq(&z_id))
                 .filter(dns_record::user_id.eq(&curr_user_id))
                 .select((
-                    dns_record::id,
-                    dns_record::record_name,
-                    dns_record::content,
-                    dns_record::ttl,
-                    dns_record::record_type,
-                    dns_record::proxied,
+                    (
+                        dns_record::id,
+                        dns_record::record_name,
+                        dns_record::content,
+                        dns_record::ttl,
+                        dns_record::record_type,
+                        dns_record::proxied,
+                    ),
+                    (
+                        dns_record::drift_policy,
+                        dns_record::reconcile_status,
+                        dns_record::reconcile_detail,
+                        dns_record::last_reconciled_on,
+                    ),
+                    (
+                        dns_record::last_synced_on,
+                        dns_record::fallback_content,
+                        dns_record::fallback_active,
+                        dns_record::orphaned_on,
+                    ),
                 ))
-                .load::<(String, String, String, i32, String, bool)>(conn)
+                .load::<(
+                    (String, String, String, i32, String, bool),
+                    (
+                        String,
+                        Option<String>,
+                        Option<String>,
+                        Option<NaiveDateTime>,
+                    ),
+                    (NaiveDateTime, Option<String>, bool, Option<NaiveDateTime>),
+                )>(conn)
                 .unwrap_or_default()
                 .into_iter()
                 .map(
-                    |(r_id, r_name, r_content, r_ttl, r_type, r_proxied)| DnsRecord {
-                        id: r_id,
-                        name: r_name,
-                        content: r_content,
-                        ttl: r_ttl,
-                        record_type: r_type,
-                        proxied: r_proxied,
+                    |(
+                        (r_id, r_name, r_content, r_ttl, r_type, r_proxied),
+                        (drift_policy, r_status, r_detail, r_checked_on),
+                        (last_synced_on, fallback_content, fallback_active, orphaned_on),
+                    )| ManagedDnsRecord {
+                        client_status: managed_names
+                            .contains(&(curr_user_id.clone(), z_id.clone(), r_name.clone()))
+                            .then(|| {
+                                state
+                                    .liveness
+                                    .status(last_synced_on, now)
+                                    .as_str()
+                                    .to_string()
+                            }),
+                        last_synced_on,
+                        fallback_content,
+                        fallback_active,
+                        orphaned_on,
+                        record: DnsRecord {
+                            id: r_id,
+                            name: r_name,
+                            content: r_content,
+                            ttl: r_ttl,
+                            record_type: r_type,
+                            proxied: r_proxied,
+                        },
+                        drift_policy,
+                        reconcile: r_status.zip(r_checked_on).map(|(status, checked_on)| {
+                            ReconcileResult {
+                                status,
+                                detail: r_detail,
+                                checked_on,
+                            }
+                        }),
                     },
                 )
                 .collect();
 
+            let meta = ZoneMeta::parse(z_meta.as_deref()).unwrap_or_default();
+            let status = if z_orphaned_on.is_some() {
+                "orphaned".to_string()
+            } else if meta.status.is_empty() {
+                "active".to_string()
+            } else {
+                meta.status
+            };
+
             (
                 Zone {
                     id: z_id,
                     name: z_name,
-                    status: "active".to_string(),
+                    status,
+                    name_servers: meta.name_servers,
                 },
                 records,
             )
@@ -563,6 +1993,166 @@ async fn list_dns_records(State(state): State<AppState>, User(claims): User) ->
     (StatusCode::OK, Json(&zone_dns_data)).into_response()
 }
 
+/// List the zones of every token again, adding new zones and orphaning the ones that are gone
+#[utoipa::path(
+    post,
+    path = "/zones/refresh",
+    tag = "zones",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "What the refresh found", body = ZoneRefresh),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "The user has no DNS token", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
+async fn refresh_dns_zones(State(state): State<AppState>, User(claims): User) -> impl IntoResponse {
+    let user_id = claims.sub;
+    let conn = &mut state.pool.get().expect("Failed to get DB connection");
+
+    match refresh_zones(conn, &state.providers, &user_id).await {
+        Ok(refresh) if refresh.tokens == 0 => {
+            ApiError::NotFound(Resource::DnsToken).into_response()
+        }
+        Ok(refresh) => (StatusCode::OK, Json(refresh)).into_response(),
+        Err(err) => ApiError::Internal(err).into_response(),
+    }
+}
+
+/// Sync history of one of the user's records, newest first
+#[utoipa::path(
+    get,
+    path = "/records/{id}/history",
+    tag = "records",
+    params(("id" = String, Path, description = "Record id"), HistoryParams),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Sync events, newest first", body = SyncHistory),
+        (status = 400, description = "Page out of range", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Record not found", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
+async fn get_record_history(
+    State(state): State<AppState>,
+    User(claims): User,
+    Path(record_id): Path<String>,
+    Query(params): Query<HistoryParams>,
+) -> impl IntoResponse {
+    let user_id = claims.sub;
+    let page = params.page.unwrap_or(1).max(1);
+    let per_page = params
+        .per_page
+        .unwrap_or(HISTORY_PAGE_SIZE)
+        .clamp(1, HISTORY_MAX_PAGE_SIZE);
+    let Some(offset) = (page - 1).checked_mul(per_page) else {
+        return ApiError::Invalid("page is out of range".to_string()).into_response();
+    };
+
+    let conn = &mut state.pool.get().expect("Failed to get DB connection");
+
+    if let Err(err) = owned_record(conn, &user_id, &record_id) {
+        return err.into_response();
+    }
+
+    let total = sync_event::table
+        .filter(sync_event::dns_record_id.eq(&record_id))
+        .count()
+        .get_result::<i64>(conn);
+
+    let events = sync_event::table
+        .filter(sync_event::dns_record_id.eq(&record_id))
+        .order((sync_event::created_on.desc(), sync_event::id.desc()))
+        .limit(per_page)
+        .offset(offset)
+        .select((
+            sync_event::id,
+            sync_event::api_key_id,
+            sync_event::old_content,
+            sync_event::new_content,
+            sync_event::outcome,
+            sync_event::provider_response,
+            sync_event::created_on,
+        ))
+        .load::<SyncEvent>(conn);
+
+    match (events, total) {
+        (Ok(events), Ok(total)) => (
+            StatusCode::OK,
+            Json(SyncHistory {
+                events,
+                page,
+                per_page,
+                total,
+            }),
+        )
+            .into_response(),
+        (Err(err), _) | (_, Err(err)) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Where a queued provider change stands, for requests answered with 202
+#[utoipa::path(
+    get,
+    path = "/operations/{id}",
+    tag = "records",
+    params(("id" = String, Path, description = "Operation id")),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Where the operation stands", body = ProviderOperation),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Operation not found", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
+async fn get_provider_operation(
+    State(state): State<AppState>,
+    User(claims): User,
+    Path(operation_id): Path<String>,
+) -> impl IntoResponse {
+    let conn = &mut state.pool.get().expect("Failed to get DB connection");
+
+    let result = provider_operation::table
+        .filter(provider_operation::id.eq(&operation_id))
+        .filter(provider_operation::user_id.eq(&claims.sub))
+        .select((
+            provider_operation::id,
+            provider_operation::zone_id,
+            provider_operation::record_id,
+            provider_operation::kind,
+            provider_operation::status,
+            provider_operation::attempts,
+            provider_operation::last_error,
+            provider_operation::next_attempt_on,
+            provider_operation::created_on,
+            provider_operation::completed_on,
+        ))
+        .first::<ProviderOperation>(conn)
+        .optional();
+
+    match result {
+        Ok(Some(operation)) => (StatusCode::OK, Json(operation)).into_response(),
+        Ok(None) => ApiError::NotFound(Resource::Operation).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/record",
+    tag = "records",
+    params(DeleteDnsRecord),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Record deleted", body = String),
+        (status = 202, description = "The provider failed, the change is queued and retried", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Record not found", body = ErrorBody),
+        (status = 502, description = "The DNS provider failed", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
 async fn delete_dns_record(
     State(state): State<AppState>,
     User(claims): User,
@@ -572,58 +2162,38 @@ async fn delete_dns_record(
     let record_id = params.record_id;
     let zone_id = params.zone_id;
 
-    let client = reqwest::Client::new();
     let conn = &mut state.pool.get().expect("Failed to get DB connection");
 
-    let decrypted_token = match get_user_token(conn, &user_id) {
-        Ok(token) => token,
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(e)).into_response(),
-    };
-
-    let url = format!(
-        "https://api.cloudflare.com/client/v4/zones/{}/dns_records/{}",
-        zone_id, record_id
-    );
-
-    let resp = match client
-        .delete(&url)
-        .bearer_auth(&decrypted_token)
-        .send()
-        .await
-    {
-        Ok(r) => r,
-        Err(err) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(err.to_string())).into_response();
-        }
-    };
-
-    let response = resp.json::<DeleteRecordResponse>().await;
+    // Nothing reaches the provider unless the record is the user's own
+    if let Err(err) = owned_zone_record(conn, &user_id, &zone_id, &record_id) {
+        return err.into_response();
+    }
 
-    let deleted_token = match response {
-        Ok(r) => r.result,
-        Err(err) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(err.to_string())).into_response();
-        }
+    let provider = match get_zone_provider(conn, &state.providers, &user_id, &zone_id) {
+        Ok(provider) => provider,
+        Err(e) => return ApiError::Internal(e).into_response(),
     };
 
-    let result = conn.transaction(|conn| {
-        diesel::delete(
-            dns_record::table
-                .filter(dns_record::user_id.eq(&user_id))
-                .filter(dns_record::zone_id.eq(&zone_id))
-                .filter(dns_record::id.eq(&deleted_token.id)),
-        )
-        .execute(conn)?;
-        Ok::<_, diesel::result::Error>(())
-    });
+    let operation = RecordOperation::Delete { record_id };
 
-    match result {
+    match outbox::submit(conn, provider.as_ref(), &user_id, &zone_id, operation).await {
         Ok(_) => (StatusCode::OK, "Deleted DNS record from account").into_response(),
-        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
     }
 }
 
 // DNS Token Controls
+#[utoipa::path(
+    get,
+    path = "/access_tokens",
+    tag = "tokens",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The user's DNS tokens, without the secrets", body = Vec<DnsAccessToken>),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
 async fn get_dns_access_tokens(
     State(state): State<AppState>,
     User(claims): User,
@@ -633,23 +2203,42 @@ async fn get_dns_access_tokens(
 
     let tokens = match dns_token::table
         .filter(dns_token::user_id.eq(&user_id))
-        .select((dns_token::name, dns_token::id, dns_token::created_on))
-        .load::<(String, String, NaiveDateTime)>(conn)
+        .select((
+            dns_token::name,
+            dns_token::id,
+            dns_token::provider,
+            dns_token::created_on,
+        ))
+        .load::<(String, String, String, NaiveDateTime)>(conn)
     {
         Ok(tokens) => tokens
             .into_iter()
-            .map(|(name, id, created_on)| DnsAccessToken {
+            .map(|(name, id, provider, created_on)| DnsAccessToken {
                 name,
                 id,
+                provider,
                 created_on,
             })
             .collect::<Vec<_>>(),
-        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
     };
 
     (StatusCode::OK, Json(&tokens)).into_response()
 }
 
+#[utoipa::path(
+    post,
+    path = "/access_token",
+    tag = "tokens",
+    request_body = AddAccessToken,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Token stored and its zones listed", body = String),
+        (status = 400, description = "The provider refused the token", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
 async fn add_dns_access_token(
     State(state): State<AppState>,
     User(claims): User,
@@ -658,13 +2247,27 @@ async fn add_dns_access_token(
     let name = body.name;
     let user_id = claims.sub;
     let dns_token_str = body.token;
+    let provider_name = body
+        .provider
+        .unwrap_or_else(|| DEFAULT_PROVIDER.to_string());
     let id = Uuid::now_v7().to_string();
+
+    // Make sure the token actually works before we store it
+    let provider = match state.providers.build(&provider_name, &id, &dns_token_str) {
+        Ok(provider) => provider,
+        Err(err) => return ApiError::InvalidDnsToken(err.to_string()).into_response(),
+    };
+
+    if let Err(err) = provider.verify_credentials().await {
+        return ApiError::InvalidDnsToken(err.to_string()).into_response();
+    }
+
     let conn = &mut state.pool.get().expect("Failed to get DB connection");
 
     let encrypted = match encrypt(&dns_token_str) {
         Ok(enc) => enc,
         Err(err) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(err.to_string())).into_response();
+            return ApiError::Internal(err.to_string()).into_response();
         }
     };
 
@@ -672,6 +2275,7 @@ async fn add_dns_access_token(
         id: &id,
         name: &name,
         user_id: &user_id,
+        provider: &provider_name,
         nonce: &encrypted.nonce,
         token_encrypted: &encrypted.ciphertext,
         tag: &encrypted.tag,
@@ -684,12 +2288,31 @@ async fn add_dns_access_token(
         Ok::<_, diesel::result::Error>(())
     });
 
-    match result {
-        Ok(_) => (StatusCode::OK, Json("Added DNS token to account")).into_response(),
-        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, Json(err.to_string())).into_response(),
+    if let Err(err) = result {
+        return ApiError::from(err).into_response();
     }
+
+    // The token is stored either way, a failed zone listing shouldn't undo that
+    if let Err(e) = refresh_zones(conn, &state.providers, &user_id).await {
+        tracing::warn!("Failed to list zones of new token {}: {}", id, e);
+    }
+
+    (StatusCode::OK, Json("Added DNS token to account")).into_response()
 }
 
+#[utoipa::path(
+    delete,
+    path = "/access_token",
+    tag = "tokens",
+    params(DeleteAccessToken),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Token deleted", body = String),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "DNS token not found", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
 async fn delete_access_token(
     State(state): State<AppState>,
     User(claims): User,
@@ -699,6 +2322,10 @@ async fn delete_access_token(
     let user_id = claims.sub;
     let conn = &mut state.pool.get().expect("Failed to get DB connection");
 
+    if let Err(err) = owned_token(conn, &user_id, &dns_token_id) {
+        return err.into_response();
+    }
+
     let result = conn.transaction(|conn| {
         diesel::delete(
             dns_token::table
@@ -711,11 +2338,22 @@ async fn delete_access_token(
 
     match result {
         Ok(_) => (StatusCode::OK, Json("Deleted DNS token from account")).into_response(),
-        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, Json(err.to_string())).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
     }
 }
 
 // API Key Controls
+#[utoipa::path(
+    get,
+    path = "/api_keys",
+    tag = "api_keys",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The user's API keys", body = Vec<ApiKey>),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
 async fn get_api_keys(State(state): State<AppState>, User(claims): User) -> impl IntoResponse {
     let user_id = claims.sub;
 
@@ -749,14 +2387,23 @@ async fn get_api_keys(State(state): State<AppState>, User(claims): User) -> impl
             ),
         )
             .into_response(),
-        Err(err) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(format!("Error: {}", err)),
-        )
-            .into_response(),
+        Err(err) => ApiError::from(err).into_response(),
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/api_key",
+    tag = "api_keys",
+    request_body = AddApiKey,
+    security(("session" = [])),
+    responses(
+        (status = 201, description = "The new key, only ever shown here", body = String),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Record not found", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
 async fn add_api_key(
     State(state): State<AppState>,
     User(claims): User,
@@ -768,11 +2415,20 @@ async fn add_api_key(
 
     let conn = &mut state.pool.get().expect("Failed to get DB connection");
 
+    if let Err(err) = owned_record(conn, user_id, key_scope) {
+        return err.into_response();
+    }
+
     // Create an api key, hash it
     let (full_api_key, public_id, _secret) = generate_api_key();
 
     // Hash the FULL key (so verification is simple later)
-    let hashed_key = hash_raw_string(&full_api_key).expect("Hash failed");
+    let hashed_key = match hash_api_key(&full_api_key) {
+        Ok(hash) => hash,
+        Err(err) => {
+            return ApiError::Internal(err.to_string()).into_response();
+        }
+    };
 
     let result = conn.transaction(|conn| {
         diesel::insert_into(api_keys::table)
@@ -791,11 +2447,25 @@ async fn add_api_key(
     // Return full key to user
     match result {
         Ok(_) => (StatusCode::CREATED, Json(&full_api_key)).into_response(),
-        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, Json(err.to_string())).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
     }
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api_key",
+    tag = "api_keys",
+    params(DeleteApiKeyParams),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Key deleted", body = String),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "API key not found", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
 async fn delete_api_key(
+    State(state): State<AppState>,
     User(claims): User,
     Query(params): Query<DeleteApiKeyParams>,
 ) -> impl IntoResponse {
@@ -803,56 +2473,210 @@ async fn delete_api_key(
     let user_id = claims.sub;
     let mut conn = establish_connection();
 
+    if let Err(err) = owned_api_key(&mut conn, &user_id, &api_key_id) {
+        return err.into_response();
+    }
+
     let result = conn.transaction(|conn| {
         diesel::delete(
             api_keys::table
-                .filter(api_keys::id.eq(api_key_id))
+                .filter(api_keys::id.eq(&api_key_id))
                 .filter(api_keys::user_id.eq(user_id)),
         )
         .execute(conn)?;
         Ok::<_, diesel::result::Error>(())
     });
 
+    // Stop accepting the key right away instead of when its cache entry expires
+    state.api_key_cache.evict(&api_key_id).await;
+
     match result {
         Ok(_) => (StatusCode::OK, Json("API key deleted from account")).into_response(),
-        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, Json(err.to_string())).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
     }
 }
 
-// Helper functions
-// TODO: Move these to a utils file
-async fn initialize_zones(
-    conn: &mut MysqlConnection,
-    curr_user_id: &String,
-    dns_access_token: &String,
-    dns_access_token_id: &String,
-) -> Result<Vec<Zone>, Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-
-    let zones_resp = client
-        .get("https://api.cloudflare.com/client/v4/zones")
-        .bearer_auth(dns_access_token)
-        .header("Content-Type", "application/json")
-        .send()
-        .await?;
+// Webhook Controls
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    tag = "webhooks",
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The user's webhooks", body = Vec<Webhook>),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
+async fn get_webhooks(State(state): State<AppState>, User(claims): User) -> impl IntoResponse {
+    let user_id = claims.sub;
+    let conn = &mut state.pool.get().expect("Failed to get DB connection");
+
+    match webhook::table
+        .filter(webhook::user_id.eq(&user_id))
+        .select((
+            webhook::id,
+            webhook::name,
+            webhook::url,
+            webhook::created_on,
+        ))
+        .load::<(String, String, String, NaiveDateTime)>(conn)
+    {
+        Ok(rows) => (
+            StatusCode::OK,
+            Json(
+                rows.into_iter()
+                    .map(|(id, name, url, created_on)| Webhook {
+                        id,
+                        name,
+                        url,
+                        created_on,
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+        )
+            .into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/webhook",
+    tag = "webhooks",
+    request_body = AddWebhook,
+    security(("session" = [])),
+    responses(
+        (status = 201, description = "Webhook added, with its signing secret", body = CreatedWebhook),
+        (status = 400, description = "Not an http(s) URL, or one on a private network", body = ErrorBody),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
+async fn add_webhook(
+    State(state): State<AppState>,
+    User(claims): User,
+    Json(body): Json<AddWebhook>,
+) -> impl IntoResponse {
+    let user_id = &claims.sub;
 
-    let data = zones_resp.json::<DnsZonesResponse>().await?;
-    let zones = data.result;
+    if let Err(e) = check_url(&body.url) {
+        return ApiError::Invalid(e).into_response();
+    }
 
-    conn.transaction(|conn| {
-        for zone in &zones {
-            diesel::insert_into(dns_zone::table)
-                .values((
-                    dns_zone::id.eq(&zone.id),
-                    dns_zone::user_id.eq(curr_user_id),
-                    dns_zone::zone_name.eq(&zone.name),
-                    dns_zone::token_id.eq(&dns_access_token_id),
-                    dns_zone::last_synced_on.eq(chrono::Utc::now().naive_utc()),
-                ))
-                .execute(conn)?;
+    let secret = generate_webhook_secret();
+    let encrypted = match encrypt(&secret) {
+        Ok(encrypted) => encrypted,
+        Err(err) => {
+            return ApiError::Internal(err.to_string()).into_response();
         }
-        Ok::<_, diesel::result::Error>(())
-    })?;
+    };
+
+    let conn = &mut state.pool.get().expect("Failed to get DB connection");
+    let id = Uuid::now_v7().to_string();
+
+    let result = diesel::insert_into(webhook::table)
+        .values((
+            webhook::id.eq(&id),
+            webhook::user_id.eq(user_id),
+            webhook::name.eq(&body.name),
+            webhook::url.eq(&body.url),
+            webhook::nonce.eq(&encrypted.nonce),
+            webhook::secret_encrypted.eq(&encrypted.ciphertext),
+            webhook::tag.eq(&encrypted.tag),
+        ))
+        .execute(conn);
+
+    // Return the signing secret to the user, it can't be read back later
+    match result {
+        Ok(_) => (StatusCode::CREATED, Json(CreatedWebhook { id, secret })).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/webhook",
+    tag = "webhooks",
+    params(DeleteWebhookParams),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Webhook deleted", body = String),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Webhook not found", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
+async fn delete_webhook(
+    State(state): State<AppState>,
+    User(claims): User,
+    Query(params): Query<DeleteWebhookParams>,
+) -> impl IntoResponse {
+    let user_id = claims.sub;
+    let conn = &mut state.pool.get().expect("Failed to get DB connection");
+
+    if let Err(err) = owned_webhook(conn, &user_id, &params.webhook_id) {
+        return err.into_response();
+    }
+
+    let result = diesel::delete(
+        webhook::table
+            .filter(webhook::id.eq(&params.webhook_id))
+            .filter(webhook::user_id.eq(&user_id)),
+    )
+    .execute(conn);
+
+    match result {
+        Ok(_) => (StatusCode::OK, Json("Deleted webhook")).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Latest delivery attempts of one of the user's webhooks, newest first
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}/deliveries",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook id")),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Recent delivery attempts", body = Vec<WebhookDelivery>),
+        (status = 401, description = "Not signed in", body = ErrorBody),
+        (status = 404, description = "Webhook not found", body = ErrorBody),
+        (status = 500, description = "Something broke on our side", body = ErrorBody)
+    )
+)]
+async fn get_webhook_deliveries(
+    State(state): State<AppState>,
+    User(claims): User,
+    Path(webhook_id): Path<String>,
+) -> impl IntoResponse {
+    let user_id = claims.sub;
+    let conn = &mut state.pool.get().expect("Failed to get DB connection");
+
+    if let Err(err) = owned_webhook(conn, &user_id, &webhook_id) {
+        return err.into_response();
+    }
 
-    Ok(zones)
+    match webhook_delivery::table
+        .filter(webhook_delivery::webhook_id.eq(&webhook_id))
+        .order((
+            webhook_delivery::created_on.desc(),
+            webhook_delivery::id.desc(),
+        ))
+        .limit(WEBHOOK_DELIVERY_LOG_SIZE)
+        .select((
+            webhook_delivery::id,
+            webhook_delivery::event_id,
+            webhook_delivery::attempt,
+            webhook_delivery::status_code,
+            webhook_delivery::response,
+            webhook_delivery::success,
+            webhook_delivery::created_on,
+        ))
+        .load::<WebhookDelivery>(conn)
+    {
+        Ok(deliveries) => (StatusCode::OK, Json(deliveries)).into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
 }
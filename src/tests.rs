@@ -0,0 +1,441 @@
+// Handlers called with another account's ids. These need a MySQL database with the site's
+// schema pushed, so they only run with TEST_DATABASE_URL set:
+//   TEST_DATABASE_URL=mysql://... cargo test -p drago-dns -- --ignored
+use super::*;
+use crate::db::schema::user;
+use crate::lib::auth::Claims;
+use crate::lib::providers::ProviderError;
+use crate::lib::types::Zone;
+use async_trait::async_trait;
+use axum::response::Response;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const FAKE_PROVIDER: &str = "fake";
+
+// Counts every build of and call to the fake provider, none of them should happen
+#[derive(Clone, Default)]
+struct ProviderUse(Arc<AtomicUsize>);
+
+impl ProviderUse {
+    fn record(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+struct FakeProvider(ProviderUse);
+
+impl FakeProvider {
+    fn refuse(&self) -> ProviderError {
+        self.0.record();
+        ProviderError::Config("the fake provider was called".to_string())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for FakeProvider {
+    async fn verify_credentials(&self) -> Result<(), ProviderError> {
+        Err(self.refuse())
+    }
+
+    async fn list_zones(&self) -> Result<Vec<Zone>, ProviderError> {
+        Err(self.refuse())
+    }
+
+    async fn list_records(&self, _zone_id: &str) -> Result<Vec<DnsRecord>, ProviderError> {
+        Err(self.refuse())
+    }
+
+    async fn get_record(
+        &self,
+        _zone_id: &str,
+        _record_id: &str,
+    ) -> Result<Option<DnsRecord>, ProviderError> {
+        Err(self.refuse())
+    }
+
+    async fn create_record(
+        &self,
+        _zone_id: &str,
+        _record: &RecordPayload<'_>,
+    ) -> Result<DnsRecord, ProviderError> {
+        Err(self.refuse())
+    }
+
+    async fn update_record(
+        &self,
+        _zone_id: &str,
+        _record_id: &str,
+        _record: &RecordPayload<'_>,
+    ) -> Result<DnsRecord, ProviderError> {
+        Err(self.refuse())
+    }
+
+    async fn delete_record(
+        &self,
+        _zone_id: &str,
+        _record_id: &str,
+    ) -> Result<String, ProviderError> {
+        Err(self.refuse())
+    }
+}
+
+// Everything user A owns, user B is the one asking for it
+struct Accounts {
+    user_a: String,
+    user_b: String,
+    token_id: String,
+    zone_id: String,
+    record_id: String,
+    api_key_id: String,
+    webhook_id: String,
+    operation_id: String,
+}
+
+impl Accounts {
+    fn insert(conn: &mut MysqlConnection) -> Self {
+        let id = || Uuid::now_v7().to_string();
+        let accounts = Accounts {
+            user_a: id(),
+            user_b: id(),
+            token_id: id(),
+            zone_id: id(),
+            record_id: id(),
+            api_key_id: id(),
+            webhook_id: id(),
+            operation_id: id(),
+        };
+        let now = chrono::Utc::now().naive_utc();
+
+        for user_id in [&accounts.user_a, &accounts.user_b] {
+            diesel::insert_into(user::table)
+                .values((
+                    user::id.eq(user_id),
+                    user::name.eq("Cross tenant"),
+                    user::email.eq(format!("{}@example.com", user_id)),
+                    user::email_verified.eq(true),
+                    user::created_at.eq(now),
+                    user::updated_at.eq(now),
+                ))
+                .execute(conn)
+                .expect("insert user");
+        }
+
+        let token = encrypt("fake-secret").expect("encrypt the token");
+        diesel::insert_into(dns_token::table)
+            .values((
+                dns_token::id.eq(&accounts.token_id),
+                dns_token::name.eq("A's token"),
+                dns_token::user_id.eq(&accounts.user_a),
+                dns_token::provider.eq(FAKE_PROVIDER),
+                dns_token::nonce.eq(&token.nonce),
+                dns_token::token_encrypted.eq(&token.ciphertext),
+                dns_token::tag.eq(&token.tag),
+            ))
+            .execute(conn)
+            .expect("insert dns_token");
+
+        diesel::insert_into(dns_zone::table)
+            .values((
+                dns_zone::id.eq(&accounts.zone_id),
+                dns_zone::user_id.eq(&accounts.user_a),
+                dns_zone::token_id.eq(&accounts.token_id),
+                dns_zone::zone_name.eq("a.example.com"),
+            ))
+            .execute(conn)
+            .expect("insert dns_zone");
+
+        diesel::insert_into(dns_record::table)
+            .values((
+                dns_record::id.eq(&accounts.record_id),
+                dns_record::user_id.eq(&accounts.user_a),
+                dns_record::zone_id.eq(&accounts.zone_id),
+                dns_record::record_name.eq("home.a.example.com"),
+                dns_record::record_type.eq("A"),
+                dns_record::content.eq("192.0.2.1"),
+            ))
+            .execute(conn)
+            .expect("insert dns_record");
+
+        diesel::insert_into(api_keys::table)
+            .values((
+                api_keys::id.eq(&accounts.api_key_id),
+                api_keys::user_id.eq(&accounts.user_a),
+                api_keys::name.eq("A's key"),
+                api_keys::prefix_id.eq(&accounts.api_key_id[..20]),
+                api_keys::key_hash.eq("not a real hash"),
+                api_keys::dns_record_id.eq(&accounts.record_id),
+            ))
+            .execute(conn)
+            .expect("insert api_keys");
+
+        let secret = encrypt("fake-webhook-secret").expect("encrypt the webhook secret");
+        diesel::insert_into(webhook::table)
+            .values((
+                webhook::id.eq(&accounts.webhook_id),
+                webhook::user_id.eq(&accounts.user_a),
+                webhook::name.eq("A's webhook"),
+                webhook::url.eq("https://example.com/hook"),
+                webhook::nonce.eq(&secret.nonce),
+                webhook::secret_encrypted.eq(&secret.ciphertext),
+                webhook::tag.eq(&secret.tag),
+            ))
+            .execute(conn)
+            .expect("insert webhook");
+
+        diesel::insert_into(provider_operation::table)
+            .values((
+                provider_operation::id.eq(&accounts.operation_id),
+                provider_operation::user_id.eq(&accounts.user_a),
+                provider_operation::zone_id.eq(&accounts.zone_id),
+                provider_operation::record_id.eq(&accounts.record_id),
+                provider_operation::kind.eq("delete"),
+                provider_operation::operation.eq("{}"),
+                provider_operation::status.eq("done"),
+            ))
+            .execute(conn)
+            .expect("insert provider_operation");
+
+        accounts
+    }
+
+    // Everything else goes with the users
+    fn delete(&self, conn: &mut MysqlConnection) {
+        diesel::delete(user::table.filter(user::id.eq_any([&self.user_a, &self.user_b])))
+            .execute(conn)
+            .expect("delete users");
+    }
+}
+
+fn test_state(provider_use: &ProviderUse) -> AppState {
+    let database_url = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+    // delete_api_key opens its own connection
+    env::set_var("DATABASE_URL", &database_url);
+    if env::var("ENCRYPTION_KEY").is_err() {
+        env::set_var("ENCRYPTION_KEY", "42".repeat(32));
+    }
+
+    let pool = Pool::builder()
+        .max_size(2)
+        .build(ConnectionManager::<MysqlConnection>::new(database_url))
+        .expect("Could not build connection pool");
+
+    let mut providers = ProviderRegistry::new();
+    let builds = provider_use.clone();
+    providers.register(FAKE_PROVIDER, move |_, _, _| {
+        builds.record();
+        Ok(Box::new(FakeProvider(builds.clone())))
+    });
+
+    AppState {
+        webhooks: WebhookDispatcher::new(pool.clone()),
+        pool,
+        auth: AuthState::new("http://localhost:5173"),
+        providers,
+        api_key_cache: ApiKeyCache::new(API_KEY_CACHE_TTL),
+        trusted_proxies: TrustedProxies::default(),
+        liveness: LivenessConfig {
+            sync_interval: Duration::from_secs(600),
+            stale_after: 3,
+            offline_after: 6,
+        },
+    }
+}
+
+fn signed_in(user_id: &str) -> User {
+    User(Claims {
+        sub: user_id.to_string(),
+        exp: usize::MAX,
+        extra: HashMap::new(),
+    })
+}
+
+async fn assert_not_found(handler: &str, response: Response, code: &str) {
+    assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", handler);
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("read the body");
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("a JSON error body");
+    assert_eq!(body["error"]["code"], code, "{}", handler);
+}
+
+#[tokio::test]
+#[ignore = "needs a MySQL database in TEST_DATABASE_URL"]
+async fn other_accounts_ids_are_not_found() {
+    let provider_use = ProviderUse::default();
+    let state = test_state(&provider_use);
+    let accounts = Accounts::insert(&mut state.pool.get().expect("DB connection"));
+    let b = &accounts.user_b;
+
+    let response = add_dns_record(
+        State(state.clone()),
+        signed_in(b),
+        Json(AddDnsRecord {
+            zone_id: accounts.zone_id.clone(),
+            zone_name: None,
+            record_type: "A".to_string(),
+            name: "stolen".to_string(),
+            content: "192.0.2.2".to_string(),
+            ttl: 60,
+            proxied: false,
+            drift_policy: None,
+            fallback_content: None,
+        }),
+    )
+    .await
+    .into_response();
+    assert_not_found("add_dns_record", response, "zone.not_found").await;
+
+    let response = delete_dns_record(
+        State(state.clone()),
+        signed_in(b),
+        Query(DeleteDnsRecord {
+            record_id: accounts.record_id.clone(),
+            zone_id: accounts.zone_id.clone(),
+        }),
+    )
+    .await
+    .into_response();
+    assert_not_found("delete_dns_record", response, "record.not_found").await;
+
+    let response = update_dns_record(
+        State(state.clone()),
+        signed_in(b),
+        Json(UpdateDnsRecord {
+            record_id: accounts.record_id.clone(),
+            name: None,
+            record_type: None,
+            content: Some("192.0.2.2".to_string()),
+            ttl: None,
+            proxied: None,
+            drift_policy: None,
+        }),
+    )
+    .await
+    .into_response();
+    assert_not_found("update_dns_record", response, "record.not_found").await;
+
+    let response = adopt_dns_record(
+        State(state.clone()),
+        signed_in(b),
+        Json(AdoptDnsRecord {
+            zone_id: accounts.zone_id.clone(),
+            record_id: "unmanaged".to_string(),
+            drift_policy: None,
+            fallback_content: None,
+            api_key_name: None,
+        }),
+    )
+    .await
+    .into_response();
+    assert_not_found("adopt_dns_record", response, "zone.not_found").await;
+
+    let response = list_provider_records(
+        State(state.clone()),
+        signed_in(b),
+        Path(accounts.zone_id.clone()),
+    )
+    .await
+    .into_response();
+    assert_not_found("list_provider_records", response, "zone.not_found").await;
+
+    let response = get_record_history(
+        State(state.clone()),
+        signed_in(b),
+        Path(accounts.record_id.clone()),
+        Query(HistoryParams {
+            page: None,
+            per_page: None,
+        }),
+    )
+    .await
+    .into_response();
+    assert_not_found("get_record_history", response, "record.not_found").await;
+
+    let response = add_api_key(
+        State(state.clone()),
+        signed_in(b),
+        Json(AddApiKey {
+            name: "stolen".to_string(),
+            scope: accounts.record_id.clone(),
+        }),
+    )
+    .await
+    .into_response();
+    assert_not_found("add_api_key", response, "record.not_found").await;
+
+    let response = delete_api_key(
+        State(state.clone()),
+        signed_in(b),
+        Query(DeleteApiKeyParams {
+            key_id: accounts.api_key_id.clone(),
+        }),
+    )
+    .await
+    .into_response();
+    assert_not_found("delete_api_key", response, "api_key.not_found").await;
+
+    let response = delete_access_token(
+        State(state.clone()),
+        signed_in(b),
+        Query(DeleteAccessToken {
+            token_id: accounts.token_id.clone(),
+        }),
+    )
+    .await
+    .into_response();
+    assert_not_found("delete_access_token", response, "token.not_found").await;
+
+    let response = delete_webhook(
+        State(state.clone()),
+        signed_in(b),
+        Query(DeleteWebhookParams {
+            webhook_id: accounts.webhook_id.clone(),
+        }),
+    )
+    .await
+    .into_response();
+    assert_not_found("delete_webhook", response, "webhook.not_found").await;
+
+    let response = get_webhook_deliveries(
+        State(state.clone()),
+        signed_in(b),
+        Path(accounts.webhook_id.clone()),
+    )
+    .await
+    .into_response();
+    assert_not_found("get_webhook_deliveries", response, "webhook.not_found").await;
+
+    let response = get_provider_operation(
+        State(state.clone()),
+        signed_in(b),
+        Path(accounts.operation_id.clone()),
+    )
+    .await
+    .into_response();
+    assert_not_found("get_provider_operation", response, "operation.not_found").await;
+
+    assert_eq!(provider_use.count(), 0, "the provider was used");
+
+    // A's rows are all still there
+    let conn = &mut state.pool.get().expect("DB connection");
+    let owned = (
+        owned_token(conn, &accounts.user_a, &accounts.token_id),
+        owned_zone_record(
+            conn,
+            &accounts.user_a,
+            &accounts.zone_id,
+            &accounts.record_id,
+        ),
+        owned_api_key(conn, &accounts.user_a, &accounts.api_key_id),
+        owned_webhook(conn, &accounts.user_a, &accounts.webhook_id),
+    );
+    accounts.delete(conn);
+    assert!(matches!(owned, (Ok(_), Ok(_), Ok(_), Ok(_))), "{:?}", owned);
+}